@@ -0,0 +1,48 @@
+//! Time single point lookups in small synthetic tables, e.g. to profile the
+//! interpolation kernels in isolation from the embedded data.
+//!
+//! Run with `cargo run --release --example single_point`.
+
+use std::{hint::black_box, time::Instant};
+
+use music_mesa_tables::{
+    eos_tables::{StateVar, VolumeEnergyTable},
+    index::Range,
+    opacity_tables::RTempTable,
+};
+use ndarray::{Array2, Array3};
+
+const N_QUERIES: usize = 1_000_000;
+
+/// Average time of `lookup` over `N_QUERIES` queries spread over `[0, 1)`.
+fn time_per_query(name: &str, mut lookup: impl FnMut(f64) -> f64) {
+    let start = Instant::now();
+    let mut acc = 0.0;
+    for i in 0..N_QUERIES {
+        acc += lookup(black_box(i as f64 / N_QUERIES as f64));
+    }
+    black_box(acc);
+    let elapsed = start.elapsed();
+    println!("{name}: {:?} per query", elapsed / N_QUERIES as u32);
+}
+
+fn main() {
+    let log_energy = Range::linspace(10.0, 18.0, 81).unwrap();
+    let log_volume = Range::linspace(0.0, 10.0, 101).unwrap();
+    let values = Array3::from_shape_fn((81, 101, StateVar::COUNT), |(i, j, k)| {
+        (i as f64 * 0.1).sin() + (j as f64 * 0.05).cos() + k as f64
+    });
+    let eos = VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, values).unwrap();
+    time_per_query("VolumeEnergyTable::at", |x| {
+        eos.at(12.0 + 4.0 * x, 2.0 + 6.0 * x, StateVar::LogTemperature)
+            .unwrap()
+    });
+
+    let log_temperature = Range::linspace(3.75, 8.7, 100).unwrap();
+    let log_r = Range::linspace(-8.0, 1.0, 19).unwrap();
+    let values = Array2::from_shape_fn((100, 19), |(i, j)| (i as f64 * 0.1).sin() - j as f64);
+    let opacity = RTempTable::from_parts(0.02, 0.7, log_temperature, log_r, values).unwrap();
+    time_per_query("RTempTable::at", |x| {
+        opacity.at(4.0 + 4.0 * x, -7.0 + 7.0 * x).unwrap()
+    });
+}
@@ -0,0 +1,72 @@
+//! Time state computations with and without the stencil cache, on a
+//! structured grid (few distinct coordinates) and on scattered points (all
+//! coordinates distinct).
+//!
+//! Run with `cargo run --release --example stencil_cache`.
+
+use std::{sync::Arc, time::Instant};
+
+use music_mesa_tables::{
+    coords,
+    eos_tables::{StateVar, VolumeEnergyTable},
+    index::Range,
+    state::CstCompoState,
+};
+use ndarray::{Array2, Array3};
+
+const N: usize = 512;
+
+fn time_compute(name: &str, state: &CstCompoState<ndarray::Ix2>) {
+    let start = Instant::now();
+    let values = state.compute(StateVar::LogTemperature);
+    let elapsed = start.elapsed();
+    println!(
+        "{name}: {:?} per element (sum {:.3e})",
+        elapsed / values.len() as u32,
+        values.sum()
+    );
+}
+
+fn main() {
+    let log_energy = Range::linspace(10.0, 18.0, 81).unwrap();
+    let log_volume = Range::linspace(0.0, 10.0, 101).unwrap();
+    let values = Array3::from_shape_fn((81, 101, StateVar::COUNT), |(i, j, k)| {
+        (i as f64 * 0.1).sin() + (j as f64 * 0.05).cos() + k as f64
+    });
+    let table =
+        Arc::new(VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, values).unwrap());
+
+    // both fields span the same region of the table, the structured grid is
+    // regular in log_energy and log_volume.
+    let state_at = |logv: f64, loge: f64| {
+        (
+            10_f64.powf(coords::log_density(logv, loge)),
+            10_f64.powf(loge),
+        )
+    };
+    let structured = |(i, j): (usize, usize)| {
+        let loge = 12.0 + 4.0 * (j / 8) as f64 / (N / 8) as f64;
+        let logv = 2.0 + 4.0 * (i / 8) as f64 / (N / 8) as f64;
+        state_at(logv, loge)
+    };
+    let scattered = |(i, j): (usize, usize)| {
+        let x = ((i * N + j) as f64 * 0.618_034).fract();
+        let y = ((i * N + j) as f64 * 0.754_878).fract();
+        state_at(2.0 + 4.0 * x, 12.0 + 4.0 * y)
+    };
+
+    for (regime, field) in [
+        (
+            "structured",
+            &structured as &dyn Fn((usize, usize)) -> (f64, f64),
+        ),
+        ("scattered", &scattered),
+    ] {
+        let density = Array2::from_shape_fn((N, N), |idx| field(idx).0);
+        let energy = Array2::from_shape_fn((N, N), |idx| field(idx).1);
+        let state = CstCompoState::new(table.clone(), density.view(), energy.view());
+        time_compute(&format!("{regime}, no cache"), &state);
+        let state = state.with_stencil_cache(true);
+        time_compute(&format!("{regime}, cache"), &state);
+    }
+}
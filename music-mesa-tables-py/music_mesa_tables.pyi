@@ -1,17 +1,94 @@
+from collections.abc import Sequence
 from enum import Enum, auto
+from typing import Any, overload
 from numpy.typing import NDArray
 import numpy as np
 
 
 def get_mesa_tables_version() -> str: ...
+def get_tables_fingerprint() -> int: ...
+def get_embedded_eos_bytes() -> int: ...
+def get_embedded_opacity_bytes() -> int: ...
+def preload_default_tables() -> None: ...
+
+
+def embedded_eos_grid() -> list[tuple[float, Grid]]: ...
+
+
+class OutOfBoundsError(ValueError):
+    value: float
+    axis: str | None
+    bounds: tuple[float, float]
+    nearest_point: tuple[float, float] | None
+    state: tuple[float, float] | None
+
+
+class RangeError(ValueError): ...
+
+
+class CompositionError(ValueError):
+    elements: list[tuple[int, tuple[float, float, float]]]
+    n_invalid: int
+
+
+class Grid:
+    @property
+    def first(self) -> float: ...
+    @property
+    def last(self) -> float: ...
+    @property
+    def step(self) -> float: ...
+    @property
+    def n_values(self) -> int: ...
+    def to_numpy(self) -> NDArray[np.float64]: ...
+    def argmax(self, values: NDArray[np.float64]) -> tuple[float, float] | None: ...
+    def argmin(self, values: NDArray[np.float64]) -> tuple[float, float] | None: ...
+    def __len__(self) -> int: ...
+
+
+class InterpolationMethod(Enum):
+    Nearest = auto()
+    Linear = auto()
+    Cubic = auto()
+
+
+class OobPolicy(Enum):
+    Error = auto()
+    Clamp = auto()
+    Nan = auto()
 
 
 class CstCompoEos:
     def __init__(self, metallicity: float, he_frac: float): ...
+    @property
+    def log_energy(self) -> Grid: ...
+    @property
+    def log_volume(self) -> Grid: ...
+    @property
+    def method(self) -> InterpolationMethod: ...
+    def with_method(self, method: InterpolationMethod) -> CstCompoEos: ...
+    @overload
+    def at(self, log_energy: float, log_volume: float, var: StateVar) -> float: ...
+    @overload
+    def at(
+        self,
+        log_energy: float | NDArray[np.float64],
+        log_volume: float | NDArray[np.float64],
+        var: StateVar,
+    ) -> NDArray[np.float64]: ...
 
 
 class CstMetalEos:
     def __init__(self, metallicity: float): ...
+    @property
+    def h_fracs(self) -> Grid: ...
+    def with_method(self, method: InterpolationMethod) -> CstMetalEos: ...
+    def at_he_frac(self, he_frac: float) -> CstCompoEos: ...
+
+
+class AllEos:
+    def __init__(self): ...
+    def at_metallicities(self, metallicities: Sequence[float]) -> list[CstMetalEos]: ...
 
 
 class StateVar(Enum):
@@ -28,6 +105,9 @@ class StateVar(Enum):
     Gamma1 = auto()
     Gamma = auto()
 
+    @staticmethod
+    def all() -> list[StateVar]: ...
+
 
 class CstCompoState:
     def __init__(
@@ -37,7 +117,15 @@ class CstCompoState:
         energy: NDArray[np.float64]
     ): ...
 
-    def compute(self, var: StateVar): ...
+    def compute(self, var: StateVar) -> NDArray[np.float64]: ...
+    def compute_linear(self, var: StateVar) -> NDArray[np.float64]: ...
+
+    def jacobian(self) -> tuple[
+        NDArray[np.float64],
+        NDArray[np.float64],
+        NDArray[np.float64],
+        NDArray[np.float64],
+    ]: ...
 
 
 class CstMetalState:
@@ -49,14 +137,119 @@ class CstMetalState:
         energy: NDArray[np.float64]
     ): ...
 
-    def compute(self, var: StateVar): ...
+    @staticmethod
+    def from_h_frac(
+        table: CstMetalEos,
+        h_frac: NDArray[np.float64],
+        density: NDArray[np.float64],
+        energy: NDArray[np.float64]
+    ) -> CstMetalState: ...
+
+    def compute(self, var: StateVar) -> NDArray[np.float64]: ...
+    def compute_linear(self, var: StateVar) -> NDArray[np.float64]: ...
+    def h_frac(self) -> NDArray[np.float64]: ...
+    def he_frac(self) -> NDArray[np.float64]: ...
+
+    def check_gamma_consistency(self, rel_tol: float) -> NDArray[np.bool_]: ...
+    def validate_composition(self, tol: float) -> None: ...
+
+    def jacobian(self) -> tuple[
+        NDArray[np.float64],
+        NDArray[np.float64],
+        NDArray[np.float64],
+        NDArray[np.float64],
+    ]: ...
+    def pressure_scale_height(
+        self, gravity: NDArray[np.float64]
+    ) -> NDArray[np.float64]: ...
+
+    def domain_report(self) -> dict[str, Any]: ...
+
+
+class CstVaryingState:
+    def __init__(
+        self,
+        tables: AllEos,
+        metallicity: NDArray[np.float64],
+        h_frac: NDArray[np.float64],
+        density: NDArray[np.float64],
+        energy: NDArray[np.float64]
+    ): ...
+
+    def compute(self, var: StateVar) -> NDArray[np.float64]: ...
+    def compute_linear(self, var: StateVar) -> NDArray[np.float64]: ...
+
+
+class CstCompoOpacityTable:
+    def __init__(self, metallicity: float, he_frac: float): ...
+    @property
+    def metallicity(self) -> float: ...
+    @property
+    def h_frac(self) -> float: ...
+    @property
+    def method(self) -> InterpolationMethod: ...
+    def with_method(
+        self, method: InterpolationMethod
+    ) -> CstCompoOpacityTable: ...
+    def opacity_vs_temperature(
+        self,
+        log_density: float,
+        first: float,
+        last: float,
+        n_values: int,
+        policy: OobPolicy = OobPolicy.Error,
+    ) -> NDArray[np.float64]: ...
+
+
+class CstMetalOpacityTable:
+    def __init__(self, metallicity: float): ...
+    @property
+    def metallicity(self) -> float: ...
+    @property
+    def h_fracs(self) -> Grid: ...
+    @property
+    def log_temperature(self) -> Grid: ...
+    @property
+    def log_r(self) -> Grid: ...
+    def values(self) -> NDArray[np.float64]: ...
+    def to_numpy(self) -> tuple[
+        NDArray[np.float64],
+        NDArray[np.float64],
+        NDArray[np.float64],
+        NDArray[np.float64],
+    ]: ...
 
 
 class CstCompoOpacity:
-    def __init__(self, state: CstCompoState): ...
-    def log_opacity(self) -> NDArray[np.float64]: ...
+    def __init__(self, state: CstCompoState, floor: float | None = None): ...
+    @staticmethod
+    def with_table(
+        table: CstCompoOpacityTable,
+        state: CstCompoState,
+        floor: float | None = None,
+    ) -> CstCompoOpacity: ...
+    @property
+    def table(self) -> CstCompoOpacityTable: ...
+    def log_opacity(self, parallel: bool = False) -> NDArray[np.float64]: ...
+    def log_opacity_with_temperature(
+        self,
+    ) -> tuple[NDArray[np.float64], NDArray[np.float64]]: ...
+    def log_opacity_with_derivatives(
+        self,
+    ) -> tuple[NDArray[np.float64], NDArray[np.float64], NDArray[np.float64]]: ...
+    def dlogkappa_dlogrho(self) -> NDArray[np.float64]: ...
 
 
 class CstMetalOpacity:
-    def __init__(self, state: CstMetalState): ...
-    def log_opacity(self) -> NDArray[np.float64]: ...
+    def __init__(self, state: CstMetalState, floor: float | None = None): ...
+    def log_opacity(self, parallel: bool = False) -> NDArray[np.float64]: ...
+    def dlogkappa_dx(self) -> NDArray[np.float64]: ...
+
+
+def eos_and_opacity(
+    table: CstMetalEos,
+    he_frac: NDArray[np.float64],
+    density: NDArray[np.float64],
+    energy: NDArray[np.float64],
+    floor: float | None = None,
+) -> dict[str, NDArray[np.float64]]: ...
@@ -2,9 +2,12 @@ use std::sync::Arc;
 
 use music_mesa_tables::{eos_tables, state};
 use numpy::{IxDyn, PyArrayDyn};
-use pyo3::prelude::*;
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyDict};
 
-use crate::eos_tables::{CstCompoEos, CstMetalEos};
+use crate::{
+    eos_tables::{AllEos, CstCompoEos, CstMetalEos},
+    errors::Error,
+};
 
 /// Represent a state variable that can be computed from MESA tables.
 #[pyclass(frozen)]
@@ -43,6 +46,46 @@ impl From<StateVar> for eos_tables::StateVar {
     }
 }
 
+impl From<eos_tables::StateVar> for StateVar {
+    fn from(var: eos_tables::StateVar) -> Self {
+        match var {
+            eos_tables::StateVar::LogDensity => StateVar::LogDensity,
+            eos_tables::StateVar::LogPressure => StateVar::LogPressure,
+            eos_tables::StateVar::LogPgas => StateVar::LogPgas,
+            eos_tables::StateVar::LogTemperature => StateVar::LogTemperature,
+            eos_tables::StateVar::DPresDDensEcst => StateVar::DPresDDensEcst,
+            eos_tables::StateVar::DPresDEnerDcst => StateVar::DPresDEnerDcst,
+            eos_tables::StateVar::DTempDDensEcst => StateVar::DTempDDensEcst,
+            eos_tables::StateVar::DTempDEnerDcst => StateVar::DTempDEnerDcst,
+            eos_tables::StateVar::LogEntropy => StateVar::LogEntropy,
+            eos_tables::StateVar::DTempDPresScst => StateVar::DTempDPresScst,
+            eos_tables::StateVar::Gamma1 => StateVar::Gamma1,
+            eos_tables::StateVar::Gamma => StateVar::Gamma,
+        }
+    }
+}
+
+#[pymethods]
+impl StateVar {
+    /// All the state variables, in the order of MESA tables.
+    #[staticmethod]
+    pub fn all() -> Vec<StateVar> {
+        eos_tables::StateVar::ALL.map(Into::into).to_vec()
+    }
+
+    #[getter]
+    pub fn name(&self) -> &'static str {
+        eos_tables::StateVar::from(*self).name()
+    }
+}
+
+type Jacobian<'py> = (
+    &'py PyArrayDyn<f64>,
+    &'py PyArrayDyn<f64>,
+    &'py PyArrayDyn<f64>,
+    &'py PyArrayDyn<f64>,
+);
+
 /// A state at constant metallicity and helium fraction.
 #[pyclass(frozen)]
 pub struct CstCompoState(Arc<state::CstCompoState<IxDyn>>);
@@ -59,9 +102,37 @@ impl CstCompoState {
     }
 
     /// Compute the requested [`StateVar`] for this state.
-    pub fn compute<'py>(&self, py: Python<'py>, var: StateVar) -> &'py PyArrayDyn<f64> {
-        let out = self.0.compute(var.into());
-        PyArrayDyn::from_owned_array(py, out)
+    pub fn compute<'py>(
+        &self,
+        py: Python<'py>,
+        var: StateVar,
+    ) -> Result<&'py PyArrayDyn<f64>, Error> {
+        let out = self.0.try_compute(var.into())?;
+        Ok(PyArrayDyn::from_owned_array(py, out))
+    }
+
+    /// Compute the requested [`StateVar`] in linear space, variables stored
+    /// in log (`LogDensity`, `LogPressure`, `LogPgas`, `LogTemperature` and
+    /// `LogEntropy`) are exponentiated.
+    pub fn compute_linear<'py>(
+        &self,
+        py: Python<'py>,
+        var: StateVar,
+    ) -> Result<&'py PyArrayDyn<f64>, Error> {
+        let out = self.0.try_compute_linear(var.into())?;
+        Ok(PyArrayDyn::from_owned_array(py, out))
+    }
+
+    /// Compute the log-derivatives of pressure and temperature with respect to
+    /// density and energy, in the order of the corresponding [`StateVar`]s.
+    pub fn jacobian<'py>(&self, py: Python<'py>) -> Result<Jacobian<'py>, Error> {
+        let [dp_dd, dp_de, dt_dd, dt_de] = self.0.try_jacobian()?;
+        Ok((
+            PyArrayDyn::from_owned_array(py, dp_dd),
+            PyArrayDyn::from_owned_array(py, dp_de),
+            PyArrayDyn::from_owned_array(py, dt_dd),
+            PyArrayDyn::from_owned_array(py, dt_de),
+        ))
     }
 }
 
@@ -96,10 +167,134 @@ impl CstMetalState {
         Self(state.into())
     }
 
+    /// Build a state from the hydrogen fraction rather than the helium one.
+    #[staticmethod]
+    pub fn from_h_frac(
+        table: &CstMetalEos,
+        h_frac: &PyArrayDyn<f64>,
+        density: &PyArrayDyn<f64>,
+        energy: &PyArrayDyn<f64>,
+    ) -> Self {
+        let density = density.readonly();
+        let energy = energy.readonly();
+        let h_frac = h_frac.readonly();
+        let state = state::CstMetalState::from_h_frac(
+            table.inner_table(),
+            h_frac.as_array(),
+            density.as_array(),
+            energy.as_array(),
+        );
+        Self(state.into())
+    }
+
     /// Compute the requested [`StateVar`] for this state.
-    pub fn compute<'py>(&self, py: Python<'py>, var: StateVar) -> &'py PyArrayDyn<f64> {
-        let out = self.0.compute(var.into());
-        PyArrayDyn::from_owned_array(py, out)
+    pub fn compute<'py>(
+        &self,
+        py: Python<'py>,
+        var: StateVar,
+    ) -> Result<&'py PyArrayDyn<f64>, Error> {
+        let out = self.0.try_compute(var.into())?;
+        Ok(PyArrayDyn::from_owned_array(py, out))
+    }
+
+    /// Compute the requested [`StateVar`] in linear space, variables stored
+    /// in log (`LogDensity`, `LogPressure`, `LogPgas`, `LogTemperature` and
+    /// `LogEntropy`) are exponentiated.
+    pub fn compute_linear<'py>(
+        &self,
+        py: Python<'py>,
+        var: StateVar,
+    ) -> Result<&'py PyArrayDyn<f64>, Error> {
+        let out = self.0.try_compute_linear(var.into())?;
+        Ok(PyArrayDyn::from_owned_array(py, out))
+    }
+
+    /// Hydrogen fraction of the state.
+    pub fn h_frac<'py>(&self, py: Python<'py>) -> &'py PyArrayDyn<f64> {
+        PyArrayDyn::from_owned_array(py, self.0.h_frac().to_owned())
+    }
+
+    /// Helium fraction of the state.
+    pub fn he_frac<'py>(&self, py: Python<'py>) -> &'py PyArrayDyn<f64> {
+        PyArrayDyn::from_owned_array(py, self.0.he_frac())
+    }
+
+    /// Flag the elements where `Gamma1` and `Gamma` are consistent with the
+    /// pressure and temperature derivatives, within `rel_tol`.
+    pub fn check_gamma_consistency<'py>(
+        &self,
+        py: Python<'py>,
+        rel_tol: f64,
+    ) -> Result<&'py PyArrayDyn<bool>, Error> {
+        let out = self.0.try_check_gamma_consistency(rel_tol)?;
+        Ok(PyArrayDyn::from_owned_array(py, out))
+    }
+
+    /// Check that the mass fractions of each element lie in `[0, 1]` and sum
+    /// to 1 within `tol`, raising a `CompositionError` listing the invalid
+    /// elements otherwise.
+    pub fn validate_composition(&self, tol: f64) -> Result<(), Error> {
+        Ok(self.0.validate_composition(tol)?)
+    }
+
+    /// Compute the log-derivatives of pressure and temperature with respect to
+    /// density and energy, in the order of the corresponding [`StateVar`]s.
+    pub fn jacobian<'py>(&self, py: Python<'py>) -> Result<Jacobian<'py>, Error> {
+        let [dp_dd, dp_de, dt_dd, dt_de] = self.0.try_jacobian()?;
+        Ok((
+            PyArrayDyn::from_owned_array(py, dp_dd),
+            PyArrayDyn::from_owned_array(py, dp_de),
+            PyArrayDyn::from_owned_array(py, dt_dd),
+            PyArrayDyn::from_owned_array(py, dt_de),
+        ))
+    }
+
+    /// Pressure scale height `P / (rho g)` in cm, from the norm of the
+    /// gravitational acceleration in cm/s^2 at each element of the state.
+    pub fn pressure_scale_height<'py>(
+        &self,
+        py: Python<'py>,
+        gravity: &PyArrayDyn<f64>,
+    ) -> PyResult<&'py PyArrayDyn<f64>> {
+        let gravity = gravity.readonly();
+        let gravity = gravity.as_array();
+        if gravity.shape() != self.0.log_density().shape() {
+            return Err(PyValueError::new_err(format!(
+                "gravity of shape {:?} doesn't match the state of shape {:?}",
+                gravity.shape(),
+                self.0.log_density().shape()
+            )));
+        }
+        let out = self
+            .0
+            .try_pressure_scale_height(gravity)
+            .map_err(Error::from)?;
+        Ok(PyArrayDyn::from_owned_array(py, out))
+    }
+
+    /// Coverage of the tables by the elements of the state, with the number
+    /// of elements outside of the tables overall and on each axis.
+    pub fn domain_report<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let report = self.0.domain_report();
+        let out = PyDict::new(py);
+        out.set_item("n_elements", report.n_elements)?;
+        out.set_item("n_outside", report.n_outside)?;
+        out.set_item("fraction_outside", report.fraction_outside())?;
+        for (name, axis) in [
+            ("log_energy", report.log_energy),
+            ("log_volume", report.log_volume),
+            ("h_frac", report.h_frac),
+        ] {
+            let coverage = PyDict::new(py);
+            coverage.set_item("bounds", axis.bounds)?;
+            coverage.set_item("n_below", axis.n_below)?;
+            coverage.set_item("n_above", axis.n_above)?;
+            coverage.set_item("n_nan", axis.n_nan)?;
+            coverage.set_item("min", axis.min)?;
+            coverage.set_item("max", axis.max)?;
+            out.set_item(name, coverage)?;
+        }
+        Ok(out)
     }
 }
 
@@ -108,3 +303,53 @@ impl CstMetalState {
         self.0.clone()
     }
 }
+
+/// A state with varying metallicity and hydrogen fraction.
+#[pyclass(frozen)]
+pub struct CstVaryingState(state::CstVaryingState<IxDyn>);
+
+#[pymethods]
+impl CstVaryingState {
+    #[new]
+    pub fn new(
+        tables: &AllEos,
+        metallicity: &PyArrayDyn<f64>,
+        h_frac: &PyArrayDyn<f64>,
+        density: &PyArrayDyn<f64>,
+        energy: &PyArrayDyn<f64>,
+    ) -> Self {
+        let metallicity = metallicity.readonly();
+        let h_frac = h_frac.readonly();
+        let density = density.readonly();
+        let energy = energy.readonly();
+        Self(state::CstVaryingState::new(
+            tables.inner_tables(),
+            metallicity.as_array(),
+            h_frac.as_array(),
+            density.as_array(),
+            energy.as_array(),
+        ))
+    }
+
+    /// Compute the requested [`StateVar`] for this state.
+    pub fn compute<'py>(
+        &self,
+        py: Python<'py>,
+        var: StateVar,
+    ) -> Result<&'py PyArrayDyn<f64>, Error> {
+        let out = self.0.try_compute(var.into())?;
+        Ok(PyArrayDyn::from_owned_array(py, out))
+    }
+
+    /// Compute the requested [`StateVar`] in linear space, variables stored
+    /// in log (`LogDensity`, `LogPressure`, `LogPgas`, `LogTemperature` and
+    /// `LogEntropy`) are exponentiated.
+    pub fn compute_linear<'py>(
+        &self,
+        py: Python<'py>,
+        var: StateVar,
+    ) -> Result<&'py PyArrayDyn<f64>, Error> {
+        let out = self.0.try_compute_linear(var.into())?;
+        Ok(PyArrayDyn::from_owned_array(py, out))
+    }
+}
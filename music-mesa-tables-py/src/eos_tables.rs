@@ -1,7 +1,43 @@
-use music_mesa_tables::eos_tables;
-use pyo3::prelude::*;
+use music_mesa_tables::{cache, eos_tables};
+use numpy::{
+    ndarray::{Array, ArrayD, IxDyn},
+    PyArrayDyn,
+};
+use pyo3::{exceptions::PyValueError, prelude::*};
 use std::sync::Arc;
 
+use crate::{
+    eos::StateVar,
+    errors::Error,
+    grid::{bounds_repr, Grid, InterpolationMethod},
+};
+
+/// Metallicities covered by the embedded EOS tables, with their hydrogen
+/// fractions.
+#[pyfunction]
+pub fn embedded_eos_grid() -> Vec<(f64, Grid)> {
+    eos_tables::embedded_grid()
+        .into_iter()
+        .map(|(metallicity, h_fracs)| (metallicity, h_fracs.into()))
+        .collect()
+}
+
+/// Coordinate given either as a float or as an array.
+#[derive(FromPyObject)]
+enum ScalarOrArray<'py> {
+    Scalar(f64),
+    Array(&'py PyArrayDyn<f64>),
+}
+
+impl ScalarOrArray<'_> {
+    fn to_array(&self) -> ArrayD<f64> {
+        match self {
+            Self::Scalar(value) => ArrayD::from_elem(IxDyn(&[]), *value),
+            Self::Array(array) => array.to_owned_array(),
+        }
+    }
+}
+
 /// EOS tables at constant metallicity and helium fraction.
 #[pyclass(frozen)]
 pub struct CstCompoEos(Arc<eos_tables::VolumeEnergyTable>);
@@ -9,13 +45,87 @@ pub struct CstCompoEos(Arc<eos_tables::VolumeEnergyTable>);
 #[pymethods]
 impl CstCompoEos {
     #[new]
-    fn new(metallicity: f64, he_frac: f64) -> Self {
-        let inner = eos_tables::AllTables::default()
-            .take_at_metallicity(metallicity)
-            .expect("metallicity is out of range")
-            .take_at_he_frac(he_frac)
-            .expect("helium fraction is out of range");
-        Self(inner.into())
+    fn new(metallicity: f64, he_frac: f64) -> Result<Self, Error> {
+        let inner = cache::eos_tables()
+            .at_metallicity(metallicity)?
+            .take_at_he_frac(he_frac)?;
+        Ok(Self(inner.into()))
+    }
+
+    #[getter]
+    fn log_energy(&self) -> Grid {
+        self.0.log_energy().into()
+    }
+
+    #[getter]
+    fn log_volume(&self) -> Grid {
+        self.0.log_volume().into()
+    }
+
+    #[getter]
+    fn method(&self) -> InterpolationMethod {
+        self.0.method().into()
+    }
+
+    /// Copy of the table using `method` for interpolation.
+    fn with_method(&self, method: InterpolationMethod) -> Self {
+        let table = self.0.as_ref().clone().with_method(method.into());
+        Self(table.into())
+    }
+
+    /// Interpolate `var` at `(log_energy, log_volume)`, the native
+    /// coordinates of the table.
+    ///
+    /// Coordinates are floats or arrays, a float is broadcast to the shape of
+    /// the other coordinate.  The result is a float if both coordinates are.
+    fn at(
+        &self,
+        py: Python<'_>,
+        log_energy: ScalarOrArray<'_>,
+        log_volume: ScalarOrArray<'_>,
+        var: StateVar,
+    ) -> PyResult<PyObject> {
+        let var = var.into();
+        if let (ScalarOrArray::Scalar(loge), ScalarOrArray::Scalar(logv)) =
+            (&log_energy, &log_volume)
+        {
+            let value = self.0.at(*loge, *logv, var).map_err(Error::from)?;
+            return Ok(value.into_py(py));
+        }
+        let (log_energy, log_volume) = (log_energy.to_array(), log_volume.to_array());
+        let shape = if log_energy.ndim() == 0 {
+            log_volume.raw_dim()
+        } else {
+            log_energy.raw_dim()
+        };
+        let (Some(log_energy), Some(log_volume)) = (
+            log_energy.broadcast(shape.clone()),
+            log_volume.broadcast(shape.clone()),
+        ) else {
+            return Err(PyValueError::new_err(format!(
+                "log_energy and log_volume have incompatible shapes {:?} and {:?}",
+                log_energy.shape(),
+                log_volume.shape()
+            )));
+        };
+        let values = log_energy
+            .iter()
+            .zip(log_volume.iter())
+            .map(|(&loge, &logv)| self.0.at(loge, logv, var))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Error::from)?;
+        let values = Array::from_shape_vec(shape, values).expect("shape should match");
+        Ok(PyArrayDyn::from_owned_array(py, values).into_py(py))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "CstCompoEos(Z={}, X={}, logE={}, logV={})",
+            self.0.metallicity(),
+            self.0.h_frac(),
+            bounds_repr(self.0.log_energy()),
+            bounds_repr(self.0.log_volume())
+        )
     }
 }
 
@@ -32,11 +142,38 @@ pub struct CstMetalEos(Arc<eos_tables::ConstMetalTables>);
 #[pymethods]
 impl CstMetalEos {
     #[new]
-    fn new(metallicity: f64) -> Self {
-        let inner = eos_tables::AllTables::default()
-            .take_at_metallicity(metallicity)
-            .expect("metallicity is out of range");
-        Self(inner.into())
+    fn new(metallicity: f64) -> Result<Self, Error> {
+        let inner = cache::eos_tables().at_metallicity(metallicity)?;
+        Ok(Self(inner.into()))
+    }
+
+    #[getter]
+    fn h_fracs(&self) -> Grid {
+        self.0.h_fracs().into()
+    }
+
+    /// Copy of the tables using `method` for interpolation, hydrogen
+    /// fractions are always interpolated linearly.
+    fn with_method(&self, method: InterpolationMethod) -> Self {
+        let tables = self.0.as_ref().clone().with_method(method.into());
+        Self(tables.into())
+    }
+
+    /// Table interpolated at `he_frac`, which can be shared by several
+    /// states at that composition.
+    fn at_he_frac(&self, he_frac: f64) -> Result<CstCompoEos, Error> {
+        let table = self.0.at_he_frac(he_frac)?;
+        Ok(CstCompoEos(table.into()))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "CstMetalEos(Z={}, X={}, logE={}, logV={})",
+            self.0.metallicity(),
+            bounds_repr(self.0.h_fracs()),
+            bounds_repr(self.0.log_energy()),
+            bounds_repr(self.0.log_volume())
+        )
     }
 }
 
@@ -45,3 +182,30 @@ impl CstMetalEos {
         self.0.clone()
     }
 }
+
+/// All the EOS tables, to compute states with varying composition.
+#[pyclass(frozen)]
+pub struct AllEos(Arc<eos_tables::AllTables>);
+
+#[pymethods]
+impl AllEos {
+    #[new]
+    fn new() -> Self {
+        Self(cache::eos_tables())
+    }
+
+    /// Tables at each of the given metallicities.
+    fn at_metallicities(&self, metallicities: Vec<f64>) -> Result<Vec<CstMetalEos>, Error> {
+        let tables = self.0.at_metallicities(&metallicities)?;
+        Ok(tables
+            .into_iter()
+            .map(|table| CstMetalEos(table.into()))
+            .collect())
+    }
+}
+
+impl AllEos {
+    pub(crate) fn inner_tables(&self) -> Arc<eos_tables::AllTables> {
+        self.0.clone()
+    }
+}
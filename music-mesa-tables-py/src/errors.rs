@@ -0,0 +1,86 @@
+use music_mesa_tables::{index, state};
+use pyo3::{create_exception, exceptions::PyValueError, prelude::*};
+
+create_exception!(
+    music_mesa_tables,
+    OutOfBoundsError,
+    PyValueError,
+    "A requested value lies outside of the tables."
+);
+create_exception!(
+    music_mesa_tables,
+    RangeError,
+    PyValueError,
+    "A set of values doesn't form a valid range."
+);
+create_exception!(
+    music_mesa_tables,
+    CompositionError,
+    PyValueError,
+    "The composition of a state is not physical."
+);
+
+/// Errors from the backend library that can be raised in Python.
+pub enum Error {
+    OutOfBounds(index::OutOfBoundsError),
+    Range(index::RangeError),
+    Composition(state::CompositionError),
+}
+
+impl From<index::OutOfBoundsError> for Error {
+    fn from(err: index::OutOfBoundsError) -> Self {
+        Self::OutOfBounds(err)
+    }
+}
+
+impl From<index::RangeError> for Error {
+    fn from(err: index::RangeError) -> Self {
+        Self::Range(err)
+    }
+}
+
+impl From<state::CompositionError> for Error {
+    fn from(err: state::CompositionError) -> Self {
+        Self::Composition(err)
+    }
+}
+
+fn set_oob_attributes(
+    py: Python<'_>,
+    pyerr: &PyErr,
+    err: &index::OutOfBoundsError,
+) -> PyResult<()> {
+    let exc = pyerr.value(py);
+    exc.setattr("value", err.value())?;
+    exc.setattr("axis", err.axis())?;
+    exc.setattr("bounds", err.bounds())?;
+    exc.setattr("nearest_point", err.nearest_point())?;
+    exc.setattr("state", err.state())?;
+    Ok(())
+}
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::OutOfBounds(err) => Python::with_gil(|py| {
+                let pyerr = OutOfBoundsError::new_err(err.to_string());
+                match set_oob_attributes(py, &pyerr, &err) {
+                    Ok(()) => pyerr,
+                    Err(attr_err) => attr_err,
+                }
+            }),
+            Error::Range(err) => RangeError::new_err(err.to_string()),
+            Error::Composition(err) => Python::with_gil(|py| {
+                let pyerr = CompositionError::new_err(err.to_string());
+                let exc = pyerr.value(py);
+                let attrs = exc
+                    .setattr("elements", err.elements().to_vec())
+                    .and_then(|()| exc.setattr("n_invalid", err.n_invalid()));
+                match attrs {
+                    Ok(()) => pyerr,
+                    Err(attr_err) => attr_err,
+                }
+            }),
+        }
+    }
+}
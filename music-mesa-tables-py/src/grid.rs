@@ -0,0 +1,135 @@
+use music_mesa_tables::{grid, index::Range};
+use numpy::{PyArray1, PyReadonlyArray1};
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+/// Read-only axis of a table, evenly spaced values from `first` to `last`.
+#[pyclass(frozen)]
+pub struct Grid(Range);
+
+#[pymethods]
+impl Grid {
+    #[getter]
+    pub fn first(&self) -> f64 {
+        self.0.first()
+    }
+
+    #[getter]
+    pub fn last(&self) -> f64 {
+        self.0.last()
+    }
+
+    #[getter]
+    pub fn step(&self) -> f64 {
+        self.0.step()
+    }
+
+    #[getter]
+    pub fn n_values(&self) -> usize {
+        self.0.n_values()
+    }
+
+    /// All the values of the axis.
+    pub fn to_numpy<'py>(&self, py: Python<'py>) -> &'py PyArray1<f64> {
+        PyArray1::from_iter(py, self.0)
+    }
+
+    /// Position along the axis and value of the maximum of `values`, refined
+    /// with a parabola through the discrete maximum and its neighbours.
+    pub fn argmax(&self, values: PyReadonlyArray1<f64>) -> PyResult<Option<(f64, f64)>> {
+        let values = self.sampled_values(&values)?;
+        Ok(self.0.argmax(&values))
+    }
+
+    /// Same as `argmax`, for the minimum of `values`.
+    pub fn argmin(&self, values: PyReadonlyArray1<f64>) -> PyResult<Option<(f64, f64)>> {
+        let values = self.sampled_values(&values)?;
+        Ok(self.0.argmin(&values))
+    }
+
+    pub fn __len__(&self) -> usize {
+        self.0.n_values()
+    }
+
+    pub fn __repr__(&self) -> String {
+        format!(
+            "Grid(first={}, last={}, n_values={})",
+            self.0.first(),
+            self.0.last(),
+            self.0.n_values()
+        )
+    }
+}
+
+/// `[first, last]` bounds of `range` for `__repr__`, with a fixed precision
+/// to hide floating point noise on the last value.
+pub(crate) fn bounds_repr(range: Range) -> String {
+    format!("[{:.6}, {:.6}]", range.first(), range.last())
+}
+
+impl Grid {
+    fn sampled_values(&self, values: &PyReadonlyArray1<f64>) -> PyResult<Vec<f64>> {
+        let values = values.as_array();
+        if values.len() != self.0.n_values() {
+            return Err(PyValueError::new_err(format!(
+                "expected {} values sampled on the grid, got {}",
+                self.0.n_values(),
+                values.len()
+            )));
+        }
+        Ok(values.to_vec())
+    }
+}
+
+impl From<Range> for Grid {
+    fn from(range: Range) -> Self {
+        Self(range)
+    }
+}
+
+/// Interpolation method used by lookups in a table.
+#[pyclass(frozen)]
+#[derive(Copy, Clone)]
+pub enum InterpolationMethod {
+    Nearest,
+    Linear,
+    Cubic,
+}
+
+impl From<InterpolationMethod> for grid::InterpolationMethod {
+    fn from(method: InterpolationMethod) -> Self {
+        match method {
+            InterpolationMethod::Nearest => grid::InterpolationMethod::Nearest,
+            InterpolationMethod::Linear => grid::InterpolationMethod::Linear,
+            InterpolationMethod::Cubic => grid::InterpolationMethod::Cubic,
+        }
+    }
+}
+
+impl From<grid::InterpolationMethod> for InterpolationMethod {
+    fn from(method: grid::InterpolationMethod) -> Self {
+        match method {
+            grid::InterpolationMethod::Nearest => InterpolationMethod::Nearest,
+            grid::InterpolationMethod::Linear => InterpolationMethod::Linear,
+            grid::InterpolationMethod::Cubic => InterpolationMethod::Cubic,
+        }
+    }
+}
+
+/// What lookups do when the query lies outside of a table.
+#[pyclass(frozen)]
+#[derive(Copy, Clone)]
+pub enum OobPolicy {
+    Error,
+    Clamp,
+    Nan,
+}
+
+impl From<OobPolicy> for grid::OobPolicy {
+    fn from(policy: OobPolicy) -> Self {
+        match policy {
+            OobPolicy::Error => grid::OobPolicy::Error,
+            OobPolicy::Clamp => grid::OobPolicy::Clamp,
+            OobPolicy::Nan => grid::OobPolicy::Nan,
+        }
+    }
+}
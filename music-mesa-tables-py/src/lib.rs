@@ -1,6 +1,12 @@
+// pyo3 0.19 macros trip these lints on recent toolchains.
+#![allow(non_local_definitions, unexpected_cfgs)]
+
 mod eos;
 mod eos_tables;
+mod errors;
+mod grid;
 mod opacity;
+mod opacity_tables;
 use pyo3::prelude::*;
 
 /// Get the version number of the MESA tables backend library.
@@ -9,16 +15,64 @@ fn get_mesa_tables_version() -> &'static str {
     ::music_mesa_tables::VERSION
 }
 
+/// Decode the embedded tables now rather than on first use.
+#[pyfunction]
+fn preload_default_tables(py: Python<'_>) {
+    py.allow_threads(::music_mesa_tables::cache::preload_default_tables);
+}
+
+/// Get a stable hash of the table data embedded in the backend library.
+#[pyfunction]
+fn get_tables_fingerprint() -> u64 {
+    ::music_mesa_tables::tables_fingerprint()
+}
+
+/// Get the size in bytes of the EOS table data embedded in the backend
+/// library.
+#[pyfunction]
+fn get_embedded_eos_bytes() -> usize {
+    ::music_mesa_tables::embedded_eos_bytes()
+}
+
+/// Get the size in bytes of the opacity table data embedded in the backend
+/// library.
+#[pyfunction]
+fn get_embedded_opacity_bytes() -> usize {
+    ::music_mesa_tables::embedded_opacity_bytes()
+}
+
 /// This exposes interpolation routines of MESA tables.
 #[pymodule]
-fn music_mesa_tables(_py: Python<'_>, pymod: &PyModule) -> PyResult<()> {
+fn music_mesa_tables(py: Python<'_>, pymod: &PyModule) -> PyResult<()> {
     pymod.add_class::<eos_tables::CstCompoEos>()?;
     pymod.add_class::<eos_tables::CstMetalEos>()?;
+    pymod.add_class::<eos_tables::AllEos>()?;
     pymod.add_class::<eos::CstCompoState>()?;
     pymod.add_class::<eos::CstMetalState>()?;
+    pymod.add_class::<eos::CstVaryingState>()?;
     pymod.add_class::<eos::StateVar>()?;
+    pymod.add_class::<grid::Grid>()?;
+    pymod.add_class::<grid::InterpolationMethod>()?;
+    pymod.add_class::<grid::OobPolicy>()?;
     pymod.add_class::<opacity::CstCompoOpacity>()?;
     pymod.add_class::<opacity::CstMetalOpacity>()?;
+    pymod.add_class::<opacity_tables::CstCompoOpacityTable>()?;
+    pymod.add_class::<opacity_tables::CstMetalOpacityTable>()?;
+    pymod.add(
+        "OutOfBoundsError",
+        py.get_type::<errors::OutOfBoundsError>(),
+    )?;
+    pymod.add("RangeError", py.get_type::<errors::RangeError>())?;
+    pymod.add(
+        "CompositionError",
+        py.get_type::<errors::CompositionError>(),
+    )?;
     pymod.add_function(wrap_pyfunction!(get_mesa_tables_version, pymod)?)?;
+    pymod.add_function(wrap_pyfunction!(get_tables_fingerprint, pymod)?)?;
+    pymod.add_function(wrap_pyfunction!(get_embedded_eos_bytes, pymod)?)?;
+    pymod.add_function(wrap_pyfunction!(get_embedded_opacity_bytes, pymod)?)?;
+    pymod.add_function(wrap_pyfunction!(preload_default_tables, pymod)?)?;
+    pymod.add_function(wrap_pyfunction!(eos_tables::embedded_eos_grid, pymod)?)?;
+    pymod.add_function(wrap_pyfunction!(opacity::eos_and_opacity, pymod)?)?;
     Ok(())
 }
@@ -1,8 +1,19 @@
-use music_mesa_tables::opacity;
+use music_mesa_tables::{eos_tables, opacity};
 use numpy::{IxDyn, PyArrayDyn};
-use pyo3::prelude::*;
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyDict};
 
-use crate::eos::{CstCompoState, CstMetalState};
+use crate::{
+    eos::{CstCompoState, CstMetalState},
+    eos_tables::CstMetalEos,
+    errors::Error,
+    opacity_tables::CstCompoOpacityTable,
+};
+
+type OpacityDerivatives<'py> = (
+    &'py PyArrayDyn<f64>,
+    &'py PyArrayDyn<f64>,
+    &'py PyArrayDyn<f64>,
+);
 
 /// Opacity of a state at constant metallicity and helium fraction.
 #[pyclass(frozen)]
@@ -11,15 +22,100 @@ pub struct CstCompoOpacity(opacity::CstCompoOpacity<IxDyn>);
 #[pymethods]
 impl CstCompoOpacity {
     #[new]
-    pub fn new(state: &CstCompoState) -> Self {
-        let state = opacity::CstCompoOpacity::new(state.inner_state());
-        Self(state)
+    #[pyo3(signature = (state, floor=None))]
+    pub fn new(state: &CstCompoState, floor: Option<f64>) -> Self {
+        let opacity = opacity::CstCompoOpacity::new(state.inner_state());
+        match floor {
+            Some(floor) => Self(opacity.with_floor(floor)),
+            None => Self(opacity),
+        }
+    }
+
+    /// Opacity of a state looked up in a given table, whose composition
+    /// should match the one of the state.
+    #[staticmethod]
+    #[pyo3(signature = (table, state, floor=None))]
+    pub fn with_table(
+        table: &CstCompoOpacityTable,
+        state: &CstCompoState,
+        floor: Option<f64>,
+    ) -> PyResult<Self> {
+        let table = table.inner_table();
+        let state = state.inner_state();
+        if (table.metallicity() - state.metallicity()).abs() > 1e-12
+            || (table.h_frac() - state.h_frac()).abs() > 1e-12
+        {
+            return Err(PyValueError::new_err(format!(
+                "table composition (Z={}, X={}) doesn't match the state (Z={}, X={})",
+                table.metallicity(),
+                table.h_frac(),
+                state.metallicity(),
+                state.h_frac()
+            )));
+        }
+        let opacity = opacity::CstCompoOpacity::with_table(table, state);
+        Ok(match floor {
+            Some(floor) => Self(opacity.with_floor(floor)),
+            None => Self(opacity),
+        })
+    }
+
+    /// Table used for lookups.
+    #[getter]
+    pub fn table(&self) -> CstCompoOpacityTable {
+        self.0.table().clone().into()
+    }
+
+    /// Compute the opacity for this state, optionally over several threads.
+    #[pyo3(signature = (parallel=false))]
+    pub fn log_opacity<'py>(
+        &self,
+        py: Python<'py>,
+        parallel: bool,
+    ) -> Result<&'py PyArrayDyn<f64>, Error> {
+        let out = if parallel {
+            py.allow_threads(|| self.0.try_par_log_opacity())?
+        } else {
+            self.0.try_log_opacity()?
+        };
+        Ok(PyArrayDyn::from_owned_array(py, out))
     }
 
-    /// Compute the opacity for this state.
-    pub fn log_opacity<'py>(&self, py: Python<'py>) -> &'py PyArrayDyn<f64> {
-        let out = self.0.log_opacity();
-        PyArrayDyn::from_owned_array(py, out)
+    /// Compute the opacity and the log temperature of this state, the
+    /// temperature is only computed once.
+    pub fn log_opacity_with_temperature<'py>(
+        &self,
+        py: Python<'py>,
+    ) -> Result<(&'py PyArrayDyn<f64>, &'py PyArrayDyn<f64>), Error> {
+        let (log_kappa, logt) = self.0.try_log_opacity_with_temperature()?;
+        Ok((
+            PyArrayDyn::from_owned_array(py, log_kappa),
+            PyArrayDyn::from_owned_array(py, logt),
+        ))
+    }
+
+    /// Compute the opacity together with its derivatives with respect to the
+    /// log temperature at constant density and the log density at constant
+    /// temperature, from a single temperature computation.
+    pub fn log_opacity_with_derivatives<'py>(
+        &self,
+        py: Python<'py>,
+    ) -> Result<OpacityDerivatives<'py>, Error> {
+        let (log_kappa, dlogk_dlogt, dlogk_dlogrho) = self.0.try_log_opacity_with_derivatives()?;
+        Ok((
+            PyArrayDyn::from_owned_array(py, log_kappa),
+            PyArrayDyn::from_owned_array(py, dlogk_dlogt),
+            PyArrayDyn::from_owned_array(py, dlogk_dlogrho),
+        ))
+    }
+
+    /// Derivative of the log opacity with respect to the log density at
+    /// constant temperature.
+    pub fn dlogkappa_dlogrho<'py>(&self, py: Python<'py>) -> Result<&'py PyArrayDyn<f64>, Error> {
+        Ok(PyArrayDyn::from_owned_array(
+            py,
+            self.0.try_dlogkappa_dlogrho()?,
+        ))
     }
 }
 
@@ -30,14 +126,72 @@ pub struct CstMetalOpacity(opacity::CstMetalOpacity<IxDyn>);
 #[pymethods]
 impl CstMetalOpacity {
     #[new]
-    pub fn new(state: &CstMetalState) -> Self {
-        let state = opacity::CstMetalOpacity::new(state.inner_state());
-        Self(state)
+    #[pyo3(signature = (state, floor=None))]
+    pub fn new(state: &CstMetalState, floor: Option<f64>) -> Self {
+        let opacity = opacity::CstMetalOpacity::new(state.inner_state());
+        match floor {
+            Some(floor) => Self(opacity.with_floor(floor)),
+            None => Self(opacity),
+        }
     }
 
-    /// Compute the opacity for this state.
-    pub fn log_opacity<'py>(&self, py: Python<'py>) -> &'py PyArrayDyn<f64> {
-        let out = self.0.log_opacity();
-        PyArrayDyn::from_owned_array(py, out)
+    /// Compute the opacity for this state, optionally over several threads.
+    #[pyo3(signature = (parallel=false))]
+    pub fn log_opacity<'py>(
+        &self,
+        py: Python<'py>,
+        parallel: bool,
+    ) -> Result<&'py PyArrayDyn<f64>, Error> {
+        let out = if parallel {
+            py.allow_threads(|| self.0.try_par_log_opacity())?
+        } else {
+            self.0.try_log_opacity()?
+        };
+        Ok(PyArrayDyn::from_owned_array(py, out))
     }
+
+    /// Derivative of the log opacity with respect to the hydrogen fraction.
+    pub fn dlogkappa_dx<'py>(&self, py: Python<'py>) -> Result<&'py PyArrayDyn<f64>, Error> {
+        Ok(PyArrayDyn::from_owned_array(py, self.0.try_dlogkappa_dx()?))
+    }
+}
+
+/// Compute the temperature, pressure, entropy, first adiabatic index and
+/// opacity of a state at constant metallicity in one call.
+///
+/// The temperature is interpolated once and reused for the opacity.
+#[pyfunction]
+#[pyo3(signature = (table, he_frac, density, energy, floor=None))]
+pub fn eos_and_opacity<'py>(
+    py: Python<'py>,
+    table: &CstMetalEos,
+    he_frac: &PyArrayDyn<f64>,
+    density: &PyArrayDyn<f64>,
+    energy: &PyArrayDyn<f64>,
+    floor: Option<f64>,
+) -> PyResult<&'py PyDict> {
+    let state = CstMetalState::new(table, he_frac, density, energy).inner_state();
+    let [logt, logp, logs, gamma1] = state
+        .try_compute_many([
+            eos_tables::StateVar::LogTemperature,
+            eos_tables::StateVar::LogPressure,
+            eos_tables::StateVar::LogEntropy,
+            eos_tables::StateVar::Gamma1,
+        ])
+        .map_err(Error::from)?;
+    let opacity = opacity::CstMetalOpacity::new(state);
+    let opacity = match floor {
+        Some(floor) => opacity.with_floor(floor),
+        None => opacity,
+    };
+    let log_kappa = opacity
+        .try_log_opacity_at(logt.view())
+        .map_err(Error::from)?;
+    let out = PyDict::new(py);
+    out.set_item("log_temperature", PyArrayDyn::from_owned_array(py, logt))?;
+    out.set_item("log_pressure", PyArrayDyn::from_owned_array(py, logp))?;
+    out.set_item("log_entropy", PyArrayDyn::from_owned_array(py, logs))?;
+    out.set_item("gamma1", PyArrayDyn::from_owned_array(py, gamma1))?;
+    out.set_item("log_opacity", PyArrayDyn::from_owned_array(py, log_kappa))?;
+    Ok(out)
 }
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use music_mesa_tables::{cache, index::Range, opacity_tables};
+use numpy::{PyArray1, PyArray3};
+use pyo3::prelude::*;
+
+use crate::{
+    errors::Error,
+    grid::{bounds_repr, Grid, InterpolationMethod, OobPolicy},
+};
+
+/// Opacity table at constant metallicity and helium fraction.
+#[pyclass(frozen)]
+pub struct CstCompoOpacityTable(opacity_tables::RTempTable);
+
+#[pymethods]
+impl CstCompoOpacityTable {
+    #[new]
+    fn new(metallicity: f64, he_frac: f64) -> Result<Self, Error> {
+        let inner = cache::opacity_tables()
+            .at_metallicity(metallicity)?
+            .take_at_he_frac(he_frac)?;
+        Ok(Self(inner))
+    }
+
+    #[getter]
+    fn metallicity(&self) -> f64 {
+        self.0.metallicity()
+    }
+
+    #[getter]
+    fn h_frac(&self) -> f64 {
+        self.0.h_frac()
+    }
+
+    #[getter]
+    fn method(&self) -> InterpolationMethod {
+        self.0.method().into()
+    }
+
+    /// Copy of the table using `method` for interpolation.
+    fn with_method(&self, method: InterpolationMethod) -> Self {
+        Self(self.0.clone().with_method(method.into()))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "CstCompoOpacityTable(Z={}, X={}, logT={}, logR={})",
+            self.0.metallicity(),
+            self.0.h_frac(),
+            bounds_repr(self.0.log_temperature()),
+            bounds_repr(self.0.log_r())
+        )
+    }
+
+    /// Log opacity at constant `log_density` along `n_values` log
+    /// temperatures evenly spaced from `first` to `last`.
+    #[pyo3(signature = (log_density, first, last, n_values, policy=OobPolicy::Error))]
+    fn opacity_vs_temperature<'py>(
+        &self,
+        py: Python<'py>,
+        log_density: f64,
+        first: f64,
+        last: f64,
+        n_values: usize,
+        policy: OobPolicy,
+    ) -> Result<&'py PyArray1<f64>, Error> {
+        let log_temperatures = Range::linspace(first, last, n_values)?;
+        let curve = self
+            .0
+            .opacity_vs_temperature(log_density, log_temperatures, policy.into())?;
+        Ok(PyArray1::from_owned_array(py, curve))
+    }
+}
+
+impl CstCompoOpacityTable {
+    pub(crate) fn inner_table(&self) -> opacity_tables::RTempTable {
+        self.0.clone()
+    }
+}
+
+impl From<opacity_tables::RTempTable> for CstCompoOpacityTable {
+    fn from(table: opacity_tables::RTempTable) -> Self {
+        Self(table)
+    }
+}
+
+type TableArrays<'py> = (
+    &'py PyArray3<f64>,
+    &'py PyArray1<f64>,
+    &'py PyArray1<f64>,
+    &'py PyArray1<f64>,
+);
+
+/// Opacity tables at constant metallicity.
+#[pyclass(frozen)]
+pub struct CstMetalOpacityTable(Arc<opacity_tables::ConstMetalTables>);
+
+#[pymethods]
+impl CstMetalOpacityTable {
+    #[new]
+    fn new(metallicity: f64) -> Result<Self, Error> {
+        let inner = cache::opacity_tables().at_metallicity(metallicity)?;
+        Ok(Self(inner.into()))
+    }
+
+    #[getter]
+    fn metallicity(&self) -> f64 {
+        self.0.metallicity()
+    }
+
+    #[getter]
+    fn h_fracs(&self) -> Grid {
+        self.0.h_fracs().into()
+    }
+
+    #[getter]
+    fn log_temperature(&self) -> Grid {
+        self.0.log_temperature().into()
+    }
+
+    #[getter]
+    fn log_r(&self) -> Grid {
+        self.0.log_r().into()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "CstMetalOpacityTable(Z={}, X={}, logT={}, logR={})",
+            self.0.metallicity(),
+            bounds_repr(self.0.h_fracs()),
+            bounds_repr(self.0.log_temperature()),
+            bounds_repr(self.0.log_r())
+        )
+    }
+
+    /// Log opacity, indexed by hydrogen fraction, log temperature and log R.
+    fn values<'py>(&self, py: Python<'py>) -> &'py PyArray3<f64> {
+        PyArray3::from_array(py, &self.0.values())
+    }
+
+    /// Log opacity values together with the hydrogen fraction, log
+    /// temperature and log R axes.
+    fn to_numpy<'py>(&self, py: Python<'py>) -> TableArrays<'py> {
+        (
+            self.values(py),
+            PyArray1::from_iter(py, self.0.h_fracs()),
+            PyArray1::from_iter(py, self.0.log_temperature()),
+            PyArray1::from_iter(py, self.0.log_r()),
+        )
+    }
+}
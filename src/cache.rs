@@ -0,0 +1,94 @@
+//! Process-wide cache of the embedded tables.
+//!
+//! Decoding the embedded tables takes a while, the cache decodes them on
+//! first use (or when [`preload_default_tables`] is called) and then shares
+//! them through an `Arc`. The cache is behind a lock, so all functions of this
+//! module can be called from any thread; concurrent first uses decode the
+//! tables only once.
+
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::{eos_tables, opacity_tables};
+
+static EOS: RwLock<Option<Arc<eos_tables::AllTables>>> = RwLock::new(None);
+static OPACITY: RwLock<Option<Arc<opacity_tables::AllTables>>> = RwLock::new(None);
+
+fn get_or_init<T: Default>(cache: &RwLock<Option<Arc<T>>>) -> Arc<T> {
+    if let Some(tables) = cache
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+    {
+        return tables.clone();
+    }
+    cache
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_with(Default::default)
+        .clone()
+}
+
+fn set<T>(cache: &RwLock<Option<Arc<T>>>, tables: Option<Arc<T>>) {
+    *cache.write().unwrap_or_else(PoisonError::into_inner) = tables;
+}
+
+/// Cached EOS tables, the embedded ones unless replaced.
+pub fn eos_tables() -> Arc<eos_tables::AllTables> {
+    get_or_init(&EOS)
+}
+
+/// Cached opacity tables, the embedded ones unless replaced.
+pub fn opacity_tables() -> Arc<opacity_tables::AllTables> {
+    get_or_init(&OPACITY)
+}
+
+/// Decode the embedded EOS and opacity tables now rather than on first use,
+/// e.g. at the startup of a service. This does nothing for tables that are
+/// already cached.
+pub fn preload_default_tables() {
+    eos_tables();
+    opacity_tables();
+}
+
+/// Use `tables` instead of the embedded EOS tables.
+pub fn replace_eos_tables(tables: Arc<eos_tables::AllTables>) {
+    set(&EOS, Some(tables));
+}
+
+/// Use `tables` instead of the embedded opacity tables.
+pub fn replace_opacity_tables(tables: Arc<opacity_tables::AllTables>) {
+    set(&OPACITY, Some(tables));
+}
+
+/// Drop the cached tables, the embedded ones are decoded again on next use.
+///
+/// Tables still referenced elsewhere stay alive until those references are
+/// dropped.
+pub fn reset_cache() {
+    set(&EOS, None);
+    set(&OPACITY, None);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use super::{get_or_init, set};
+
+    // a local cache rather than the global ones, which other tests share
+    #[test]
+    fn init_replace_reset() {
+        let cache: RwLock<Option<Arc<Vec<f64>>>> = RwLock::new(None);
+        let tables = get_or_init(&cache);
+        assert!(Arc::ptr_eq(&tables, &get_or_init(&cache)));
+
+        let custom = Arc::new(vec![1.0]);
+        set(&cache, Some(custom.clone()));
+        assert!(Arc::ptr_eq(&custom, &get_or_init(&cache)));
+
+        set(&cache, None);
+        let reset = get_or_init(&cache);
+        assert!(!Arc::ptr_eq(&custom, &reset));
+        assert!(reset.is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+//! Coordinates of the tables computed from physical quantities.
+//!
+//! EOS tables are indexed by `log_energy` and `log_volume`, and opacity
+//! tables by `log_temperature` and `log_r` (see [`LogRConvention`]).  The
+//! conventions are detailed in [`crate::units`].
+
+use ndarray::{Array, ArrayView, Dimension, Zip};
+
+use crate::{
+    eos_tables::{ConstMetalTables, StateVar},
+    index::OutOfBoundsError,
+    opacity::LogRConvention,
+    units::{LOG_VOLUME_ENERGY_COEF, LOG_VOLUME_OFFSET},
+    zip::try_zip_map,
+};
+
+/// The `log_volume` axis of EOS tables at the given density and energy.
+pub fn log_volume(log_density: f64, log_energy: f64) -> f64 {
+    LOG_VOLUME_OFFSET + log_density - LOG_VOLUME_ENERGY_COEF * log_energy
+}
+
+/// The log density of the state at the given `log_volume` and energy.
+pub fn log_density(log_volume: f64, log_energy: f64) -> f64 {
+    log_volume - LOG_VOLUME_OFFSET + LOG_VOLUME_ENERGY_COEF * log_energy
+}
+
+/// Coordinates of states in EOS tables.
+pub struct Coordinates<D: Dimension> {
+    pub log_density: Array<f64, D>,
+    pub log_volume: Array<f64, D>,
+    pub log_energy: Array<f64, D>,
+}
+
+impl<D: Dimension> Coordinates<D> {
+    /// Coordinates of states from their density and specific internal energy.
+    ///
+    /// # Panics
+    ///
+    /// If `density` and `energy` don't have the same shape.
+    pub fn from_density_energy(
+        density: ArrayView<'_, f64, D>,
+        energy: ArrayView<'_, f64, D>,
+    ) -> Self {
+        assert_eq!(density.shape(), energy.shape());
+        let log_density = density.mapv(f64::log10);
+        let log_energy = energy.mapv(f64::log10);
+        let log_volume = Zip::from(&log_density)
+            .and(&log_energy)
+            .map_collect(|&logd, &loge| log_volume(logd, loge));
+        Self {
+            log_density,
+            log_volume,
+            log_energy,
+        }
+    }
+
+    /// Compute the requested [`StateVar`] at the given hydrogen fraction.
+    ///
+    /// The coordinates can be reused for several compositions, e.g. in a
+    /// sweep over hydrogen fractions, without recomputing the logarithms.
+    pub fn try_compute(
+        &self,
+        table: &ConstMetalTables,
+        h_frac: ArrayView<'_, f64, D>,
+        var: StateVar,
+    ) -> Result<Array<f64, D>, OutOfBoundsError> {
+        try_zip_map(
+            [
+                h_frac.view(),
+                self.log_volume.view(),
+                self.log_energy.view(),
+            ],
+            |[h_frac, logv, loge]| table.at(h_frac, loge, logv, var),
+        )
+    }
+
+    /// The `log_r` coordinate of opacity tables, the temperature being
+    /// e.g. computed from the EOS tables.
+    pub fn log_r(
+        &self,
+        log_temperature: ArrayView<'_, f64, D>,
+        convention: LogRConvention,
+    ) -> Array<f64, D> {
+        Zip::from(&self.log_density)
+            .and(log_temperature)
+            .map_collect(|&logd, &logt| convention.log_r(logd, logt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::arr1;
+
+    use crate::{is_close::IsClose, opacity::LogRConvention};
+
+    use super::Coordinates;
+
+    #[test]
+    fn physical_to_table_coordinates() {
+        let density = arr1(&[1e-2, 10.0]);
+        let energy = arr1(&[1e14, 1e16]);
+        let coords = Coordinates::from_density_energy(density.view(), energy.view());
+        assert!(coords.log_density[0].is_close(-2.0));
+        assert!(coords.log_energy[1].is_close(16.0));
+        assert!(coords.log_volume[0].is_close(20.0 - 2.0 - 0.7 * 14.0));
+        assert!(coords.log_volume[1].is_close(20.0 + 1.0 - 0.7 * 16.0));
+        let log_r = coords.log_r(arr1(&[6.0, 7.0]).view(), LogRConvention::default());
+        assert!(log_r[0].is_close(-2.0));
+        assert!(log_r[1].is_close(-2.0));
+        for (&logd, (&logv, &loge)) in coords
+            .log_density
+            .iter()
+            .zip(coords.log_volume.iter().zip(&coords.log_energy))
+        {
+            assert!(super::log_density(logv, loge).is_close(logd));
+        }
+    }
+}
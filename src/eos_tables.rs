@@ -1,14 +1,32 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
 use ndarray::{Array3, ArrayView3, Axis};
+use thiserror::Error;
 
 use crate::{
-    index::{IdxLin, Indexable, LinearInterpolable, OutOfBoundsError, Range},
-    interp::{cubic_spline_2d, LinearInterpolator},
+    grid::{InterpolationMethod, LookupMode, OobPolicy, SplineConfig, SplineValidRanges},
+    index::{
+        CustomRange, IdxLin, Indexable, LinearInterpolable, MetalAxis, OutOfBoundsError, Range,
+        RangeError,
+    },
+    interp::{
+        check_batch, cubic_spline_2d, cubic_spline_2d_batch, lin_interp_2d, lin_interp_2d_batch,
+        LinearInterpolator, LinearStencil, SplineStencil,
+    },
     is_close::IsClose,
-    raw_tables::eos::{AllRawTables, MetalRawTables, RawTableContent, RAW_TABLES},
+    raw_tables::eos::RawTableContent,
 };
 
+#[cfg(feature = "embedded-tables")]
+use crate::raw_tables::eos::{AllRawTables, MetalRawTables, RAW_TABLES};
+
 /// State variable labels.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[repr(usize)]
 pub enum StateVar {
     LogDensity,
@@ -25,60 +43,427 @@ pub enum StateVar {
     Gamma,
 }
 
+impl StateVar {
+    /// Number of state variables stored in MESA tables.
+    pub const COUNT: usize = 12;
+
+    /// All state variables, in the order they are stored in MESA tables.
+    pub const ALL: [StateVar; StateVar::COUNT] = [
+        StateVar::LogDensity,
+        StateVar::LogPressure,
+        StateVar::LogPgas,
+        StateVar::LogTemperature,
+        StateVar::DPresDDensEcst,
+        StateVar::DPresDEnerDcst,
+        StateVar::DTempDDensEcst,
+        StateVar::DTempDEnerDcst,
+        StateVar::LogEntropy,
+        StateVar::DTempDPresScst,
+        StateVar::Gamma1,
+        StateVar::Gamma,
+    ];
+
+    /// Whether the variable is stored as a base-10 logarithm, this is the case
+    /// of `LogDensity`, `LogPressure`, `LogPgas`, `LogTemperature` and
+    /// `LogEntropy`.  The derivatives and adiabatic indices are not.
+    pub fn is_log(&self) -> bool {
+        matches!(
+            self,
+            StateVar::LogDensity
+                | StateVar::LogPressure
+                | StateVar::LogPgas
+                | StateVar::LogTemperature
+                | StateVar::LogEntropy
+        )
+    }
+
+    /// Name of the variant, e.g. to label outputs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StateVar::LogDensity => "LogDensity",
+            StateVar::LogPressure => "LogPressure",
+            StateVar::LogPgas => "LogPgas",
+            StateVar::LogTemperature => "LogTemperature",
+            StateVar::DPresDDensEcst => "DPresDDensEcst",
+            StateVar::DPresDEnerDcst => "DPresDEnerDcst",
+            StateVar::DTempDDensEcst => "DTempDDensEcst",
+            StateVar::DTempDEnerDcst => "DTempDEnerDcst",
+            StateVar::LogEntropy => "LogEntropy",
+            StateVar::DTempDPresScst => "DTempDPresScst",
+            StateVar::Gamma1 => "Gamma1",
+            StateVar::Gamma => "Gamma",
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum StateVarError {
+    #[error("no state variable at index {0}")]
+    Index(usize),
+    #[error("unknown state variable {0:?}")]
+    Name(String),
+}
+
+impl TryFrom<usize> for StateVar {
+    type Error = StateVarError;
+
+    /// State variable at index `value` of the variable axis of tables, this
+    /// is the reverse of `var as usize`.
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        StateVar::ALL
+            .get(value)
+            .copied()
+            .ok_or(StateVarError::Index(value))
+    }
+}
+
+impl FromStr for StateVar {
+    type Err = StateVarError;
+
+    /// Parse a state variable from its [`StateVar::name`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        StateVar::ALL
+            .into_iter()
+            .find(|var| var.name() == s)
+            .ok_or_else(|| StateVarError::Name(s.to_owned()))
+    }
+}
+
+impl fmt::Display for StateVar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TableError {
+    #[error("values of shape {found:?} don't match the expected shape {expected:?}")]
+    ShapeMismatch {
+        expected: [usize; 3],
+        found: [usize; 3],
+    },
+    #[error("tables have different {0}")]
+    Incompatible(&'static str),
+    #[error("hydrogen fractions at metallicities {left} and {right} don't overlap")]
+    DisjointHFracs { left: f64, right: f64 },
+    #[error("no tables to assemble")]
+    NoTables,
+    #[error("this requires cubic interpolation, the table uses {0:?}")]
+    NotCubic(InterpolationMethod),
+    #[error(transparent)]
+    OutOfBounds(#[from] OutOfBoundsError),
+    #[error(transparent)]
+    Range(#[from] RangeError),
+}
+
+/// Composition coordinates of tables.
+enum Composition {
+    Metallicity,
+    HFrac,
+}
+
+impl From<TableError> for io::Error {
+    fn from(err: TableError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Metallicities covered by the embedded tables, with their hydrogen
+/// fractions.
+///
+/// This doesn't decode the tables, and is therefore much cheaper than
+/// `AllTables::default()` to inspect the coverage of the embedded data.
+#[cfg(feature = "embedded-tables")]
+pub fn embedded_grid() -> Vec<(f64, Range)> {
+    RAW_TABLES
+        .tables
+        .iter()
+        .map(|t| (t.metallicity, t.h_fracs))
+        .collect()
+}
+
+/// Grid nodes bracketing a query along one composition axis.
+#[derive(Clone, Debug)]
+pub struct AxisTrace {
+    /// Indices of the left and right nodes, equal if the query is on a node.
+    pub indices: (usize, usize),
+    /// Values at the left and right nodes.
+    pub nodes: (f64, f64),
+    /// Weight of the left node, the right node has weight `1 - left_weight`.
+    pub left_weight: f64,
+}
+
+impl AxisTrace {
+    fn new(axis: &impl Indexable, idx: IdxLin, value: f64) -> Self {
+        let (i, j) = idx.bracket();
+        let (left, right) = (axis.at(i), axis.at(j));
+        let left_weight = if i == j {
+            1.0
+        } else {
+            (right - value) / (right - left)
+        };
+        Self {
+            indices: (i, j),
+            nodes: (left, right),
+            left_weight,
+        }
+    }
+
+    fn fmt_as(&self, name: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // nodes of linear ranges are computed and might be off by one ulp,
+        // hence the fixed precision
+        let (left, right) = self.nodes;
+        if self.indices.0 == self.indices.1 {
+            write!(f, "{name}={left:.6}")
+        } else {
+            write!(
+                f,
+                "{name}={left:.6}/{right:.6} (left weight {:.3})",
+                self.left_weight
+            )
+        }
+    }
+}
+
+/// Tables that contributed to an interpolation, see [`AllTables::at_traced`].
+#[derive(Clone, Debug)]
+pub struct InterpTrace {
+    pub metallicity: AxisTrace,
+    /// Hydrogen fraction nodes, for each metallicity node in order.
+    pub h_frac: Vec<AxisTrace>,
+}
+
+impl fmt::Display for InterpTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("interpolated between ")?;
+        self.metallicity.fmt_as("Z", f)?;
+        for h_frac in &self.h_frac {
+            f.write_str(" and ")?;
+            h_frac.fmt_as("X", f)?;
+        }
+        Ok(())
+    }
+}
+
 /// The collection of all MESA tables available
 pub struct AllTables {
-    metallicities: Range,
+    metallicities: MetalAxis<CustomRange>,
     tables: Vec<ConstMetalTables>,
 }
 
 impl AllTables {
+    /// Assemble tables at metallicities in increasing order, which need not
+    /// be evenly spaced.
+    ///
+    /// The grid is checked with [`AllTables::validate_grid`].
+    pub fn from_metal_tables(tables: Vec<ConstMetalTables>) -> Result<Self, TableError> {
+        let metallicities = match tables.as_slice() {
+            [] => return Err(TableError::NoTables),
+            [single] => MetalAxis::Single(single.metallicity),
+            tables => {
+                let metallicities: Vec<_> = tables.iter().map(|t| t.metallicity).collect();
+                MetalAxis::Grid(CustomRange::new(metallicities)?)
+            }
+        };
+        let all = Self {
+            metallicities,
+            tables,
+        };
+        all.validate_grid()?;
+        Ok(all)
+    }
+
+    /// Check that tables at adjacent metallicities share their axes and that
+    /// their hydrogen fractions overlap, which is necessary to interpolate
+    /// between them.
+    pub fn validate_grid(&self) -> Result<(), TableError> {
+        for pair in self.tables.windows(2) {
+            let [left, right] = pair else { unreachable!() };
+            if left.h_fracs.subrange_in(right.h_fracs).is_none() {
+                return Err(TableError::DisjointHFracs {
+                    left: left.metallicity,
+                    right: right.metallicity,
+                });
+            }
+            if let (Some(l_table), Some(r_table)) = (left.tables.first(), right.tables.first()) {
+                l_table.check_compatible(r_table, &[])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn at(
+        &self,
+        metallicity: f64,
+        h_frac: f64,
+        log_energy: f64,
+        log_volume: f64,
+        var: StateVar,
+    ) -> Result<f64, OutOfBoundsError> {
+        let idx = self
+            .metallicities
+            .idx_lin(metallicity)
+            .map_err(|e| e.on("metallicity"))?;
+        self.at_metallicity_idx(idx, metallicity, h_frac, log_energy, log_volume, var)
+    }
+
+    /// Same as [`AllTables::at`], with the metallicity already located on
+    /// its axis.
+    fn at_metallicity_idx(
+        &self,
+        idx: IdxLin,
+        metallicity: f64,
+        h_frac: f64,
+        log_energy: f64,
+        log_volume: f64,
+        var: StateVar,
+    ) -> Result<f64, OutOfBoundsError> {
+        match idx {
+            IdxLin::Exact(i) => self.tables[i].at(h_frac, log_energy, log_volume, var),
+            IdxLin::Between(i, j) => {
+                let lin = LinearInterpolator::new(
+                    self.metallicities.at(i),
+                    self.metallicities.at(j),
+                    metallicity,
+                );
+                let left = self.tables[i].at(h_frac, log_energy, log_volume, var)?;
+                let right = self.tables[j].at(h_frac, log_energy, log_volume, var)?;
+                Ok(lin.interp_scalar(left, right))
+            }
+        }
+    }
+
+    /// Same as [`AllTables::at`], also reporting which tables contributed to
+    /// the result and with which weights.
+    pub fn at_traced(
+        &self,
+        metallicity: f64,
+        h_frac: f64,
+        log_energy: f64,
+        log_volume: f64,
+        var: StateVar,
+    ) -> Result<(f64, InterpTrace), OutOfBoundsError> {
+        let idx = self
+            .metallicities
+            .idx_lin(metallicity)
+            .map_err(|e| e.on("metallicity"))?;
+        let metal_trace = AxisTrace::new(&self.metallicities, idx, metallicity);
+        let (ileft, iright) = metal_trace.indices;
+        let nodes = if ileft == iright {
+            vec![ileft]
+        } else {
+            vec![ileft, iright]
+        };
+        let h_frac_traces = nodes
+            .iter()
+            .map(|&i| self.tables[i].h_frac_trace(h_frac))
+            .collect::<Result<_, _>>()?;
+        let value =
+            self.at_metallicity_idx(idx, metallicity, h_frac, log_energy, log_volume, var)?;
+        let trace = InterpTrace {
+            metallicity: metal_trace,
+            h_frac: h_frac_traces,
+        };
+        Ok((value, trace))
+    }
+
+    /// Borrow the table at `(metallicity, h_frac)` if both are grid nodes.
+    ///
+    /// This returns `None` when interpolation is needed (or the composition is
+    /// out of the tables), in which case `take_at_metallicity` and
+    /// `take_at_h_frac` should be used instead.
+    pub fn exact_table(&self, metallicity: f64, h_frac: f64) -> Option<&VolumeEnergyTable> {
+        let IdxLin::Exact(i) = self.metallicities.idx_lin(metallicity).ok()? else {
+            return None;
+        };
+        let metal_tables = &self.tables[i];
+        match metal_tables.h_fracs.idx_lin(h_frac).ok()? {
+            IdxLin::Exact(j) => Some(&metal_tables.tables[j]),
+            IdxLin::Between(..) => None,
+        }
+    }
+
     pub fn take_at_metallicity(
         mut self,
         metallicity: f64,
     ) -> Result<ConstMetalTables, OutOfBoundsError> {
-        match self.metallicities.idx_lin(metallicity)? {
+        match self
+            .metallicities
+            .idx_lin(metallicity)
+            .map_err(|e| e.on("metallicity"))?
+        {
             IdxLin::Exact(i) => Ok(self.tables.swap_remove(i)),
             IdxLin::Between(i, j) => {
-                let r_tables = self.tables.swap_remove(j);
-                let l_tables = self.tables.swap_remove(i);
-                let h_fracs = l_tables
-                    .h_fracs
-                    .subrange_in(r_tables.h_fracs)
-                    .expect("Hydrogen fractions should overlap");
-                let tables: Vec<_> = h_fracs
-                    .into_iter()
-                    .map(move |h_frac| {
-                        // this is not in-place!
-                        let left = l_tables.at_h_frac(h_frac)?;
-                        let right = r_tables.at_h_frac(h_frac)?;
-                        Ok(left.interp_at_metal(&right, metallicity))
-                    })
-                    .collect::<Result<_, _>>()?;
-                Ok(ConstMetalTables {
-                    metallicity,
-                    h_fracs,
-                    tables,
-                })
+                ConstMetalTables::between(&self.tables[i], &self.tables[j], metallicity)
+            }
+        }
+    }
+
+    /// Same as [`AllTables::take_at_metallicity`], without consuming the
+    /// tables. This copies the values.
+    pub fn at_metallicity(&self, metallicity: f64) -> Result<ConstMetalTables, OutOfBoundsError> {
+        match self
+            .metallicities
+            .idx_lin(metallicity)
+            .map_err(|e| e.on("metallicity"))?
+        {
+            IdxLin::Exact(i) => Ok(self.tables[i].clone()),
+            IdxLin::Between(i, j) => {
+                ConstMetalTables::between(&self.tables[i], &self.tables[j], metallicity)
             }
         }
     }
+
+    /// Tables at each of `metallicities`, see [`AllTables::at_metallicity`].
+    ///
+    /// This is meant for metallicity sweeps, the tables are only loaded
+    /// once.  Fails on the first metallicity out of the grid.
+    pub fn at_metallicities(
+        &self,
+        metallicities: &[f64],
+    ) -> Result<Vec<ConstMetalTables>, OutOfBoundsError> {
+        metallicities
+            .iter()
+            .map(|&metallicity| self.at_metallicity(metallicity))
+            .collect()
+    }
+
+    /// Iterate over every table of the grid, by increasing metallicity then
+    /// hydrogen fraction.
+    ///
+    /// The composition of each table is given by
+    /// [`VolumeEnergyTable::metallicity`] and [`VolumeEnergyTable::h_frac`].
+    pub fn iter_tables(&self) -> impl Iterator<Item = &VolumeEnergyTable> {
+        self.tables
+            .iter()
+            .flat_map(|metal_tables| metal_tables.tables.iter())
+    }
 }
 
+#[cfg(feature = "embedded-tables")]
 impl From<&AllRawTables> for AllTables {
     fn from(rawtbls: &AllRawTables) -> Self {
-        let metallicities = rawtbls.metallicities;
+        let metallicities = match rawtbls.tables {
+            [single] => MetalAxis::Single(single.metallicity),
+            tables => {
+                let metallicities: Vec<_> = tables.iter().map(|t| t.metallicity).collect();
+                let axis = CustomRange::new(metallicities)
+                    .expect("metallicities of raw tables should be increasing");
+                MetalAxis::Grid(axis)
+            }
+        };
         Self {
             metallicities,
             tables: rawtbls
                 .tables
                 .iter()
-                .zip(metallicities)
-                .map(|(t, m)| ConstMetalTables::from_raw(m, t))
+                .map(|t| ConstMetalTables::from_raw(t.metallicity, t))
                 .collect(),
         }
     }
 }
 
+#[cfg(feature = "embedded-tables")]
 impl Default for AllTables {
     fn default() -> Self {
         (&RAW_TABLES).into()
@@ -86,6 +471,13 @@ impl Default for AllTables {
 }
 
 /// The collection of MESA tables at a given metallicity
+///
+/// When obtained by interpolation in metallicity (see
+/// [`AllTables::take_at_metallicity`]), the tables at each hydrogen fraction
+/// are interpolated once and stored.  This costs one table per hydrogen
+/// fraction in memory, but lookups then only interpolate the nodes they need
+/// and don't allocate.
+#[derive(Clone)]
 pub struct ConstMetalTables {
     metallicity: f64,
     h_fracs: Range,
@@ -93,6 +485,59 @@ pub struct ConstMetalTables {
 }
 
 impl ConstMetalTables {
+    /// Tables at `metallicity`, interpolated between `left` and `right` on
+    /// the hydrogen fractions they share.
+    fn between(left: &Self, right: &Self, metallicity: f64) -> Result<Self, OutOfBoundsError> {
+        let h_fracs = left
+            .h_fracs
+            .subrange_in(right.h_fracs)
+            .expect("hydrogen fractions overlap on a validated grid")
+            .range;
+        let tables: Vec<_> = h_fracs
+            .into_iter()
+            .map(|h_frac| {
+                let l_table = left.at_h_frac(h_frac)?;
+                let r_table = right.at_h_frac(h_frac)?;
+                Ok(l_table
+                    .interp_at_metal(&r_table, metallicity)
+                    .expect("tables should be compatible"))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            metallicity,
+            h_fracs,
+            tables,
+        })
+    }
+
+    /// Assemble tables at `metallicity`, one per value of `h_fracs`.
+    ///
+    /// All tables should share their axes and be at the given composition.
+    pub fn from_tables(
+        metallicity: f64,
+        h_fracs: Range,
+        tables: Vec<VolumeEnergyTable>,
+    ) -> Result<Self, TableError> {
+        if tables.len() != h_fracs.n_values() {
+            return Err(TableError::Incompatible("h_fracs"));
+        }
+        for (table, h_frac) in tables.iter().zip(h_fracs) {
+            tables[0].check_compatible(table, &[])?;
+            if !table.metallicity.is_close(metallicity) {
+                return Err(TableError::Incompatible("metallicities"));
+            }
+            if !table.h_frac.is_close(h_frac) {
+                return Err(TableError::Incompatible("h_fracs"));
+            }
+        }
+        Ok(Self {
+            metallicity,
+            h_fracs,
+            tables,
+        })
+    }
+
+    #[cfg(feature = "embedded-tables")]
     fn from_raw(metallicity: f64, raw: &MetalRawTables) -> Self {
         let h_fracs = raw.h_fracs;
         Self {
@@ -108,13 +553,14 @@ impl ConstMetalTables {
     }
 
     pub fn take_at_h_frac(mut self, h_frac: f64) -> Result<VolumeEnergyTable, OutOfBoundsError> {
-        match self.h_fracs.idx_lin(h_frac)? {
+        match self.h_fracs.idx_lin(h_frac).map_err(|e| e.on("h_frac"))? {
             IdxLin::Exact(i) => Ok(self.tables.swap_remove(i)),
             IdxLin::Between(i, j) => {
                 let right = self.tables.swap_remove(j);
                 let left = self.tables.swap_remove(i);
-                // not in-place!
-                Ok(left.interp_at_h_frac(&right, h_frac))
+                Ok(left
+                    .interp_at_h_frac(&right, h_frac)
+                    .expect("tables should be compatible"))
             }
         }
     }
@@ -125,20 +571,102 @@ impl ConstMetalTables {
     }
 
     pub fn at_h_frac(&self, h_frac: f64) -> Result<VolumeEnergyTable, OutOfBoundsError> {
-        match self.h_fracs.idx_lin(h_frac)? {
+        match self.h_fracs.idx_lin(h_frac).map_err(|e| e.on("h_frac"))? {
             IdxLin::Exact(i) => Ok(self.tables[i].clone()),
             IdxLin::Between(i, j) => {
-                let left = &self.tables[i];
+                let left = self.tables[i].clone();
                 let right = &self.tables[j];
-                Ok(left.interp_at_h_frac(right, h_frac))
+                Ok(left
+                    .interp_at_h_frac(right, h_frac)
+                    .expect("tables should be compatible"))
             }
         }
     }
 
+    /// Same as [`ConstMetalTables::take_at_he_frac`], without consuming the
+    /// tables.
+    pub fn at_he_frac(&self, he_frac: f64) -> Result<VolumeEnergyTable, OutOfBoundsError> {
+        self.at_h_frac(1.0 - he_frac - self.metallicity())
+    }
+
+    /// Tables on the `new_h_fracs` grid, interpolated linearly in hydrogen
+    /// fraction from the current ones.
+    ///
+    /// Fails if `new_h_fracs` doesn't lie within the current hydrogen
+    /// fractions.
+    pub fn resample_h_fracs(&self, new_h_fracs: &Range) -> Result<Self, OutOfBoundsError> {
+        let tables = new_h_fracs
+            .into_iter()
+            .map(|h_frac| self.at_h_frac(h_frac))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            metallicity: self.metallicity,
+            h_fracs: *new_h_fracs,
+            tables,
+        })
+    }
+
     pub fn metallicity(&self) -> f64 {
         self.metallicity
     }
 
+    pub fn h_fracs(&self) -> Range {
+        self.h_fracs
+    }
+
+    /// Log internal energy axis, shared by all the tables.
+    pub fn log_energy(&self) -> Range {
+        self.tables[0].log_energy()
+    }
+
+    /// Log volume axis, shared by all the tables.
+    pub fn log_volume(&self) -> Range {
+        self.tables[0].log_volume()
+    }
+
+    /// Same as [`VolumeEnergyTable::spline_valid_ranges`], shared by all the
+    /// tables.
+    pub fn spline_valid_ranges(&self) -> SplineValidRanges {
+        self.tables[0].spline_valid_ranges()
+    }
+
+    /// Interpolation method of the tables.
+    pub fn method(&self) -> InterpolationMethod {
+        self.tables[0].method()
+    }
+
+    /// Same as [`VolumeEnergyTable::method_domain`], shared by all the
+    /// tables.
+    pub(crate) fn method_domain(&self) -> (Range, Range) {
+        self.tables[0].method_domain()
+    }
+
+    /// Use `spline` for interpolation in all the tables.
+    pub fn with_spline_config(mut self, spline: SplineConfig) -> Self {
+        self.tables = self
+            .tables
+            .into_iter()
+            .map(|table| table.with_spline_config(spline))
+            .collect();
+        self
+    }
+
+    /// Use `method` for interpolation in all the tables, hydrogen fractions
+    /// are always interpolated linearly.
+    pub fn with_method(mut self, method: InterpolationMethod) -> Self {
+        self.tables = self
+            .tables
+            .into_iter()
+            .map(|table| table.with_method(method))
+            .collect();
+        self
+    }
+
+    fn h_frac_trace(&self, h_frac: f64) -> Result<AxisTrace, OutOfBoundsError> {
+        let idx = self.h_fracs.idx_lin(h_frac).map_err(|e| e.on("h_frac"))?;
+        Ok(AxisTrace::new(&self.h_fracs, idx, h_frac))
+    }
+
     pub fn at(
         &self,
         h_frac: f64,
@@ -146,29 +674,185 @@ impl ConstMetalTables {
         log_volume: f64,
         var: StateVar,
     ) -> Result<f64, OutOfBoundsError> {
-        match self.h_fracs.idx_lin(h_frac)? {
-            IdxLin::Exact(i) => self.tables[i].at(log_energy, log_volume, var),
-            IdxLin::Between(i, j) => {
+        let [value] = self.at_many(h_frac, log_energy, log_volume, [var])?;
+        Ok(value)
+    }
+
+    /// Same as [`ConstMetalTables::at`], queries outside of the tables are
+    /// handled according to `policy`. Clamped queries are moved to the
+    /// closest hydrogen fraction, and then to the nearest valid point of the
+    /// tables (see [`VolumeEnergyTable::nearest_valid`]).
+    pub fn at_with_policy(
+        &self,
+        h_frac: f64,
+        log_energy: f64,
+        log_volume: f64,
+        var: StateVar,
+        policy: OobPolicy,
+    ) -> Result<f64, OutOfBoundsError> {
+        self.tables[0].check_vars(&[var])?;
+        policy.apply(
+            (h_frac, log_energy, log_volume),
+            |(h_frac, loge, logv)| {
+                let (loge, logv) = self.tables[0].nearest_valid(loge, logv);
+                (self.h_fracs.clamp(h_frac), loge, logv)
+            },
+            |(h_frac, loge, logv)| self.at(h_frac, loge, logv, var),
+        )
+    }
+
+    /// Compute several state variables at once, sharing the interpolation
+    /// stencils between them.
+    pub fn at_many<const N: usize>(
+        &self,
+        h_frac: f64,
+        log_energy: f64,
+        log_volume: f64,
+        vars: [StateVar; N],
+    ) -> Result<[f64; N], OutOfBoundsError> {
+        match self.h_fracs.idx_lin(h_frac).map_err(|e| e.on("h_frac"))? {
+            IdxLin::Exact(i) => self.tables[i].at_many(log_energy, log_volume, vars),
+            IdxLin::Between(i, j) if self.tables[i].method != InterpolationMethod::Cubic => {
                 let lin = LinearInterpolator::new(self.h_fracs.at(i), self.h_fracs.at(j), h_frac);
-                let loge_stencil = self.tables[i].log_energy().spline_stencil(log_energy)?;
-                let logv_stencil = self.tables[i].log_volume().spline_stencil(log_volume)?;
-
-                let mut ltable = self.tables[i].values();
-                let mut rtable = self.tables[j].values();
-
-                // take only the elements of the tables that are needed for the
-                // spline interpolation.
-                loge_stencil.slice_view(Axis(0), &mut ltable);
-                let loge_stencil = loge_stencil.slice_view(Axis(0), &mut rtable);
-                logv_stencil.slice_view(Axis(1), &mut ltable);
-                let logv_stencil = logv_stencil.slice_view(Axis(1), &mut rtable);
-                let table = lin.interp(
-                    ltable.index_axis(Axis(2), var as usize),
-                    rtable.index_axis(Axis(2), var as usize),
-                );
-                Ok(cubic_spline_2d(loge_stencil, logv_stencil, table.view()))
+                let left = self.tables[i].at_many(log_energy, log_volume, vars)?;
+                let right = self.tables[j].at_many(log_energy, log_volume, vars)?;
+                Ok(std::array::from_fn(|k| {
+                    lin.interp_scalar(left[k], right[k])
+                }))
+            }
+            IdxLin::Between(i, j) => {
+                self.tables[i].check_vars(&vars)?;
+                let stencils = self.tables[i].stencils(log_energy, log_volume)?;
+                Ok(self.spline_between((i, j), h_frac, stencils, vars))
+            }
+        }
+    }
+
+    /// Same as [`ConstMetalTables::at_many`], reusing the spline stencils
+    /// stored in `cache`.
+    pub(crate) fn at_many_cached<const N: usize>(
+        &self,
+        h_frac: f64,
+        log_energy: f64,
+        log_volume: f64,
+        vars: [StateVar; N],
+        cache: &mut StencilCache,
+    ) -> Result<[f64; N], OutOfBoundsError> {
+        match self.h_fracs.idx_lin(h_frac).map_err(|e| e.on("h_frac"))? {
+            IdxLin::Exact(i) => self.tables[i].at_many_cached(log_energy, log_volume, vars, cache),
+            IdxLin::Between(i, _) if self.tables[i].method != InterpolationMethod::Cubic => {
+                self.at_many(h_frac, log_energy, log_volume, vars)
+            }
+            IdxLin::Between(i, j) => {
+                self.tables[i].check_vars(&vars)?;
+                let stencils = cache.stencils(&self.tables[i], log_energy, log_volume)?;
+                Ok(self.spline_between((i, j), h_frac, stencils, vars))
+            }
+        }
+    }
+
+    /// Spline interpolation between the tables of indices `i` and `j`.
+    fn spline_between<const N: usize>(
+        &self,
+        (i, j): (usize, usize),
+        h_frac: f64,
+        (loge_stencil, logv_stencil): (SplineStencil, SplineStencil),
+        vars: [StateVar; N],
+    ) -> [f64; N] {
+        let lin = LinearInterpolator::new(self.h_fracs.at(i), self.h_fracs.at(j), h_frac);
+        let ltable = self.tables[i].values();
+        let rtable = self.tables[j].values();
+
+        // the spline is linear in the values, interpolating both tables with
+        // the same stencils avoids building a temporary table in hydrogen
+        // fraction.
+        vars.map(|var| {
+            let spline = |table: ArrayView3<'_, f64>| {
+                cubic_spline_2d(
+                    loge_stencil,
+                    logv_stencil,
+                    table.index_axis_move(Axis(2), var as usize),
+                )
+            };
+            lin.interp_scalar(spline(ltable), spline(rtable))
+        })
+    }
+}
+
+/// Spline stencils already built along each axis of EOS tables, keyed by the
+/// coordinate quantized to [`StencilCache::QUANTUM`].
+///
+/// Only the nodes of a cached stencil are reused, its position is that of
+/// the actual query, so cached lookups are as accurate as uncached ones.
+/// A cached stencil is only reused for queries within its middle cell,
+/// others are looked up in the table as without the cache, which reports
+/// queries out of the table the same way.
+///
+/// This only pays off when coordinates are repeated, e.g. on structured
+/// grids, it is therefore opt-in (see
+/// [`CstCompoState::with_stencil_cache`](crate::state::CstCompoState::with_stencil_cache)).
+/// The cache should only be used with tables sharing the same axes and
+/// spline settings.
+#[derive(Default)]
+pub(crate) struct StencilCache {
+    log_energy: HashMap<i64, SplineStencil>,
+    log_volume: HashMap<i64, SplineStencil>,
+}
+
+impl StencilCache {
+    /// Number of stencils kept along each axis, the cache of an axis is
+    /// emptied when it gets full.
+    const CAPACITY: usize = 4096;
+
+    /// Coordinates closer than this (in log) share their stencil nodes.
+    const QUANTUM: f64 = 1e-10;
+
+    /// Key of `value` in the cache, non-finite values are never cached.
+    fn key(value: f64) -> Option<i64> {
+        value
+            .is_finite()
+            .then(|| (value / Self::QUANTUM).round() as i64)
+    }
+
+    /// Cached stencil at `value`, if `value` lies in its middle cell with the
+    /// tolerance of [`Range::spline_stencil`].
+    fn reuse(stencil: Option<&SplineStencil>, value: f64) -> Option<SplineStencil> {
+        let stencil = stencil?;
+        let (left, right) = (stencil.xs[1], stencil.xs[2]);
+        let in_cell =
+            (left..=right).contains(&value) || value.is_close(left) || value.is_close(right);
+        in_cell.then_some(SplineStencil {
+            at: value,
+            ..*stencil
+        })
+    }
+
+    fn stencils(
+        &mut self,
+        table: &VolumeEnergyTable,
+        log_energy: f64,
+        log_volume: f64,
+    ) -> Result<(SplineStencil, SplineStencil), OutOfBoundsError> {
+        let (Some(e_key), Some(v_key)) = (Self::key(log_energy), Self::key(log_volume)) else {
+            return table.stencils(log_energy, log_volume);
+        };
+        if let (Some(loge_st), Some(logv_st)) = (
+            Self::reuse(self.log_energy.get(&e_key), log_energy),
+            Self::reuse(self.log_volume.get(&v_key), log_volume),
+        ) {
+            return Ok((loge_st, logv_st));
+        }
+        let (loge_st, logv_st) = table.stencils(log_energy, log_volume)?;
+        for (cache, key, stencil) in [
+            (&mut self.log_energy, e_key, loge_st),
+            (&mut self.log_volume, v_key, logv_st),
+        ] {
+            if cache.len() >= Self::CAPACITY {
+                cache.clear();
             }
+            cache.insert(key, stencil);
         }
+        Ok((loge_st, logv_st))
     }
 }
 
@@ -185,6 +869,12 @@ pub struct VolumeEnergyTable {
     log_energy: Range,
     /// Table indexed by energy, volume, and state variables
     values: Array3<f64>,
+    /// Settings of the spline interpolation
+    spline: SplineConfig,
+    /// Interpolation method used by lookups
+    method: InterpolationMethod,
+    /// Opaque content following the last record of the table file
+    metadata: Vec<u8>,
 }
 
 impl VolumeEnergyTable {
@@ -200,7 +890,62 @@ impl VolumeEnergyTable {
             log_volume,
             log_energy,
             values,
+            spline: SplineConfig::default(),
+            method: InterpolationMethod::Cubic,
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Build a table from its components. `values` should be indexed by
+    /// energy, volume, and state variables (in the order of [`StateVar`]).
+    ///
+    /// Tables may hold fewer state variables than [`StateVar::COUNT`], in
+    /// which case the last ones are missing (see
+    /// [`VolumeEnergyTable::available_vars`]); extra columns are ignored.
+    pub fn from_parts(
+        metallicity: f64,
+        h_frac: f64,
+        log_energy: Range,
+        log_volume: Range,
+        values: Array3<f64>,
+    ) -> Result<Self, TableError> {
+        let (n_e, n_v, n_vars) = values.dim();
+        let expected = [log_energy.n_values(), log_volume.n_values(), n_vars.max(1)];
+        if expected != [n_e, n_v, n_vars] {
+            return Err(TableError::ShapeMismatch {
+                expected,
+                found: [n_e, n_v, n_vars],
+            });
         }
+        Ok(Self {
+            metallicity,
+            h_frac,
+            log_volume,
+            log_energy,
+            values,
+            spline: SplineConfig::default(),
+            method: InterpolationMethod::Cubic,
+            metadata: Vec::new(),
+        })
+    }
+
+    /// Decode a table from its raw binary representation.
+    ///
+    /// The content is a sequence of Fortran unformatted records: the shape
+    /// (`ne`, `nv`, `nvars` as `u32`), the log volumes, the log energies, and
+    /// then one record of state variables per volume and energy (with energy
+    /// varying fastest). Floating point records can be either `f64` or `f32`.
+    ///
+    /// Reading stops after the last record, any trailing bytes (e.g. a
+    /// provenance footer) are kept as [`VolumeEnergyTable::metadata`].
+    pub fn read_from<R: Read>(metallicity: f64, h_frac: f64, mut reader: R) -> io::Result<Self> {
+        let raw = RawTableContent::read_from(&mut reader)?;
+        let mut metadata = Vec::new();
+        reader.read_to_end(&mut metadata)?;
+        Ok(Self {
+            metadata,
+            ..Self::from_raw(metallicity, h_frac, raw)
+        })
     }
 
     pub fn metallicity(&self) -> f64 {
@@ -219,72 +964,616 @@ impl VolumeEnergyTable {
         self.log_energy
     }
 
-    pub fn values(&self) -> ArrayView3<f64> {
+    /// Intervals of log energy and log volume in which cubic interpolation
+    /// is possible, see [`Range::spline_valid_range`].
+    pub fn spline_valid_ranges(&self) -> SplineValidRanges {
+        (
+            self.log_energy.spline_valid_range(),
+            self.log_volume.spline_valid_range(),
+        )
+    }
+
+    /// Log energy and log volume axes restricted to the domain in which the
+    /// table can be interpolated with its method.
+    ///
+    /// Axes too short for cubic interpolation are kept whole, lookups then
+    /// fail everywhere on them.
+    pub(crate) fn method_domain(&self) -> (Range, Range) {
+        let domain = |axis: Range| match self.method {
+            InterpolationMethod::Cubic => axis.spline_domain().unwrap_or(axis),
+            InterpolationMethod::Linear | InterpolationMethod::Nearest => axis,
+        };
+        (domain(self.log_energy), domain(self.log_volume))
+    }
+
+    /// Bytes found after the last record when reading the table, empty for
+    /// tables built otherwise.
+    pub fn metadata(&self) -> &[u8] {
+        &self.metadata
+    }
+
+    /// Tabulated values, indexed by log energy, log volume, and state
+    /// variable.
+    pub fn values(&self) -> ArrayView3<'_, f64> {
         self.values.view()
     }
 
-    pub(crate) fn interp_at_metal(&self, other: &Self, metallicity: f64) -> Self {
-        assert!(self.log_volume.is_close(other.log_volume));
-        assert!(self.log_energy.is_close(other.log_energy));
-        assert!(self.h_frac.is_close(other.h_frac));
-        let lin = LinearInterpolator::new(self.metallicity, other.metallicity, metallicity);
-        Self {
-            metallicity,
-            h_frac: self.h_frac,
-            values: lin.interp(self.values.view(), other.values.view()),
-            log_volume: self.log_volume,
-            log_energy: self.log_energy,
+    /// Tabulated value of `var` at the node of index `i_energy` on the
+    /// [`log_energy`](Self::log_energy) axis and `i_volume` on the
+    /// [`log_volume`](Self::log_volume) axis, without interpolation.
+    ///
+    /// # Panics
+    ///
+    /// If an index is out of its axis or `var` is not stored in the table.
+    pub fn node_value(&self, i_energy: usize, i_volume: usize, var: StateVar) -> f64 {
+        self.values[[i_energy, i_volume, var as usize]]
+    }
+
+    /// Write the values of `vars` as CSV, with a header row
+    /// (`log_energy,log_volume` followed by the names of `vars`) and then one
+    /// row per node of the table, with volume varying fastest.
+    ///
+    /// Requesting a variable not stored in the table is reported as an
+    /// [`io::ErrorKind::InvalidInput`] error.
+    pub fn write_csv<W: Write>(&self, mut writer: W, vars: &[StateVar]) -> io::Result<()> {
+        self.check_vars(vars)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        write!(writer, "log_energy,log_volume")?;
+        for var in vars {
+            write!(writer, ",{}", var.name())?;
+        }
+        writeln!(writer)?;
+        for (i_e, loge) in self.log_energy.into_iter().enumerate() {
+            for (i_v, logv) in self.log_volume.into_iter().enumerate() {
+                write!(writer, "{loge},{logv}")?;
+                for &var in vars {
+                    write!(writer, ",{}", self.node_value(i_e, i_v, var))?;
+                }
+                writeln!(writer)?;
+            }
         }
+        Ok(())
     }
 
-    pub(crate) fn interp_at_h_frac(&self, other: &Self, h_frac: f64) -> Self {
-        assert!(self.log_volume.is_close(other.log_volume));
-        assert!(self.log_energy.is_close(other.log_energy));
-        assert!(self.metallicity.is_close(other.metallicity));
-        let lin = LinearInterpolator::new(self.h_frac, other.h_frac, h_frac);
-        Self {
-            metallicity: self.metallicity,
-            h_frac,
-            values: lin.interp(self.values.view(), other.values.view()),
-            log_volume: self.log_volume,
-            log_energy: self.log_energy,
+    /// Number of state variables stored in the table.
+    pub fn n_vars(&self) -> usize {
+        self.values.dim().2
+    }
+
+    /// State variables stored in the table, querying other variables fails.
+    pub fn available_vars(&self) -> Vec<StateVar> {
+        StateVar::ALL
+            .into_iter()
+            .filter(|&var| (var as usize) < self.n_vars())
+            .collect()
+    }
+
+    /// Check that `vars` are stored in the table, a missing variable is
+    /// reported as out of bounds of the `state_var` axis.
+    fn check_vars(&self, vars: &[StateVar]) -> Result<(), OutOfBoundsError> {
+        let n_vars = self.n_vars();
+        match vars.iter().find(|&&var| var as usize >= n_vars) {
+            Some(&var) => Err(OutOfBoundsError::new(
+                var as usize as f64,
+                (0.0, n_vars as f64 - 1.0),
+            )
+            .on("state_var")),
+            None => Ok(()),
         }
     }
 
-    pub fn at(
+    /// Use `spline` for interpolation in the table.
+    pub fn with_spline_config(self, spline: SplineConfig) -> Self {
+        Self { spline, ..self }
+    }
+
+    pub fn spline_config(&self) -> SplineConfig {
+        self.spline
+    }
+
+    /// Use `method` for interpolation in the table, the default is
+    /// [`InterpolationMethod::Cubic`].
+    pub fn with_method(self, method: InterpolationMethod) -> Self {
+        Self { method, ..self }
+    }
+
+    pub fn method(&self) -> InterpolationMethod {
+        self.method
+    }
+
+    /// Largest distance, in units of grid steps, from the query to the nearest
+    /// node along each axis.
+    ///
+    /// This is 0 on grid nodes and 0.5 mid-span, a cheap proxy for the
+    /// interpolation error.
+    pub fn interpolation_quality(&self, log_energy: f64, log_volume: f64) -> f64 {
+        let dist = |fidx: f64| (fidx - fidx.round()).abs();
+        let d_energy = dist(self.log_energy.fractional_index(log_energy));
+        let d_volume = dist(self.log_volume.fractional_index(log_volume));
+        d_energy.max(d_volume)
+    }
+
+    /// Closest point to `(log_energy, log_volume)` at which the table can be
+    /// interpolated with its method, this is the point itself if it is in
+    /// the table.
+    ///
+    /// Axes too short for cubic interpolation are kept whole.
+    pub fn nearest_valid(&self, log_energy: f64, log_volume: f64) -> (f64, f64) {
+        let (loge_domain, logv_domain) = self.method_domain();
+        (loge_domain.clamp(log_energy), logv_domain.clamp(log_volume))
+    }
+
+    /// Spline stencils along both axes, errors suggest the nearest valid
+    /// point.
+    fn stencils(
         &self,
         log_energy: f64,
         log_volume: f64,
-        var: StateVar,
-    ) -> Result<f64, OutOfBoundsError> {
-        Ok(cubic_spline_2d(
-            self.log_energy.spline_stencil(log_energy)?,
-            self.log_volume.spline_stencil(log_volume)?,
-            self.values().index_axis(Axis(2), var as usize),
-        ))
+    ) -> Result<(SplineStencil, SplineStencil), OutOfBoundsError> {
+        let stencils = self
+            .log_energy_stencil(log_energy)
+            .and_then(|loge_st| Ok((loge_st, self.log_volume_stencil(log_volume)?)));
+        stencils.map_err(|e| e.with_nearest_point(self.nearest_valid(log_energy, log_volume)))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::is_close::IsClose;
+    fn log_energy_stencil(&self, log_energy: f64) -> Result<SplineStencil, OutOfBoundsError> {
+        self.log_energy
+            .spline_stencil(log_energy)
+            .map(|st| st.with_tension(self.spline.tension))
+            .map_err(|e| e.on("log_energy"))
+    }
 
-    use super::{AllTables, StateVar};
+    fn log_volume_stencil(&self, log_volume: f64) -> Result<SplineStencil, OutOfBoundsError> {
+        self.log_volume
+            .spline_stencil(log_volume)
+            .map(|st| st.with_tension(self.spline.tension))
+            .map_err(|e| e.on("log_volume"))
+    }
 
-    #[test]
-    fn read_eos_table() {
-        let ve_eos = AllTables::default()
-            .take_at_metallicity(0.02)
-            .expect("metallicity is in range")
-            .take_at_h_frac(0.8)
-            .expect("helium fraction is in range");
-        assert!(ve_eos.log_volume().first().is_close(0.0));
+    /// Check that `other` has the same axes and, for the coordinates listed
+    /// in `same`, the same composition as `self`.
+    fn check_compatible(&self, other: &Self, same: &[Composition]) -> Result<(), TableError> {
+        if !self.log_volume.is_close(other.log_volume) {
+            return Err(TableError::Incompatible("log_volume axes"));
+        }
+        if !self.log_energy.is_close(other.log_energy) {
+            return Err(TableError::Incompatible("log_energy axes"));
+        }
+        if self.n_vars() != other.n_vars() {
+            return Err(TableError::Incompatible("state variables"));
+        }
+        for coord in same {
+            let (name, mine, theirs) = match coord {
+                Composition::Metallicity => ("metallicities", self.metallicity, other.metallicity),
+                Composition::HFrac => ("h_fracs", self.h_frac, other.h_frac),
+            };
+            if !mine.is_close(theirs) {
+                return Err(TableError::Incompatible(name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Linear interpolation of values between `self` at `at_self` and `other`
+    /// at `at_other`.
+    fn interp_values(
+        mut self,
+        other: &Self,
+        (at_self, at_other): (f64, f64),
+        at: f64,
+        axis: &'static str,
+    ) -> Result<Self, OutOfBoundsError> {
+        let bounds = (at_self.min(at_other), at_self.max(at_other));
+        if at.is_close(at_self) {
+            return Ok(self);
+        } else if at.is_close(at_other) {
+            self.values.assign(&other.values);
+        } else if at < bounds.0 || at > bounds.1 {
+            return Err(OutOfBoundsError::new(at, bounds).on(axis));
+        } else if at_self < at_other {
+            LinearInterpolator::new(at_self, at_other, at)
+                .interp_into(&mut self.values, other.values.view());
+        } else {
+            let lin = LinearInterpolator::new(at_other, at_self, at);
+            self.values = lin.interp(other.values.view(), self.values.view());
+        }
+        Ok(self)
+    }
+
+    /// Interpolate between this table and `other` at `metallicity`.
+    ///
+    /// Both tables should have the same axes and hydrogen fraction, and
+    /// `metallicity` should be between theirs.
+    pub fn interp_at_metal(self, other: &Self, metallicity: f64) -> Result<Self, TableError> {
+        self.check_compatible(other, &[Composition::HFrac])?;
+        let nodes = (self.metallicity, other.metallicity);
+        let table = self.interp_values(other, nodes, metallicity, "metallicity")?;
+        Ok(Self {
+            metallicity,
+            ..table
+        })
+    }
+
+    /// Interpolate between this table and `other` at `h_frac`.
+    ///
+    /// Both tables should have the same axes and metallicity, and `h_frac`
+    /// should be between theirs.
+    pub fn interp_at_h_frac(self, other: &Self, h_frac: f64) -> Result<Self, TableError> {
+        self.check_compatible(other, &[Composition::Metallicity])?;
+        let nodes = (self.h_frac, other.h_frac);
+        let table = self.interp_values(other, nodes, h_frac, "h_frac")?;
+        Ok(Self { h_frac, ..table })
+    }
+
+    /// Linear blend of this table (at `fraction` 0) and `other` (at
+    /// `fraction` 1), regardless of their composition.
+    ///
+    /// Both tables should have the same axes, the metallicity and hydrogen
+    /// fraction of the result are blended the same way as the values.
+    pub fn blend(&self, other: &Self, fraction: f64) -> Result<Self, TableError> {
+        self.check_compatible(other, &[])?;
+        let mix = |mine: f64, theirs: f64| (1.0 - fraction) * mine + fraction * theirs;
+        let metallicity = mix(self.metallicity, other.metallicity);
+        let h_frac = mix(self.h_frac, other.h_frac);
+        let table = self
+            .clone()
+            .interp_values(other, (0.0, 1.0), fraction, "fraction")?;
+        Ok(Self {
+            metallicity,
+            h_frac,
+            ..table
+        })
+    }
+
+    pub fn at(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+        var: StateVar,
+    ) -> Result<f64, OutOfBoundsError> {
+        let [value] = self.at_many(log_energy, log_volume, [var])?;
+        Ok(value)
+    }
+
+    /// Lookup of `var` prepared once for repeated calls with
+    /// `(log_energy, log_volume)`, equivalent to [`VolumeEnergyTable::at`].
+    ///
+    /// The variable is checked and its values selected once, and the
+    /// interpolation method is fixed when preparing.  The returned closure
+    /// borrows the table, it can't outlive it.
+    ///
+    /// ```
+    /// # use music_mesa_tables::eos_tables::{StateVar, VolumeEnergyTable};
+    /// # use music_mesa_tables::index::Range;
+    /// # use ndarray::Array3;
+    /// # let log_energy = Range::linspace(10.0, 14.0, 9).unwrap();
+    /// # let log_volume = Range::linspace(0.0, 4.0, 9).unwrap();
+    /// # let values = Array3::from_shape_fn((9, 9, StateVar::COUNT), |(i, j, _)| (i + j) as f64);
+    /// # let table = VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, values).unwrap();
+    /// let temperature = table.prepare(StateVar::LogTemperature);
+    /// let sum: f64 = (0..10).map(|i| temperature(12.0, 1.0 + 0.1 * i as f64).unwrap()).sum();
+    /// # assert!(sum > 0.0);
+    /// ```
+    pub fn prepare(
+        &self,
+        var: StateVar,
+    ) -> impl Fn(f64, f64) -> Result<f64, OutOfBoundsError> + '_ {
+        let values = self
+            .check_vars(&[var])
+            .map(|()| self.values.index_axis(Axis(2), var as usize));
+        let method = self.method;
+        move |log_energy, log_volume| {
+            let values = values.as_ref().map_err(Clone::clone)?;
+            match method {
+                InterpolationMethod::Nearest => {
+                    let (i_e, i_v) = self.nearest_indices(log_energy, log_volume)?;
+                    Ok(values[[i_e, i_v]])
+                }
+                InterpolationMethod::Linear => {
+                    let (loge_st, logv_st) = self.linear_stencils(log_energy, log_volume)?;
+                    Ok(lin_interp_2d(loge_st, logv_st, values.view()))
+                }
+                InterpolationMethod::Cubic => {
+                    let (loge_st, logv_st) = self.stencils(log_energy, log_volume)?;
+                    Ok(cubic_spline_2d(loge_st, logv_st, values.view()))
+                }
+            }
+        }
+    }
+
+    /// Same as [`VolumeEnergyTable::at`], queries outside of the table are
+    /// handled according to `policy`. Clamped queries are moved to
+    /// [`VolumeEnergyTable::nearest_valid`].
+    pub fn at_with_policy(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+        var: StateVar,
+        policy: OobPolicy,
+    ) -> Result<f64, OutOfBoundsError> {
+        self.check_vars(&[var])?;
+        policy.apply(
+            (log_energy, log_volume),
+            |(loge, logv)| self.nearest_valid(loge, logv),
+            |(loge, logv)| self.at(loge, logv, var),
+        )
+    }
+
+    /// Interpolate `var`, also returning a rough estimate of the interpolation
+    /// error.
+    ///
+    /// Along each axis, the spline is exact for quadratic data and its error
+    /// on cubic data is `d3 * t * (1 - t) * (1 - 2 * t) / 6`, with `d3` the
+    /// third difference of the four values of the stencil and `t` the position
+    /// of the query within its cell.  The estimate drops the `1 - 2 * t`
+    /// factor (which only vanishes mid-cell for exactly cubic data), uses the
+    /// largest `|d3|` over the stencil, and sums the contributions of both
+    /// axes.  It vanishes on grid nodes and grows with the local curvature of
+    /// the table, but is only meant as an order of magnitude.
+    ///
+    /// The estimate is specific to the spline, tables using another
+    /// interpolation method are reported with [`TableError::NotCubic`].
+    pub fn at_with_error(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+        var: StateVar,
+    ) -> Result<(f64, f64), TableError> {
+        if self.method != InterpolationMethod::Cubic {
+            return Err(TableError::NotCubic(self.method));
+        }
+        self.check_vars(&[var])?;
+        let (loge_stencil, logv_stencil) = self.stencils(log_energy, log_volume)?;
+        let values = self.values.index_axis(Axis(2), var as usize);
+        let value = cubic_spline_2d(loge_stencil, logv_stencil, values);
+
+        let third_diff = |y: [f64; 4]| (y[3] - 3.0 * y[2] + 3.0 * y[1] - y[0]).abs();
+        let position = |st: &SplineStencil| (st.at - st.xs[1]) / (st.xs[2] - st.xs[1]);
+        let (ie, iv) = (loge_stencil.nodes, logv_stencil.nodes);
+        let d3_energy = iv
+            .iter()
+            .map(|&j| third_diff(ie.map(|i| values[[i, j]])))
+            .fold(0.0, f64::max);
+        let d3_volume = ie
+            .iter()
+            .map(|&i| third_diff(iv.map(|j| values[[i, j]])))
+            .fold(0.0, f64::max);
+        let (t_e, t_v) = (position(&loge_stencil), position(&logv_stencil));
+        let error = (d3_energy * t_e * (1.0 - t_e) + d3_volume * t_v * (1.0 - t_v)) / 6.0;
+        Ok((value, error))
+    }
+
+    /// Look up `var` with the given `mode`.
+    ///
+    /// [`LookupMode::Nearest`] never fails within the bounds of the axes,
+    /// while interpolation needs a full spline stencil.
+    pub fn lookup(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+        var: StateVar,
+        mode: LookupMode,
+    ) -> Result<f64, OutOfBoundsError> {
+        match mode {
+            LookupMode::Interpolated => self.at(log_energy, log_volume, var),
+            LookupMode::Nearest => {
+                let [value] = self.nearest_many(log_energy, log_volume, [var])?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// Interpolate `var` at each `(log_energies[i], log_volumes[i])`, with
+    /// the interpolation method of the table.
+    ///
+    /// Bounds are checked once for the whole batch, and no stencil is built,
+    /// which is faster than calling [`VolumeEnergyTable::at`] on each point for
+    /// large batches. However, the output is collected in memory and a single
+    /// point out of the table makes the whole batch fail.
+    ///
+    /// # Panics
+    ///
+    /// If `log_energies` and `log_volumes` don't have the same length.
+    pub fn at_batch(
+        &self,
+        log_energies: &[f64],
+        log_volumes: &[f64],
+        var: StateVar,
+    ) -> Result<Vec<f64>, OutOfBoundsError> {
+        assert_eq!(log_energies.len(), log_volumes.len());
+        self.check_vars(&[var])?;
+        let values = self.values.index_axis(Axis(2), var as usize);
+        match self.method {
+            InterpolationMethod::Nearest => log_energies
+                .iter()
+                .zip(log_volumes)
+                .map(|(&loge, &logv)| {
+                    let (i_e, i_v) = self.nearest_indices(loge, logv)?;
+                    Ok(values[[i_e, i_v]])
+                })
+                .collect(),
+            InterpolationMethod::Linear => {
+                check_batch(log_energies, |v| {
+                    self.log_energy.idx_lin(v).map_err(|e| e.on("log_energy"))
+                })?;
+                check_batch(log_volumes, |v| {
+                    self.log_volume.idx_lin(v).map_err(|e| e.on("log_volume"))
+                })?;
+                Ok(lin_interp_2d_batch(
+                    self.log_energy,
+                    self.log_volume,
+                    log_energies,
+                    log_volumes,
+                    values,
+                ))
+            }
+            InterpolationMethod::Cubic => {
+                check_batch(log_energies, |v| self.log_energy_stencil(v))?;
+                check_batch(log_volumes, |v| self.log_volume_stencil(v))?;
+                Ok(cubic_spline_2d_batch(
+                    self.log_energy,
+                    self.log_volume,
+                    log_energies,
+                    log_volumes,
+                    values,
+                    self.spline.tension,
+                ))
+            }
+        }
+    }
+
+    /// Compute several state variables at once, sharing the interpolation
+    /// stencils between them.
+    pub fn at_many<const N: usize>(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+        vars: [StateVar; N],
+    ) -> Result<[f64; N], OutOfBoundsError> {
+        match self.method {
+            InterpolationMethod::Nearest => self.nearest_many(log_energy, log_volume, vars),
+            InterpolationMethod::Linear => self.linear_many(log_energy, log_volume, vars),
+            InterpolationMethod::Cubic => self.cubic_many(log_energy, log_volume, vars),
+        }
+    }
+
+    fn nearest_many<const N: usize>(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+        vars: [StateVar; N],
+    ) -> Result<[f64; N], OutOfBoundsError> {
+        self.check_vars(&vars)?;
+        let (i_e, i_v) = self.nearest_indices(log_energy, log_volume)?;
+        Ok(vars.map(|var| self.values[[i_e, i_v, var as usize]]))
+    }
+
+    fn nearest_indices(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+    ) -> Result<(usize, usize), OutOfBoundsError> {
+        let i_e = self
+            .log_energy
+            .nearest_index(log_energy)
+            .map_err(|e| e.on("log_energy"))?;
+        let i_v = self
+            .log_volume
+            .nearest_index(log_volume)
+            .map_err(|e| e.on("log_volume"))?;
+        Ok((i_e, i_v))
+    }
+
+    fn linear_many<const N: usize>(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+        vars: [StateVar; N],
+    ) -> Result<[f64; N], OutOfBoundsError> {
+        self.check_vars(&vars)?;
+        let (loge_stencil, logv_stencil) = self.linear_stencils(log_energy, log_volume)?;
+        Ok(vars.map(|var| {
+            lin_interp_2d(
+                loge_stencil,
+                logv_stencil,
+                self.values().index_axis_move(Axis(2), var as usize),
+            )
+        }))
+    }
+
+    fn linear_stencils(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+    ) -> Result<(LinearStencil, LinearStencil), OutOfBoundsError> {
+        let loge_stencil = self
+            .log_energy
+            .linear_stencil(log_energy)
+            .map_err(|e| e.on("log_energy"))?;
+        let logv_stencil = self
+            .log_volume
+            .linear_stencil(log_volume)
+            .map_err(|e| e.on("log_volume"))?;
+        Ok((loge_stencil, logv_stencil))
+    }
+
+    fn cubic_many<const N: usize>(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+        vars: [StateVar; N],
+    ) -> Result<[f64; N], OutOfBoundsError> {
+        self.check_vars(&vars)?;
+        let stencils = self.stencils(log_energy, log_volume)?;
+        Ok(self.spline_many(stencils, vars))
+    }
+
+    /// Same as [`VolumeEnergyTable::at_many`], reusing the spline stencils
+    /// stored in `cache`.
+    pub(crate) fn at_many_cached<const N: usize>(
+        &self,
+        log_energy: f64,
+        log_volume: f64,
+        vars: [StateVar; N],
+        cache: &mut StencilCache,
+    ) -> Result<[f64; N], OutOfBoundsError> {
+        if self.method != InterpolationMethod::Cubic {
+            return self.at_many(log_energy, log_volume, vars);
+        }
+        self.check_vars(&vars)?;
+        let stencils = cache.stencils(self, log_energy, log_volume)?;
+        Ok(self.spline_many(stencils, vars))
+    }
+
+    fn spline_many<const N: usize>(
+        &self,
+        (loge_stencil, logv_stencil): (SplineStencil, SplineStencil),
+        vars: [StateVar; N],
+    ) -> [f64; N] {
+        vars.map(|var| {
+            cubic_spline_2d(
+                loge_stencil,
+                logv_stencil,
+                self.values().index_axis_move(Axis(2), var as usize),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, io};
+
+    use ndarray::Array3;
+
+    use crate::{
+        fort_unfmt::write_fort_record,
+        grid::{InterpolationMethod, OobPolicy},
+        index::{Indexable, Range},
+        is_close::IsClose,
+    };
+
+    use super::{
+        AllTables, ConstMetalTables, StateVar, StateVarError, StencilCache, TableError,
+        VolumeEnergyTable,
+    };
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn read_eos_table() {
+        let ve_eos = AllTables::default()
+            .take_at_metallicity(0.02)
+            .expect("metallicity is in range")
+            .take_at_h_frac(0.8)
+            .expect("helium fraction is in range");
+        assert!(ve_eos.log_volume().first().is_close(0.0));
         assert!(ve_eos.log_volume().last().is_close(14.0));
         assert!(ve_eos.log_energy().first().is_close(10.5));
         assert!(ve_eos.log_energy().last().is_close(17.5));
     }
 
     #[test]
+    #[cfg(feature = "embedded-tables")]
     fn check_density() {
         let ve_eos = AllTables::default()
             .take_at_metallicity(0.02)
@@ -302,6 +1591,72 @@ mod tests {
     }
 
     #[test]
+    fn table_from_parts() {
+        let log_energy = Range::from_slice(&[10.0, 11.0, 12.0, 13.0, 14.0]).unwrap();
+        let log_volume = Range::from_slice(&[0.0, 2.0, 4.0, 6.0]).unwrap();
+        let surface = |loge: f64, logv: f64| 0.5 * loge * loge - 3.0 * logv + 1.0;
+        let values = Array3::from_shape_fn((5, 4, StateVar::COUNT), |(i, j, _)| {
+            surface(log_energy.at(i), log_volume.at(j))
+        });
+        let table = VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, values)
+            .expect("shape is consistent");
+        let value = table
+            .at(12.3, 3.1, StateVar::LogPressure)
+            .expect("point is in range");
+        assert!(value.is_close(surface(12.3, 3.1)));
+
+        let bad_values = Array3::zeros((4, 5, StateVar::COUNT));
+        assert!(
+            VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, bad_values).is_err()
+        );
+    }
+
+    #[test]
+    fn decode_synthetic_table() {
+        let log_volume = [0.0, 1.0, 2.0, 3.0];
+        let log_energy = [12.0, 12.5, 13.0, 13.5, 14.0];
+        let mut raw = Vec::new();
+        write_fort_record(&mut raw, &[5_u32, 4, StateVar::COUNT as u32]).unwrap();
+        write_fort_record(&mut raw, &log_volume).unwrap();
+        write_fort_record(&mut raw, &log_energy).unwrap();
+        for logv in log_volume {
+            for loge in log_energy {
+                write_fort_record(&mut raw, &[loge + 2.0 * logv; StateVar::COUNT]).unwrap();
+            }
+        }
+        let table = VolumeEnergyTable::read_from(0.02, 0.7, raw.as_slice())
+            .expect("synthetic table is well-formed");
+        assert!(table.log_energy().last().is_close(14.0));
+        assert!(table.values()[[4, 1, 0]].is_close(16.0));
+        let value = table
+            .at(13.2, 1.5, StateVar::LogTemperature)
+            .expect("point is in range");
+        assert!(value.is_close(16.2));
+        assert!(table.metadata().is_empty());
+
+        let footer = b"generated by synthetic test\n";
+        raw.extend_from_slice(footer);
+        let table = VolumeEnergyTable::read_from(0.02, 0.7, raw.as_slice())
+            .expect("trailing bytes are kept as metadata");
+        assert_eq!(table.metadata(), footer);
+        assert!(table.values()[[4, 1, 0]].is_close(16.0));
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn out_of_bounds_details() {
+        let err = AllTables::default()
+            .take_at_metallicity(0.05)
+            .err()
+            .expect("metallicity is out of range");
+        assert_eq!(err.axis(), Some("metallicity"));
+        assert!(err.value().is_close(0.05));
+        assert!(err.bounds().0.is_close(0.0));
+        assert!(err.bounds().1.is_close(0.04));
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
     fn interp_compo_consistency() {
         let z_eos = AllTables::default()
             .take_at_metallicity(0.02)
@@ -324,4 +1679,969 @@ mod tests {
 
         assert!(logt_direct.is_close(logt_full_interp))
     }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn exact_table_at_nodes() {
+        let tables = AllTables::default();
+        let exact = tables
+            .exact_table(0.02, 0.4)
+            .expect("composition is on grid nodes");
+        assert!(exact.metallicity().is_close(0.02));
+        assert!(exact.h_frac().is_close(0.4));
+        assert!(tables.exact_table(0.01, 0.4).is_none());
+        assert!(tables.exact_table(0.02, 0.3).is_none());
+        assert!(tables.exact_table(0.5, 0.4).is_none());
+
+        let taken = AllTables::default()
+            .take_at_metallicity(0.02)
+            .expect("metallicity is in range")
+            .take_at_h_frac(0.4)
+            .expect("hydrogen fraction is in range");
+        let (exact, taken) = (exact.values(), taken.values());
+        assert_eq!(exact.shape(), taken.shape());
+        assert!(exact
+            .iter()
+            .zip(taken.iter())
+            .all(|(e, t)| e.to_bits() == t.to_bits()));
+    }
+
+    #[test]
+    fn tables_are_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<AllTables>();
+        assert_send_sync::<ConstMetalTables>();
+        assert_send_sync::<VolumeEnergyTable>();
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn interpolation_quality_at_nodes_and_mid_span() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .expect("metallicity is in range")
+            .take_at_h_frac(0.4)
+            .expect("hydrogen fraction is in range");
+        let (loge, logv) = (table.log_energy(), table.log_volume());
+        assert!(table.interpolation_quality(loge.at(10), logv.at(5)) < 1e-9);
+        let mid_energy = 0.5 * (loge.at(10) + loge.at(11));
+        assert!(table
+            .interpolation_quality(mid_energy, logv.at(5))
+            .is_close(0.5));
+        let quarter_volume = 0.75 * logv.at(5) + 0.25 * logv.at(6);
+        assert!(table
+            .interpolation_quality(loge.at(10), quarter_volume)
+            .is_close(0.25));
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn traced_interpolation() {
+        let tables = AllTables::default();
+        let (loge, logv) = (15.2, 6.3);
+        let (value, trace) = tables
+            .at_traced(0.03, 0.7, loge, logv, StateVar::LogTemperature)
+            .expect("query is in range");
+        assert_eq!(trace.metallicity.indices, (1, 2));
+        assert!(trace.metallicity.left_weight.is_close(0.5));
+        assert_eq!(trace.h_frac.len(), 2);
+        assert!(trace.h_frac.iter().all(|t| t.indices == (3, 4)));
+        assert!(trace.h_frac[0].left_weight.is_close(0.5));
+        assert_eq!(
+            trace.to_string(),
+            "interpolated between Z=0.020000/0.040000 (left weight 0.500) \
+             and X=0.600000/0.800000 (left weight 0.500) \
+             and X=0.600000/0.800000 (left weight 0.500)"
+        );
+
+        let expected = AllTables::default()
+            .take_at_metallicity(0.03)
+            .unwrap()
+            .at(0.7, loge, logv, StateVar::LogTemperature)
+            .unwrap();
+        assert!((value - expected).abs() < 1e-10);
+
+        let (_, trace) = tables
+            .at_traced(0.02, 0.4, loge, logv, StateVar::LogTemperature)
+            .expect("query is in range");
+        assert_eq!(
+            trace.to_string(),
+            "interpolated between Z=0.020000 and X=0.400000"
+        );
+    }
+
+    #[test]
+    fn decode_single_precision_table() {
+        let log_volume = [0.0_f32, 1.0, 2.0, 3.0];
+        let log_energy = [12.0_f32, 12.5, 13.0, 13.5, 14.0];
+        let mut raw = Vec::new();
+        write_fort_record(&mut raw, &[5_u32, 4, StateVar::COUNT as u32]).unwrap();
+        write_fort_record(&mut raw, &log_volume).unwrap();
+        write_fort_record(&mut raw, &log_energy).unwrap();
+        for logv in log_volume {
+            for loge in log_energy {
+                write_fort_record(&mut raw, &[loge + 2.0 * logv; StateVar::COUNT]).unwrap();
+            }
+        }
+        let table = VolumeEnergyTable::read_from(0.02, 0.7, raw.as_slice())
+            .expect("synthetic table is well-formed");
+        assert!(table.log_energy().last().is_close(14.0));
+        assert!(table.values()[[4, 1, 0]].is_close(16.0));
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn batch_matches_pointwise() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .expect("metallicity is in range")
+            .take_at_h_frac(0.4)
+            .expect("hydrogen fraction is in range");
+        let loges = [11.0, 13.2, 15.0, 17.0];
+        let logvs = [0.5, 4.3, 7.0, 9.4];
+        let batch = table
+            .at_batch(&loges, &logvs, StateVar::LogPressure)
+            .expect("points are in range");
+        for ((&loge, &logv), value) in loges.iter().zip(&logvs).zip(batch) {
+            let expected = table.at(loge, logv, StateVar::LogPressure).unwrap();
+            assert!((value - expected).abs() < 1e-12);
+        }
+        let err = table
+            .at_batch(&[10.5, 13.0], &logvs[..2], StateVar::LogPressure)
+            .unwrap_err();
+        assert_eq!(err.axis(), Some("log_energy"));
+        let linear = table.clone().with_method(InterpolationMethod::Linear);
+        for table in [&table, &linear] {
+            let err = table
+                .at_batch(&[11.0, f64::NAN, 15.0], &logvs[..3], StateVar::LogPressure)
+                .unwrap_err();
+            assert_eq!(err.axis(), Some("log_energy"));
+        }
+    }
+
+    #[test]
+    fn all_state_vars_in_table_order() {
+        assert!(StateVar::ALL
+            .iter()
+            .enumerate()
+            .all(|(i, &var)| var as usize == i));
+        assert_eq!(StateVar::Gamma1.name(), "Gamma1");
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn single_metallicity_tables() {
+        use crate::raw_tables::eos::{AllRawTables, RAW_TABLES};
+
+        let raw = AllRawTables {
+            tables: &RAW_TABLES.tables[1..2],
+        };
+        let tables = AllTables::from(&raw);
+        let (loge, logv) = (15.2, 6.3);
+        let value = tables
+            .at(0.02, 0.4, loge, logv, StateVar::LogTemperature)
+            .expect("metallicity is the tabulated one");
+        let expected = AllTables::default()
+            .at(0.02, 0.4, loge, logv, StateVar::LogTemperature)
+            .unwrap();
+        assert!(value.is_close(expected));
+        assert!(tables.exact_table(0.02, 0.4).is_some());
+        let err = AllTables::from(&raw)
+            .take_at_metallicity(0.03)
+            .err()
+            .unwrap();
+        assert_eq!(err.axis(), Some("metallicity"));
+        assert_eq!(err.bounds(), (0.02, 0.02));
+    }
+
+    #[test]
+    fn decode_invalid_axis() {
+        let mut raw = Vec::new();
+        write_fort_record(&mut raw, &[2_u32, 3, StateVar::COUNT as u32]).unwrap();
+        write_fort_record(&mut raw, &[0.0, 1.0, 3.0]).unwrap();
+        let err = VolumeEnergyTable::read_from(0.02, 0.7, raw.as_slice())
+            .err()
+            .expect("log volume axis is not linear");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn embedded_grid_matches_tables() {
+        let grid = super::embedded_grid();
+        let tables = AllTables::default();
+        for (metallicity, h_fracs) in grid {
+            assert!(h_fracs.last() + metallicity <= 1.0);
+            assert!(tables.exact_table(metallicity, h_fracs.last()).is_some());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn interp_metallicity_with_zero() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.01)
+            .expect("metallicity is in range");
+        assert!(table.h_fracs().last().is_close(0.8));
+    }
+
+    #[test]
+    fn composition_combinators() {
+        let log_energy = Range::from_slice(&[10.0, 11.0, 12.0, 13.0]).unwrap();
+        let log_volume = Range::from_slice(&[0.0, 2.0, 4.0, 6.0]).unwrap();
+        let table = |metallicity: f64, h_frac: f64, value: f64| {
+            let values = Array3::from_elem((4, 4, StateVar::COUNT), value);
+            VolumeEnergyTable::from_parts(metallicity, h_frac, log_energy, log_volume, values)
+                .unwrap()
+        };
+        let mid = table(0.04, 0.7, 3.0)
+            .interp_at_metal(&table(0.02, 0.7, 1.0), 0.025)
+            .expect("tables are compatible");
+        assert!(mid.metallicity().is_close(0.025));
+        assert!(mid.values()[[0, 0, 0]].is_close(1.5));
+        let mid = table(0.02, 0.6, 1.0)
+            .interp_at_h_frac(&table(0.02, 0.8, 2.0), 0.7)
+            .expect("tables are compatible");
+        assert!(mid.values()[[3, 3, 0]].is_close(1.5));
+
+        assert!(matches!(
+            table(0.02, 0.6, 1.0).interp_at_metal(&table(0.04, 0.7, 2.0), 0.03),
+            Err(TableError::Incompatible(_))
+        ));
+        assert!(matches!(
+            table(0.02, 0.6, 1.0).interp_at_h_frac(&table(0.02, 0.8, 2.0), 0.9),
+            Err(TableError::OutOfBounds(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn nearest_lookup() {
+        use crate::grid::LookupMode;
+
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .expect("metallicity is in range")
+            .take_at_h_frac(0.4)
+            .expect("hydrogen fraction is in range");
+        let (loge, logv) = (table.log_energy().at(100), table.log_volume().at(80));
+        let node = table.at(loge, logv, StateVar::LogTemperature).unwrap();
+        let (loge_near, logv_near) = (loge + 0.004, logv - 0.01);
+        let nearest = table
+            .lookup(
+                loge_near,
+                logv_near,
+                StateVar::LogTemperature,
+                LookupMode::Nearest,
+            )
+            .unwrap();
+        assert_eq!(nearest, node);
+        let interpolated = table
+            .lookup(
+                loge_near,
+                logv_near,
+                StateVar::LogTemperature,
+                LookupMode::Interpolated,
+            )
+            .unwrap();
+        assert!((interpolated - nearest).abs() < 1e-2);
+        let first = (table.log_energy().first(), table.log_volume().first());
+        assert!(table
+            .lookup(
+                first.0,
+                first.1,
+                StateVar::LogTemperature,
+                LookupMode::Nearest
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn spline_tension_config() {
+        use crate::grid::SplineConfig;
+
+        let table = VolumeEnergyTable::from_parts(
+            0.02,
+            0.7,
+            Range::new(0.0, 1.0, 6),
+            Range::new(0.0, 1.0, 6),
+            Array3::from_shape_fn((6, 6, StateVar::COUNT), |(i, j, _)| (i * i + j) as f64),
+        )
+        .unwrap();
+        let at = |table: &VolumeEnergyTable| table.at(2.5, 2.0, StateVar::Gamma).unwrap();
+        let reference = at(&table);
+        assert!(reference.is_close(8.25));
+        let table = table.with_spline_config(SplineConfig { tension: 0.0 });
+        assert!(at(&table).is_close(reference));
+        let table = table.with_spline_config(SplineConfig { tension: 0.5 });
+        // curvature along energy is halved, the line at nodes is unchanged
+        assert!(at(&table).is_close(8.375));
+        assert_eq!(table.spline_config().tension, 0.5);
+        let batch = table.at_batch(&[2.5], &[2.0], StateVar::Gamma).unwrap();
+        assert!(batch[0].is_close(8.375));
+    }
+
+    fn uniform_metal_tables(metallicity: f64, h_fracs: Range) -> ConstMetalTables {
+        let tables = h_fracs
+            .into_iter()
+            .map(|h_frac| {
+                VolumeEnergyTable::from_parts(
+                    metallicity,
+                    h_frac,
+                    Range::new(0.0, 1.0, 4),
+                    Range::new(0.0, 1.0, 4),
+                    Array3::from_elem((4, 4, StateVar::COUNT), metallicity + h_frac),
+                )
+                .unwrap()
+            })
+            .collect();
+        ConstMetalTables::from_tables(metallicity, h_fracs, tables).unwrap()
+    }
+
+    #[test]
+    fn custom_metallicity_grid() {
+        let all = AllTables::from_metal_tables(vec![
+            uniform_metal_tables(0.0, Range::new(0.0, 0.2, 5)),
+            uniform_metal_tables(0.02, Range::new(0.4, 0.2, 3)),
+        ])
+        .unwrap();
+        let value = all.at(0.01, 0.6, 1.5, 1.5, StateVar::Gamma).unwrap();
+        assert!(value.is_close(0.61));
+        assert!(all.take_at_metallicity(0.01).is_ok());
+
+        let disjoint = AllTables::from_metal_tables(vec![
+            uniform_metal_tables(0.0, Range::new(0.0, 0.2, 2)),
+            uniform_metal_tables(0.02, Range::new(0.6, 0.2, 2)),
+        ]);
+        assert!(matches!(
+            disjoint,
+            Err(TableError::DisjointHFracs { left, right }) if left == 0.0 && right == 0.02
+        ));
+
+        assert!(matches!(
+            AllTables::from_metal_tables(vec![]),
+            Err(TableError::NoTables)
+        ));
+
+        let h_fracs = Range::new(0.0, 0.2, 2);
+        let other_axes = h_fracs
+            .into_iter()
+            .map(|h_frac| {
+                VolumeEnergyTable::from_parts(
+                    0.02,
+                    h_frac,
+                    Range::new(0.0, 2.0, 4),
+                    Range::new(0.0, 1.0, 4),
+                    Array3::zeros((4, 4, StateVar::COUNT)),
+                )
+                .unwrap()
+            })
+            .collect();
+        let other_axes = ConstMetalTables::from_tables(0.02, h_fracs, other_axes).unwrap();
+        assert!(matches!(
+            AllTables::from_metal_tables(vec![uniform_metal_tables(0.0, h_fracs), other_axes]),
+            Err(TableError::Incompatible(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn embedded_grid_is_valid() {
+        assert!(AllTables::default().validate_grid().is_ok());
+    }
+
+    #[test]
+    fn interpolation_error_estimate() {
+        let table = |f: fn(f64, f64) -> f64| {
+            VolumeEnergyTable::from_parts(
+                0.02,
+                0.7,
+                Range::new(0.0, 1.0, 6),
+                Range::new(0.0, 1.0, 6),
+                Array3::from_shape_fn((6, 6, StateVar::COUNT), |(i, j, _)| f(i as f64, j as f64)),
+            )
+            .unwrap()
+        };
+        let quadratic = table(|e, v| e * e - 3.0 * v * v + e * v);
+        let (value, error) = quadratic.at_with_error(2.3, 1.6, StateVar::Gamma).unwrap();
+        assert!(value.is_close(2.3 * 2.3 - 3.0 * 1.6 * 1.6 + 2.3 * 1.6));
+        assert_eq!(error, 0.0);
+
+        let cubic_fn = |e: f64, v: f64| e * e * e + 2.0 * v;
+        let cubic = table(cubic_fn);
+        for (loge, logv) in [(1.2, 2.0), (2.5, 3.3), (3.9, 1.5)] {
+            let (value, error) = cubic.at_with_error(loge, logv, StateVar::Gamma).unwrap();
+            assert!((value - cubic_fn(loge, logv)).abs() <= error);
+            assert!(error > 0.0);
+        }
+        let (_, error) = cubic.at_with_error(2.0, 3.3, StateVar::Gamma).unwrap();
+        assert_eq!(error, 0.0);
+    }
+
+    #[test]
+    fn partial_state_vars() {
+        use crate::grid::LookupMode;
+
+        let n_vars = StateVar::Gamma1 as usize;
+        let values = Array3::from_elem((4, 4, n_vars), 1.0);
+        let table = VolumeEnergyTable::from_parts(
+            0.02,
+            0.7,
+            Range::new(0.0, 1.0, 4),
+            Range::new(0.0, 1.0, 4),
+            values,
+        )
+        .unwrap();
+        assert_eq!(table.n_vars(), n_vars);
+        assert_eq!(table.available_vars().len(), n_vars);
+        assert!(table.at(1.5, 1.5, StateVar::DTempDPresScst).is_ok());
+        let err = table.at(1.5, 1.5, StateVar::Gamma).unwrap_err();
+        assert_eq!(err.axis(), Some("state_var"));
+        assert!(table
+            .at_many(1.5, 1.5, [StateVar::LogPressure, StateVar::Gamma1])
+            .is_err());
+        assert!(table
+            .lookup(1.5, 1.5, StateVar::Gamma, LookupMode::Nearest)
+            .is_err());
+        // a missing variable is not turned into NaN by the policy
+        for policy in [OobPolicy::Clamp, OobPolicy::Nan] {
+            let err = table
+                .at_with_policy(1.5, 1.5, StateVar::Gamma, policy)
+                .unwrap_err();
+            assert_eq!(err.axis(), Some("state_var"));
+        }
+
+        let full = VolumeEnergyTable::from_parts(
+            0.02,
+            0.8,
+            Range::new(0.0, 1.0, 4),
+            Range::new(0.0, 1.0, 4),
+            Array3::zeros((4, 4, StateVar::COUNT)),
+        )
+        .unwrap();
+        assert_eq!(full.available_vars().len(), StateVar::COUNT);
+        assert!(matches!(
+            table.interp_at_h_frac(&full, 0.75),
+            Err(TableError::Incompatible(_))
+        ));
+    }
+
+    #[test]
+    fn nearest_valid_point() {
+        let table = VolumeEnergyTable::from_parts(
+            0.02,
+            0.7,
+            Range::new(10.0, 1.0, 6),
+            Range::new(0.0, 2.0, 5),
+            Array3::zeros((6, 5, StateVar::COUNT)),
+        )
+        .unwrap();
+        // interpolation is possible on [11, 14] x [2, 6]
+        assert_eq!(table.nearest_valid(12.5, 3.0), (12.5, 3.0));
+        assert_eq!(table.nearest_valid(9.0, 3.0), (11.0, 3.0));
+        assert_eq!(table.nearest_valid(15.0, 3.0), (14.0, 3.0));
+        assert_eq!(table.nearest_valid(12.5, -1.0), (12.5, 2.0));
+        assert_eq!(table.nearest_valid(12.5, 7.0), (12.5, 6.0));
+        assert_eq!(table.nearest_valid(20.0, 7.0), (14.0, 6.0));
+
+        let err = table.at(12.5, 7.0, StateVar::LogPressure).unwrap_err();
+        assert_eq!(err.axis(), Some("log_volume"));
+        assert_eq!(err.nearest_point(), Some((12.5, 6.0)));
+        assert!(err
+            .to_string()
+            .ends_with("nearest valid point is (12.5, 6)"));
+    }
+
+    #[test]
+    fn out_of_bounds_on_short_axis() {
+        let table = VolumeEnergyTable::from_parts(
+            0.02,
+            0.7,
+            Range::new(10.0, 1.0, 5),
+            Range::new(0.0, 2.0, 3),
+            Array3::zeros((5, 3, StateVar::COUNT)),
+        )
+        .unwrap();
+        assert_eq!(table.nearest_valid(11.5, 5.0), (11.5, 4.0));
+        let err = table.at(11.5, 5.0, StateVar::LogPressure).unwrap_err();
+        assert_eq!(err.axis(), Some("log_volume"));
+        assert_eq!(err.nearest_point(), Some((11.5, 4.0)));
+    }
+
+    #[test]
+    fn clamp_follows_method() {
+        let log_energy = Range::new(10.0, 1.0, 6);
+        let log_volume = Range::new(0.0, 2.0, 5);
+        let values =
+            Array3::from_shape_fn((6, 5, StateVar::COUNT), |(i, j, _)| (i + 10 * j) as f64);
+        let table =
+            VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, values.clone())
+                .unwrap()
+                .with_method(InterpolationMethod::Linear);
+        // the whole axes are valid with linear interpolation
+        assert_eq!(table.nearest_valid(9.0, 9.0), (10.0, 8.0));
+        let clamped = table
+            .at_with_policy(9.0, 9.0, StateVar::LogPressure, OobPolicy::Clamp)
+            .unwrap();
+        assert_eq!(clamped, table.at(10.0, 8.0, StateVar::LogPressure).unwrap());
+        assert_eq!(clamped, 40.0);
+
+        let tables = ConstMetalTables::from_tables(
+            0.02,
+            Range::new(0.0, 0.5, 3),
+            (0..3)
+                .map(|i| {
+                    VolumeEnergyTable::from_parts(
+                        0.02,
+                        0.5 * i as f64,
+                        log_energy,
+                        log_volume,
+                        values.clone(),
+                    )
+                    .unwrap()
+                })
+                .collect(),
+        )
+        .unwrap()
+        .with_method(InterpolationMethod::Linear);
+        let clamped = tables
+            .at_with_policy(1.5, 9.0, 9.0, StateVar::LogPressure, OobPolicy::Clamp)
+            .unwrap();
+        assert_eq!(clamped, 40.0);
+    }
+
+    #[test]
+    fn state_var_round_trip() {
+        for (i, var) in StateVar::ALL.into_iter().enumerate() {
+            assert_eq!(var as usize, i);
+            assert_eq!(StateVar::try_from(i).unwrap(), var);
+            assert_eq!(var.name().parse::<StateVar>().unwrap(), var);
+            assert_eq!(var.to_string(), var.name());
+        }
+        assert!(matches!(
+            StateVar::try_from(StateVar::COUNT),
+            Err(StateVarError::Index(StateVar::COUNT))
+        ));
+        assert!(matches!(
+            "logdensity".parse::<StateVar>(),
+            Err(StateVarError::Name(_))
+        ));
+    }
+
+    #[test]
+    fn state_var_as_map_key() {
+        let names: HashMap<StateVar, &str> = StateVar::ALL
+            .into_iter()
+            .map(|var| (var, var.name()))
+            .collect();
+        assert_eq!(names.len(), StateVar::COUNT);
+        assert_eq!(names[&StateVar::Gamma1], "Gamma1");
+    }
+
+    #[test]
+    fn blend_tables() {
+        let log_energy = Range::from_slice(&[10.0, 11.0, 12.0, 13.0]).unwrap();
+        let log_volume = Range::from_slice(&[0.0, 2.0, 4.0, 6.0]).unwrap();
+        let table = |metallicity: f64, h_frac: f64, value: f64| {
+            let values = Array3::from_elem((4, 4, StateVar::COUNT), value);
+            VolumeEnergyTable::from_parts(metallicity, h_frac, log_energy, log_volume, values)
+                .unwrap()
+        };
+        let hydrogen = table(0.0, 1.0, 1.0);
+        let helium = table(0.0, 0.0, 3.0);
+        let mix = hydrogen.blend(&helium, 0.25).unwrap();
+        assert!(mix.h_frac().is_close(0.75));
+        assert!(mix.values()[[2, 1, 0]].is_close(1.5));
+        assert!(hydrogen.blend(&helium, 1.0).unwrap().values()[[0, 0, 0]].is_close(3.0));
+        assert!(matches!(
+            hydrogen.blend(&helium, 1.5),
+            Err(TableError::OutOfBounds(_))
+        ));
+        let shifted = Range::from_slice(&[1.0, 3.0, 5.0, 7.0]).unwrap();
+        let other = VolumeEnergyTable::from_parts(
+            0.0,
+            0.0,
+            log_energy,
+            shifted,
+            Array3::zeros((4, 4, StateVar::COUNT)),
+        )
+        .unwrap();
+        assert!(matches!(
+            hydrogen.blend(&other, 0.5),
+            Err(TableError::Incompatible(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn exact_node_values() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_h_frac(0.7)
+            .unwrap();
+        let (i_energy, i_volume) = (40, 30);
+        let loge = table.log_energy().at(i_energy);
+        let logv = table.log_volume().at(i_volume);
+        let node = table.node_value(i_energy, i_volume, StateVar::LogPressure);
+        assert_eq!(
+            node,
+            table.values()[[i_energy, i_volume, StateVar::LogPressure as usize]]
+        );
+        assert!(node.is_close(table.at(loge, logv, StateVar::LogPressure).unwrap()));
+    }
+
+    #[test]
+    fn irregular_metallicity_grid() {
+        let h_fracs = Range::new(0.4, 0.2, 3);
+        let all = AllTables::from_metal_tables(
+            [0.0, 0.001, 0.004, 0.02]
+                .map(|z| uniform_metal_tables(z, h_fracs))
+                .into(),
+        )
+        .unwrap();
+        let value = all.at(0.003, 0.6, 1.5, 1.5, StateVar::Gamma).unwrap();
+        assert!(value.is_close(0.603));
+        let table = all.take_at_metallicity(0.012).unwrap();
+        assert!(table.metallicity().is_close(0.012));
+        assert!(table
+            .at(0.6, 1.5, 1.5, StateVar::Gamma)
+            .unwrap()
+            .is_close(0.612));
+        assert!(AllTables::from_metal_tables(
+            [0.02, 0.0].map(|z| uniform_metal_tables(z, h_fracs)).into()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn interpolation_method_dispatch() {
+        let log_energy = Range::from_slice(&[10.0, 11.0, 12.0, 13.0, 14.0]).unwrap();
+        let log_volume = Range::from_slice(&[0.0, 2.0, 4.0, 6.0]).unwrap();
+        let surface = |loge: f64, logv: f64| 0.5 * loge * loge - 3.0 * logv + 1.0;
+        let values = Array3::from_shape_fn((5, 4, StateVar::COUNT), |(i, j, _)| {
+            surface(log_energy.at(i), log_volume.at(j))
+        });
+        let table =
+            VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, values).unwrap();
+        assert_eq!(table.method(), InterpolationMethod::Cubic);
+        let var = StateVar::LogPressure;
+
+        let linear = table.clone().with_method(InterpolationMethod::Linear);
+        let expected = 0.5 * (surface(12.0, 3.0) + surface(13.0, 3.0));
+        assert!(linear.at(12.5, 3.0, var).unwrap().is_close(expected));
+        // linear interpolation also works next to the edges of the table
+        assert!(linear.at(10.5, 1.0, var).is_ok());
+        assert!(table.at(10.5, 1.0, var).is_err());
+
+        let nearest = table.clone().with_method(InterpolationMethod::Nearest);
+        assert!(nearest
+            .at(12.3, 3.9, var)
+            .unwrap()
+            .is_close(surface(12.0, 4.0)));
+
+        // batches and error estimates follow the method of the table
+        let (log_energies, log_volumes) = ([10.5, 12.3, 14.0], [1.0, 3.9, 6.0]);
+        for table in [&linear, &nearest] {
+            let batch = table.at_batch(&log_energies, &log_volumes, var).unwrap();
+            for ((&loge, &logv), value) in log_energies.iter().zip(&log_volumes).zip(batch) {
+                assert!(value.is_close(table.at(loge, logv, var).unwrap()));
+            }
+            assert!(matches!(
+                table.at_with_error(12.3, 3.9, var),
+                Err(TableError::NotCubic(method)) if method == table.method()
+            ));
+        }
+        assert!(table.at_batch(&log_energies, &log_volumes, var).is_err());
+        assert!(table.at_with_error(12.3, 3.9, var).is_ok());
+    }
+
+    #[test]
+    fn csv_export() {
+        let log_energy = Range::from_slice(&[10.0, 11.0, 12.0, 13.0, 14.0]).unwrap();
+        let log_volume = Range::from_slice(&[0.0, 2.0, 4.0, 6.0]).unwrap();
+        let values = Array3::from_shape_fn((5, 4, 2), |(i, j, k)| (i * 10 + j + k) as f64);
+        let table =
+            VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, values).unwrap();
+        let mut csv = Vec::new();
+        table
+            .write_csv(&mut csv, &[StateVar::LogDensity, StateVar::LogPressure])
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 1 + 5 * 4);
+        assert!(rows.iter().all(|row| row.len() == 4));
+        assert_eq!(
+            rows[0],
+            ["log_energy", "log_volume", "LogDensity", "LogPressure"]
+        );
+        assert_eq!(rows[6], ["11", "2", "11", "12"]);
+
+        let err = table
+            .write_csv(io::sink(), &[StateVar::LogTemperature])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn h_frac_between_interpolated_tables() {
+        let tables = AllTables::default().take_at_metallicity(0.01).unwrap();
+        let h_fracs = tables.h_fracs();
+        let (h_left, h_right) = (h_fracs.at(1), h_fracs.at(2));
+        let h_frac = 0.3 * h_left + 0.7 * h_right;
+        let (loge, logv) = (15.2, 6.3);
+        let var = StateVar::LogPressure;
+        let at_node = |h| {
+            tables
+                .clone()
+                .take_at_h_frac(h)
+                .unwrap()
+                .at(loge, logv, var)
+                .unwrap()
+        };
+        let expected = 0.3 * at_node(h_left) + 0.7 * at_node(h_right);
+        let value = tables.at(h_frac, loge, logv, var).unwrap();
+        assert!(value.is_close(expected));
+        assert_eq!(value, tables.at(h_frac, loge, logv, var).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn resample_h_fracs() {
+        let tables = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let h_fracs = tables.h_fracs();
+        let new_h_fracs = Range::linspace(h_fracs.at(1), h_fracs.at(3), 7).unwrap();
+        let resampled = tables.resample_h_fracs(&new_h_fracs).unwrap();
+        assert_eq!(resampled.h_fracs().n_values(), 7);
+        assert!(resampled.metallicity().is_close(0.02));
+        let (loge, logv) = (14.7, 5.1);
+        let var = StateVar::LogTemperature;
+        for h_frac in new_h_fracs {
+            let expected = tables.at_h_frac(h_frac).unwrap().at(loge, logv, var);
+            let value = resampled.at_h_frac(h_frac).unwrap().at(loge, logv, var);
+            assert!(value.unwrap().is_close(expected.unwrap()));
+        }
+
+        let too_wide = Range::linspace(h_fracs.first(), h_fracs.last() + 0.1, 4).unwrap();
+        assert!(tables.resample_h_fracs(&too_wide).is_err());
+    }
+
+    #[test]
+    fn prepared_lookup() {
+        let log_energy = Range::new(10.0, 0.5, 9);
+        let log_volume = Range::new(0.0, 1.0, 7);
+        let n_vars = StateVar::Gamma1 as usize;
+        let values = Array3::from_shape_fn((9, 7, n_vars), |(i, j, k)| {
+            (0.3 * i as f64).sin() + (0.2 * j as f64).cos() + k as f64
+        });
+        let table =
+            VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, values).unwrap();
+        for method in [
+            InterpolationMethod::Nearest,
+            InterpolationMethod::Linear,
+            InterpolationMethod::Cubic,
+        ] {
+            let table = table.clone().with_method(method);
+            let pressure = table.prepare(StateVar::LogPressure);
+            for (loge, logv) in [(11.2, 2.3), (12.0, 3.0), (13.7, 4.9), (10.2, 0.4)] {
+                let expected = table.at(loge, logv, StateVar::LogPressure);
+                match (pressure(loge, logv), expected) {
+                    (Ok(value), Ok(expected)) => assert_eq!(value, expected),
+                    (Err(_), Err(_)) => {}
+                    _ => panic!("prepared lookup differs from at with {method:?}"),
+                }
+            }
+            assert!(pressure(20.0, 3.0).is_err());
+        }
+        let gamma = table.prepare(StateVar::Gamma);
+        assert_eq!(gamma(12.0, 3.0).unwrap_err().axis(), Some("state_var"));
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn hydrogen_free_tables() {
+        let tables = AllTables::default().take_at_metallicity(0.02).unwrap();
+        assert!(tables.h_fracs().first().is_close(0.0));
+        let (loge, logv) = (14.3, 5.6);
+        let var = StateVar::LogPressure;
+        let at_zero = tables.at(0.0, loge, logv, var).unwrap();
+        for h_frac in [1e-16, -1e-16, 5e-13] {
+            assert_eq!(tables.at(h_frac, loge, logv, var).unwrap(), at_zero);
+            let table = tables.at_h_frac(h_frac).unwrap();
+            assert_eq!(table.at(loge, logv, var).unwrap(), at_zero);
+        }
+        let tiny = tables.at(1e-11, loge, logv, var).unwrap();
+        assert!((tiny - at_zero).abs() < 1e-9);
+        assert!(tables.at(-1e-11, loge, logv, var).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn metallicity_sweep() {
+        let all = AllTables::default();
+        let zs = [0.0, 0.01, 0.02, 0.035];
+        let tables = all.at_metallicities(&zs).unwrap();
+        assert_eq!(tables.len(), zs.len());
+        let (loge, logv) = (15.1, 4.2);
+        let var = StateVar::LogTemperature;
+        for (table, z) in tables.iter().zip(zs) {
+            assert!(table.metallicity().is_close(z));
+            let expected = all.at_metallicity(z).unwrap().at(0.5, loge, logv, var);
+            assert_eq!(table.at(0.5, loge, logv, var).unwrap(), expected.unwrap());
+        }
+        let err = all.at_metallicities(&[0.02, 0.5]).err().unwrap();
+        assert_eq!(err.axis(), Some("metallicity"));
+        assert!(all.at_metallicities(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bilinear_mode_by_hand() {
+        let log_energy = Range::new(10.0, 0.5, 4);
+        let log_volume = Range::new(1.0, 2.0, 5);
+        let node = |i: usize, j: usize| (i * i) as f64 + 3.0 * (i * j) as f64 - (j * j) as f64;
+        let values = Array3::from_shape_fn((4, 5, StateVar::COUNT), |(i, j, _)| node(i, j));
+        let table = VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, values)
+            .unwrap()
+            .with_method(InterpolationMethod::Linear);
+
+        // cell (1, 2), at 30% of the way along log E and 75% along log V
+        let (loge, logv) = (10.65, 6.5);
+        let (tx, ty) = (0.3, 0.75);
+        let expected = (1.0 - tx) * (1.0 - ty) * node(1, 2)
+            + tx * (1.0 - ty) * node(2, 2)
+            + (1.0 - tx) * ty * node(1, 3)
+            + tx * ty * node(2, 3);
+        let value = table.at(loge, logv, StateVar::LogPressure).unwrap();
+        assert!(value.is_close(expected));
+        // the bicubic spline differs on this non-bilinear table
+        let cubic = table.clone().with_method(InterpolationMethod::Cubic);
+        let spline = cubic.at(loge, logv, StateVar::LogPressure).unwrap();
+        assert!((spline - expected).abs() > 1e-3);
+        // bilinear lookups reach the outer cells of the table
+        let corner = 0.25 * (node(0, 0) + node(1, 0) + node(0, 1) + node(1, 1));
+        let value = table.at(10.25, 2.0, StateVar::LogPressure).unwrap();
+        assert!(value.is_close(corner));
+    }
+
+    #[test]
+    fn spline_valid_ranges() {
+        let values = Array3::from_elem((6, 3, StateVar::COUNT), 1.0);
+        let table = VolumeEnergyTable::from_parts(
+            0.02,
+            0.7,
+            Range::new(10.0, 1.0, 6),
+            Range::new(0.0, 1.0, 3),
+            values,
+        )
+        .unwrap();
+        assert_eq!(table.spline_valid_ranges(), (Some((11.0, 14.0)), None));
+
+        let values = Array3::from_elem((6, 5, StateVar::COUNT), 1.0);
+        let table = VolumeEnergyTable::from_parts(
+            0.02,
+            0.7,
+            Range::new(10.0, 1.0, 6),
+            Range::new(0.0, 1.0, 5),
+            values,
+        )
+        .unwrap();
+        let (Some((loge_min, loge_max)), Some((logv_min, logv_max))) = table.spline_valid_ranges()
+        else {
+            panic!("both axes have enough nodes for splines");
+        };
+        let var = StateVar::LogPressure;
+        assert!(table.at(loge_min, logv_min, var).is_ok());
+        assert!(table.at(loge_max, logv_max, var).is_ok());
+        assert!(table.at(loge_min - 1e-6, logv_min, var).is_err());
+        assert!(table.at(loge_max, logv_max + 1e-6, var).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn iterate_over_grid() {
+        let all = AllTables::default();
+        let n_tables: usize = all.tables.iter().map(|t| t.tables.len()).sum();
+        assert_eq!(all.iter_tables().count(), n_tables);
+        let mut previous = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for table in all.iter_tables() {
+            let compo = (table.metallicity(), table.h_frac());
+            assert!(compo > previous);
+            previous = compo;
+            let node = all.exact_table(compo.0, compo.1).unwrap();
+            assert!(std::ptr::eq(node, table));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn borrowed_he_frac() {
+        let tables = AllTables::default().take_at_metallicity(0.02).unwrap();
+        for he_frac in [0.28, 0.3] {
+            let borrowed = tables.at_he_frac(he_frac).unwrap();
+            let taken = tables.clone().take_at_he_frac(he_frac).unwrap();
+            assert!(borrowed.h_frac().is_close(taken.h_frac()));
+            assert!(borrowed
+                .values
+                .iter()
+                .zip(taken.values.iter())
+                .all(|(&b, &t)| (b - t).abs() < 1e-12 || b.is_nan() && t.is_nan()));
+        }
+        let err = tables.at_he_frac(1.5).err().unwrap();
+        assert_eq!(err.axis(), Some("h_frac"));
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-tables")]
+    fn metal_free_tables() {
+        // the tables at Z=0 are every 0.2 in hydrogen fraction, X=0.4 is a
+        // node as are log_energy=15 and log_volume=5
+        let (loge, logv, var) = (15.0, 5.0, StateVar::LogPressure);
+        let all = AllTables::default();
+        let at = |metallicity| all.at(metallicity, 0.4, loge, logv, var).unwrap();
+        let on_node = |metallicity| {
+            AllTables::default()
+                .take_at_metallicity(metallicity)
+                .unwrap()
+                .take_at_h_frac(0.4)
+                .unwrap()
+                .at(loge, logv, var)
+                .unwrap()
+        };
+        assert!(at(0.0).is_close(on_node(0.0)));
+        assert!(at(0.01).is_close(0.5 * (on_node(0.0) + on_node(0.02))));
+    }
+
+    #[test]
+    fn stencil_cache_quantizes_coordinates() {
+        let log_energy = Range::from_slice(&[10.0, 11.0, 12.0, 13.0, 14.0]).unwrap();
+        let log_volume = Range::from_slice(&[0.0, 2.0, 4.0, 6.0]).unwrap();
+        let values = Array3::from_shape_fn((5, 4, StateVar::COUNT), |(i, j, _)| {
+            let (loge, logv) = (log_energy.at(i), log_volume.at(j));
+            loge * loge * loge - logv * logv
+        });
+        let table =
+            VolumeEnergyTable::from_parts(0.02, 0.7, log_energy, log_volume, values).unwrap();
+        let var = [StateVar::LogPressure];
+        let mut cache = StencilCache::default();
+        table.at_many_cached(12.3, 3.0, var, &mut cache).unwrap();
+        // within the quantum, the stencil nodes are reused at the new position
+        let (loge, logv) = (12.3 + 1e-11, 3.0 - 1e-11);
+        assert_eq!(
+            table.at_many_cached(loge, logv, var, &mut cache).unwrap(),
+            table.at_many(loge, logv, var).unwrap()
+        );
+        assert_eq!(cache.log_energy.len(), 1);
+        assert_eq!(cache.log_volume.len(), 1);
+        assert!(table
+            .at_many_cached(f64::NAN, logv, var, &mut cache)
+            .is_err());
+
+        // queries sharing the key of the edge of the domain are still checked
+        table.at_many_cached(11.0, logv, var, &mut cache).unwrap();
+        let loge = 11.0 - 4e-11;
+        assert_eq!(StencilCache::key(loge), StencilCache::key(11.0));
+        assert!(table.at_many(loge, logv, var).is_err());
+        assert!(table.at_many_cached(loge, logv, var, &mut cache).is_err());
+    }
 }
@@ -0,0 +1,25 @@
+//! Float functions that are not in `core`, taken from `libm` without `std`.
+
+#[cfg(feature = "std")]
+#[inline]
+pub(crate) fn floor(x: f64) -> f64 {
+    x.floor()
+}
+
+#[cfg(not(feature = "std"))]
+#[inline]
+pub(crate) fn floor(x: f64) -> f64 {
+    libm::floor(x)
+}
+
+#[cfg(feature = "std")]
+#[inline]
+pub(crate) fn round(x: f64) -> f64 {
+    x.round()
+}
+
+#[cfg(not(feature = "std"))]
+#[inline]
+pub(crate) fn round(x: f64) -> f64 {
+    libm::round(x)
+}
@@ -1,16 +1,28 @@
-use std::io::{self, Read};
+//! Fortran unformatted sequential records, as used in raw table files.
+//!
+//! Each record is framed by its size in bytes (as a little-endian `u32`)
+//! before and after the payload.
+
+use std::io::{self, Read, Write};
+
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 mod private {
     pub trait Sealed {}
     impl Sealed for u32 {}
+    impl Sealed for f32 {}
     impl Sealed for f64 {}
 }
 
-pub(crate) trait FromRawBinary: private::Sealed {
+/// Types that can be stored in records, this trait is sealed.
+pub trait FromRawBinary: private::Sealed {
     fn read_in<R: Read>(reader: R) -> io::Result<Self>
     where
         Self: Sized;
 
+    fn write_out<W: Write>(&self, writer: W) -> io::Result<()>;
+
     fn read_size() -> usize;
 }
 
@@ -22,6 +34,30 @@ impl FromRawBinary for u32 {
         Ok(Self::from_le_bytes(buf))
     }
 
+    #[inline]
+    fn write_out<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.to_le_bytes())
+    }
+
+    #[inline(always)]
+    fn read_size() -> usize {
+        std::mem::size_of::<Self>()
+    }
+}
+
+impl FromRawBinary for f32 {
+    #[inline]
+    fn read_in<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut buf = [0u8; std::mem::size_of::<Self>()];
+        reader.read_exact(&mut buf)?;
+        Ok(Self::from_le_bytes(buf))
+    }
+
+    #[inline]
+    fn write_out<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.to_le_bytes())
+    }
+
     #[inline(always)]
     fn read_size() -> usize {
         std::mem::size_of::<Self>()
@@ -36,13 +72,19 @@ impl FromRawBinary for f64 {
         Ok(Self::from_le_bytes(buf))
     }
 
+    #[inline]
+    fn write_out<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.to_le_bytes())
+    }
+
     #[inline(always)]
     fn read_size() -> usize {
         std::mem::size_of::<Self>()
     }
 }
 
-pub(crate) fn read_fort_record<R: Read, T: FromRawBinary>(
+/// Read a record filling exactly `buffer`.
+pub fn read_fort_record<R: Read, T: FromRawBinary>(
     mut reader: R,
     buffer: &mut [T],
 ) -> io::Result<()> {
@@ -57,6 +99,38 @@ pub(crate) fn read_fort_record<R: Read, T: FromRawBinary>(
     for elt in buffer.iter_mut() {
         *elt = FromRawBinary::read_in(&mut reader)?;
     }
+    check_record_end(reader, pre_size)
+}
+
+/// Read a record of either `f64` or `f32` filling exactly `buffer`.
+///
+/// The precision is inferred from the size of the record, `f32` values are
+/// widened to `f64`.
+pub fn read_fort_record_widened<R: Read>(mut reader: R, buffer: &mut [f64]) -> io::Result<()> {
+    let pre_size: u32 = FromRawBinary::read_in(&mut reader)?;
+    let size = pre_size as usize;
+    if size == buffer.len() * <f64 as FromRawBinary>::read_size() {
+        for elt in buffer.iter_mut() {
+            *elt = FromRawBinary::read_in(&mut reader)?;
+        }
+    } else if size == buffer.len() * <f32 as FromRawBinary>::read_size() {
+        for elt in buffer.iter_mut() {
+            let single: f32 = FromRawBinary::read_in(&mut reader)?;
+            *elt = single.into();
+        }
+    } else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "requested {} floats but next record has {pre_size} bytes",
+                buffer.len()
+            ),
+        ));
+    }
+    check_record_end(reader, pre_size)
+}
+
+fn check_record_end<R: Read>(mut reader: R, pre_size: u32) -> io::Result<()> {
     let post_size: u32 = FromRawBinary::read_in(&mut reader)?;
     if post_size != pre_size {
         return Err(io::Error::new(
@@ -67,9 +141,35 @@ pub(crate) fn read_fort_record<R: Read, T: FromRawBinary>(
     Ok(())
 }
 
+/// Read the next record from an async reader, including its framing.
+///
+/// The returned bytes can then be decoded with [`read_fort_record`] or
+/// [`read_fort_record_widened`], which check the end of the record.
+#[cfg(feature = "async")]
+pub async fn read_raw_record_async<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut pre_size = [0_u8; 4];
+    reader.read_exact(&mut pre_size).await?;
+    let size = u32::from_le_bytes(pre_size) as usize;
+    let mut record = vec![0_u8; size + 8];
+    record[..4].copy_from_slice(&pre_size);
+    reader.read_exact(&mut record[4..]).await?;
+    Ok(record)
+}
+
+/// Write `data` as a single record.
+pub fn write_fort_record<W: Write, T: FromRawBinary>(mut writer: W, data: &[T]) -> io::Result<()> {
+    let size = u32::try_from(data.len() * <T as FromRawBinary>::read_size())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    size.write_out(&mut writer)?;
+    for elt in data {
+        elt.write_out(&mut writer)?;
+    }
+    size.write_out(&mut writer)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::read_fort_record;
+    use super::{read_fort_record, read_fort_record_widened, write_fort_record};
 
     #[test]
     fn read_3_u32() {
@@ -94,4 +194,28 @@ mod tests {
         read_fort_record(raw_record.as_slice(), &mut buf).expect("record well formed");
         assert_eq!(buf, [1.0, std::f64::consts::PI]);
     }
+
+    #[test]
+    fn write_read_roundtrip() {
+        let mut raw_record = Vec::new();
+        write_fort_record(&mut raw_record, &[1.5_f64, -2.0, 1e20]).expect("write to vec");
+        assert_eq!(raw_record.len(), 3 * 8 + 2 * 4);
+        let mut buf = [0_f64; 3];
+        read_fort_record(raw_record.as_slice(), &mut buf).expect("record well formed");
+        assert_eq!(buf, [1.5, -2.0, 1e20]);
+    }
+
+    #[test]
+    fn read_widened_f32() {
+        let mut raw_record = Vec::new();
+        write_fort_record(&mut raw_record, &[1.5_f32, -2.0, 0.1]).expect("write to vec");
+        write_fort_record(&mut raw_record, &[0.1_f64, 3.0, 1e20]).expect("write to vec");
+        let mut reader = raw_record.as_slice();
+        let mut buf = [0_f64; 3];
+        read_fort_record_widened(&mut reader, &mut buf).expect("f32 record");
+        assert_eq!(buf, [1.5, -2.0, f64::from(0.1_f32)]);
+        read_fort_record_widened(&mut reader, &mut buf).expect("f64 record");
+        assert_eq!(buf, [0.1, 3.0, 1e20]);
+        assert!(read_fort_record_widened(raw_record.as_slice(), &mut [0.0; 2]).is_err());
+    }
 }
@@ -0,0 +1,264 @@
+//! Interpolation on generic 2D grids.
+
+use ndarray::{Array2, ArrayView2};
+use thiserror::Error;
+
+use crate::{
+    index::{LinearInterpolable, OutOfBoundsError, Range},
+    interp::{
+        check_batch, cubic_spline_2d, cubic_spline_2d_batch, lin_interp_2d, lin_interp_2d_batch,
+    },
+};
+
+/// Intervals of the two axes of a table in which cubic interpolation is
+/// possible, see [`Range::spline_valid_range`].
+pub type SplineValidRanges = (Option<(f64, f64)>, Option<(f64, f64)>);
+
+/// How values are looked up in tables.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LookupMode {
+    /// Interpolate between the nodes surrounding the query.
+    #[default]
+    Interpolated,
+    /// Value at the closest node, without any interpolation.
+    Nearest,
+}
+
+/// Interpolation method used by [`at`](crate::eos_tables::VolumeEnergyTable::at)
+/// lookups in tables.
+///
+/// EOS tables default to [`InterpolationMethod::Cubic`] and opacity tables
+/// to [`InterpolationMethod::Linear`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InterpolationMethod {
+    /// Value at the closest node.
+    Nearest,
+    /// Bilinear interpolation, this reproduces tools interpolating MESA
+    /// tables linearly in (log E, log V).
+    Linear,
+    /// Bicubic spline interpolation, only possible away from the first and
+    /// last nodes of each axis.
+    Cubic,
+}
+
+/// What lookups do when the query lies outside of a table.
+///
+/// The same policy can be given to EOS and opacity lookups, see e.g.
+/// [`CstCompoOpacity::with_oob_policy`](crate::opacity::CstCompoOpacity::with_oob_policy)
+/// to apply it along the whole computation of opacities.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OobPolicy {
+    /// Report an [`OutOfBoundsError`].
+    #[default]
+    Error,
+    /// Move the query to the closest point at which the table can be
+    /// interpolated. NaN queries are still reported as errors.
+    Clamp,
+    /// Return NaN.
+    Nan,
+}
+
+impl OobPolicy {
+    /// Run `lookup` at `query` according to the policy, `clamp` moving the
+    /// query inside the table.
+    ///
+    /// Any failure of `lookup` is taken as the query being out of the table,
+    /// other errors (e.g. a state variable missing from the table) should be
+    /// checked beforehand.
+    pub(crate) fn apply<Q>(
+        self,
+        query: Q,
+        clamp: impl FnOnce(Q) -> Q,
+        lookup: impl FnOnce(Q) -> Result<f64, OutOfBoundsError>,
+    ) -> Result<f64, OutOfBoundsError> {
+        self.apply_or(query, clamp, lookup, f64::NAN)
+    }
+
+    /// Same as [`OobPolicy::apply`] for lookups of several values, `nan`
+    /// being the result out of the table with [`OobPolicy::Nan`].
+    pub(crate) fn apply_or<Q, T>(
+        self,
+        query: Q,
+        clamp: impl FnOnce(Q) -> Q,
+        lookup: impl FnOnce(Q) -> Result<T, OutOfBoundsError>,
+        nan: T,
+    ) -> Result<T, OutOfBoundsError> {
+        match self {
+            OobPolicy::Error => lookup(query),
+            OobPolicy::Clamp => lookup(clamp(query)),
+            OobPolicy::Nan => Ok(lookup(query).unwrap_or(nan)),
+        }
+    }
+}
+
+/// Settings of the cubic spline interpolation.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SplineConfig {
+    /// Tension of the cardinal spline, the slopes at the nodes are scaled by
+    /// `1 - tension`. The default 0 is a Catmull-Rom spline, higher values
+    /// flatten the interpolant at the nodes and 1 gives zero slopes there.
+    pub tension: f64,
+}
+
+#[derive(Error, Debug)]
+#[error("values of shape {found:?} don't match the lengths of the axes {expected:?}")]
+pub struct ShapeError {
+    expected: [usize; 2],
+    found: [usize; 2],
+}
+
+/// Values tabulated on a 2D grid with linear axes.
+///
+/// ```
+/// use music_mesa_tables::{grid::Grid2D, index::Range};
+/// use ndarray::Array2;
+///
+/// let x = Range::from_slice(&[0.0, 1.0, 2.0, 3.0]).unwrap();
+/// let y = Range::from_slice(&[0.0, 0.5, 1.0, 1.5, 2.0]).unwrap();
+/// let values = Array2::from_shape_fn((4, 5), |(i, j)| i as f64 + 2.0 * j as f64);
+/// let grid = Grid2D::new(x, y, values).unwrap();
+/// assert!((grid.at(1.5, 0.25).unwrap() - 2.5).abs() < 1e-12);
+/// assert!((grid.at_cubic(1.5, 0.75).unwrap() - 4.5).abs() < 1e-12);
+/// assert!(grid.at(3.5, 0.0).is_err());
+/// ```
+#[derive(Clone)]
+pub struct Grid2D {
+    x: Range,
+    y: Range,
+    values: Array2<f64>,
+    names: (&'static str, &'static str),
+    spline: SplineConfig,
+}
+
+impl Grid2D {
+    /// Build a grid, `values` should be indexed by `x` and `y`.
+    ///
+    /// ```
+    /// use music_mesa_tables::{grid::Grid2D, index::Range};
+    /// use ndarray::Array2;
+    ///
+    /// let x = Range::from_slice(&[0.0, 1.0, 2.0]).unwrap();
+    /// let y = Range::from_slice(&[0.0, 1.0]).unwrap();
+    /// assert!(Grid2D::new(x, y, Array2::zeros((2, 3))).is_err());
+    /// ```
+    pub fn new(x: Range, y: Range, values: Array2<f64>) -> Result<Self, ShapeError> {
+        let expected = [x.n_values(), y.n_values()];
+        let (n_x, n_y) = values.dim();
+        if expected != [n_x, n_y] {
+            return Err(ShapeError {
+                expected,
+                found: [n_x, n_y],
+            });
+        }
+        Ok(Self {
+            x,
+            y,
+            values,
+            names: ("x", "y"),
+            spline: SplineConfig::default(),
+        })
+    }
+
+    /// Name the axes, these names label out of bounds errors.
+    pub fn with_axis_names(self, x: &'static str, y: &'static str) -> Self {
+        Self {
+            names: (x, y),
+            ..self
+        }
+    }
+
+    /// Use `spline` for bicubic interpolation.
+    pub fn with_spline_config(self, spline: SplineConfig) -> Self {
+        Self { spline, ..self }
+    }
+
+    pub fn spline_config(&self) -> SplineConfig {
+        self.spline
+    }
+
+    pub fn x(&self) -> Range {
+        self.x
+    }
+
+    pub fn y(&self) -> Range {
+        self.y
+    }
+
+    pub fn values(&self) -> ArrayView2<'_, f64> {
+        self.values.view()
+    }
+
+    /// Bilinear interpolation at `(x, y)`.
+    pub fn at(&self, x: f64, y: f64) -> Result<f64, OutOfBoundsError> {
+        let x_st = self.x.linear_stencil(x).map_err(|e| e.on(self.names.0))?;
+        let y_st = self.y.linear_stencil(y).map_err(|e| e.on(self.names.1))?;
+        Ok(lin_interp_2d(x_st, y_st, self.values()))
+    }
+
+    /// Bicubic spline interpolation at `(x, y)`, this is only possible away
+    /// from the first and last nodes of each axis.
+    pub fn at_cubic(&self, x: f64, y: f64) -> Result<f64, OutOfBoundsError> {
+        let tension = self.spline.tension;
+        let x_st = self.x.spline_stencil(x).map_err(|e| e.on(self.names.0))?;
+        let y_st = self.y.spline_stencil(y).map_err(|e| e.on(self.names.1))?;
+        Ok(cubic_spline_2d(
+            x_st.with_tension(tension),
+            y_st.with_tension(tension),
+            self.values(),
+        ))
+    }
+
+    /// Look up the value at `(x, y)`, [`LookupMode::Interpolated`] is the
+    /// same as [`Grid2D::at`].
+    pub fn lookup(&self, x: f64, y: f64, mode: LookupMode) -> Result<f64, OutOfBoundsError> {
+        match mode {
+            LookupMode::Interpolated => self.at(x, y),
+            LookupMode::Nearest => {
+                let ix = self.x.nearest_index(x).map_err(|e| e.on(self.names.0))?;
+                let iy = self.y.nearest_index(y).map_err(|e| e.on(self.names.1))?;
+                Ok(self.values[[ix, iy]])
+            }
+        }
+    }
+
+    /// Bilinear interpolation at each `(xs[i], ys[i])`.
+    ///
+    /// Bounds are checked once for the whole batch, and no stencil is built,
+    /// which is faster than calling [`Grid2D::at`] on each point for large
+    /// batches. However, the output is collected in memory and a single point
+    /// out of the grid makes the whole batch fail.
+    ///
+    /// # Panics
+    ///
+    /// If `xs` and `ys` don't have the same length.
+    pub fn at_batch(&self, xs: &[f64], ys: &[f64]) -> Result<Vec<f64>, OutOfBoundsError> {
+        assert_eq!(xs.len(), ys.len());
+        check_batch(xs, |v| self.x.idx_lin(v).map_err(|e| e.on(self.names.0)))?;
+        check_batch(ys, |v| self.y.idx_lin(v).map_err(|e| e.on(self.names.1)))?;
+        Ok(lin_interp_2d_batch(self.x, self.y, xs, ys, self.values()))
+    }
+
+    /// Same as [`Grid2D::at_batch`] with bicubic spline interpolation, see
+    /// [`Grid2D::at_cubic`].
+    ///
+    /// # Panics
+    ///
+    /// If `xs` and `ys` don't have the same length.
+    pub fn at_cubic_batch(&self, xs: &[f64], ys: &[f64]) -> Result<Vec<f64>, OutOfBoundsError> {
+        assert_eq!(xs.len(), ys.len());
+        check_batch(xs, |v| {
+            self.x.spline_stencil(v).map_err(|e| e.on(self.names.0))
+        })?;
+        check_batch(ys, |v| {
+            self.y.spline_stencil(v).map_err(|e| e.on(self.names.1))
+        })?;
+        Ok(cubic_spline_2d_batch(
+            self.x,
+            self.y,
+            xs,
+            ys,
+            self.values(),
+            self.spline.tension,
+        ))
+    }
+}
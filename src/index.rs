@@ -1,8 +1,16 @@
+use alloc::{format, string::String, vec::Vec};
+#[cfg(feature = "std")]
+use std::io;
+
+#[cfg(feature = "ndarray")]
+use ndarray::{Array1, ArrayView1};
+use thiserror::Error;
+
 use crate::{
+    float,
     interp::{LinearInterpolator, LinearStencil, SplineStencil},
     is_close::IsClose,
 };
-use thiserror::Error;
 
 #[derive(Copy, Clone)]
 pub struct Range {
@@ -13,6 +21,33 @@ pub struct Range {
 
 pub struct CustomRange(Vec<f64>);
 
+/// Overlap of two ranges, see [`Range::subrange_in`].
+#[derive(Copy, Clone)]
+pub struct RangeOverlap {
+    /// Nodes of the first range that lie within the second one.
+    pub range: Range,
+    /// Index of the first node of `range` in the first range.
+    pub self_offset: usize,
+    /// Index of the node of the second range at or right below the first
+    /// node of `range`, this is the index of that node when both ranges share
+    /// their nodes.
+    pub other_offset: usize,
+}
+
+/// Treatment of the outer cells of a [`Range`] when building spline stencils.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SplineBoundary {
+    /// Stencils are only built in cells that have a neighbouring node on
+    /// both sides, the outer cells of the range are out of bounds.
+    #[default]
+    Inner,
+    /// The boundary node is duplicated to complete the stencil in the outer
+    /// cells, which is equivalent to reflecting the grid about the boundary
+    /// node with values extrapolated linearly.  The slope at the boundary
+    /// node is then the one-sided difference with its neighbour.
+    Clamped,
+}
+
 #[derive(Error, Debug)]
 pub enum RangeError {
     #[error("range should have at least two elements")]
@@ -23,17 +58,140 @@ pub enum RangeError {
     NotLinear,
 }
 
-#[derive(Error, Debug)]
-#[error("value {value} is out of bounds")]
+#[derive(Error, Debug, Clone)]
+#[error(
+    "value {value} is out of bounds [{}, {}]{}{}{}",
+    .bounds.0,
+    .bounds.1,
+    on_axis(.axis),
+    state_hint(.state),
+    nearest_hint(.nearest_point)
+)]
 pub struct OutOfBoundsError {
     value: f64,
+    bounds: (f64, f64),
+    axis: Option<&'static str>,
+    nearest_point: Option<(f64, f64)>,
+    state: Option<(f64, f64)>,
+}
+
+fn on_axis(axis: &Option<&'static str>) -> String {
+    axis.map(|name| format!(" on {name} axis"))
+        .unwrap_or_default()
+}
+
+fn state_hint(state: &Option<(f64, f64)>) -> String {
+    state
+        .map(|(logd, logt)| format!(" for log_density {logd} and log_temperature {logt}"))
+        .unwrap_or_default()
+}
+
+fn nearest_hint(point: &Option<(f64, f64)>) -> String {
+    point
+        .map(|(x, y)| format!(", nearest valid point is ({x}, {y})"))
+        .unwrap_or_default()
+}
+
+impl OutOfBoundsError {
+    pub(crate) fn new(value: f64, bounds: (f64, f64)) -> Self {
+        Self {
+            value,
+            bounds,
+            axis: None,
+            nearest_point: None,
+            state: None,
+        }
+    }
+
+    /// Label the axis on which the error occurred.
+    #[cfg_attr(not(feature = "ndarray"), allow(dead_code))]
+    pub(crate) fn on(self, axis: &'static str) -> Self {
+        Self {
+            axis: Some(axis),
+            ..self
+        }
+    }
+
+    /// Record the closest valid point of the query that failed.
+    #[cfg_attr(not(feature = "ndarray"), allow(dead_code))]
+    pub(crate) fn with_nearest_point(self, point: (f64, f64)) -> Self {
+        Self {
+            nearest_point: Some(point),
+            ..self
+        }
+    }
+
+    /// Record the state from which the requested value was computed, e.g.
+    /// when looking up opacities at the `log_r` of that state.
+    #[cfg_attr(not(feature = "ndarray"), allow(dead_code))]
+    pub(crate) fn with_state(self, log_density: f64, log_temperature: f64) -> Self {
+        Self {
+            state: Some((log_density, log_temperature)),
+            ..self
+        }
+    }
+
+    /// The requested value.
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// The valid interval for the requested value.
+    pub fn bounds(&self) -> (f64, f64) {
+        self.bounds
+    }
+
+    /// Name of the axis on which the error occurred, if known.
+    pub fn axis(&self) -> Option<&'static str> {
+        self.axis
+    }
+
+    /// Closest valid point of the two-dimensional query that failed, if
+    /// known, with coordinates in the order of the arguments of the query
+    /// (e.g. `(log_energy, log_volume)` for EOS tables).
+    pub fn nearest_point(&self) -> Option<(f64, f64)> {
+        self.nearest_point
+    }
+
+    /// `(log_density, log_temperature)` of the state from which the
+    /// requested value was computed, if any.
+    pub fn state(&self) -> Option<(f64, f64)> {
+        self.state
+    }
+}
+
+/// Invalid axes found while decoding tables.
+#[cfg(feature = "std")]
+impl From<RangeError> for io::Error {
+    fn from(err: RangeError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
 }
 
+/// Values found outside of the tables while decoding them.
+#[cfg(feature = "std")]
+impl From<OutOfBoundsError> for io::Error {
+    fn from(err: OutOfBoundsError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+#[derive(Clone, Copy)]
 pub enum IdxLin {
     Exact(usize),
     Between(usize, usize),
 }
 
+impl IdxLin {
+    /// Indices of the nodes bracketing the value, equal on exact nodes.
+    pub fn bracket(self) -> (usize, usize) {
+        match self {
+            IdxLin::Exact(i) => (i, i),
+            IdxLin::Between(i, j) => (i, j),
+        }
+    }
+}
+
 pub trait Indexable {
     fn get(&self, index: usize) -> Option<f64>;
 
@@ -58,6 +216,35 @@ pub trait LinearInterpolable: Indexable {
     }
 }
 
+/// Metallicity axis of table collections, which might hold a single
+/// metallicity (e.g. in trimmed builds).
+#[cfg(feature = "ndarray")]
+pub(crate) enum MetalAxis<R> {
+    Single(f64),
+    Grid(R),
+}
+
+#[cfg(feature = "ndarray")]
+impl<R: Indexable> Indexable for MetalAxis<R> {
+    fn get(&self, index: usize) -> Option<f64> {
+        match self {
+            MetalAxis::Single(value) => (index == 0).then_some(*value),
+            MetalAxis::Grid(range) => range.get(index),
+        }
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl<R: LinearInterpolable> LinearInterpolable for MetalAxis<R> {
+    fn idx_lin(&self, value: f64) -> Result<IdxLin, OutOfBoundsError> {
+        match self {
+            MetalAxis::Single(single) if value.is_close(*single) => Ok(IdxLin::Exact(0)),
+            MetalAxis::Single(single) => Err(OutOfBoundsError::new(value, (*single, *single))),
+            MetalAxis::Grid(range) => range.idx_lin(value),
+        }
+    }
+}
+
 pub struct RangeIterator {
     range: Range,
     idx: usize,
@@ -89,7 +276,31 @@ impl IntoIterator for Range {
     }
 }
 
+/// Same as [`Range::from_slice`], non-contiguous views are copied first.
+#[cfg(feature = "ndarray")]
+impl TryFrom<ArrayView1<'_, f64>> for Range {
+    type Error = RangeError;
+
+    fn try_from(values: ArrayView1<'_, f64>) -> Result<Self, Self::Error> {
+        match values.as_slice() {
+            Some(slc) => Self::from_slice(slc),
+            None => Self::from_slice(&values.to_vec()),
+        }
+    }
+}
+
+/// Same as [`Range::from_slice`].
+#[cfg(feature = "ndarray")]
+impl TryFrom<Array1<f64>> for Range {
+    type Error = RangeError;
+
+    fn try_from(values: Array1<f64>) -> Result<Self, Self::Error> {
+        Self::try_from(values.view())
+    }
+}
+
 impl Range {
+    #[cfg_attr(not(feature = "embedded-tables"), allow(dead_code))]
     pub(crate) const fn new(first: f64, step: f64, n_values: usize) -> Self {
         // FP arithmetic not available in const fn yet, keeping this function
         // in the crate to avoid misuses.
@@ -102,6 +313,28 @@ impl Range {
         }
     }
 
+    /// Range of `n_values` evenly spaced values from `first` to `last`.
+    ///
+    /// ```
+    /// # use music_mesa_tables::index::Range;
+    /// let range = Range::linspace(0.0, 1.0, 5).unwrap();
+    /// assert_eq!(range.describe(), (0.0, 1.0, 0.25, 5));
+    /// assert!(Range::linspace(1.0, 0.0, 5).is_err());
+    /// ```
+    pub fn linspace(first: f64, last: f64, n_values: usize) -> Result<Self, RangeError> {
+        if n_values < 2 {
+            return Err(RangeError::FewerThanTwoValues);
+        }
+        if first.is_nan() || last.is_nan() || last <= first {
+            return Err(RangeError::NotInIncreasingOrder);
+        }
+        Ok(Self {
+            first,
+            step: (last - first) / (n_values - 1) as f64,
+            n_values,
+        })
+    }
+
     pub fn from_slice(slc: &[f64]) -> Result<Self, RangeError> {
         let n_values = slc.len();
         if n_values < 2 {
@@ -127,12 +360,55 @@ impl Range {
         Ok(range)
     }
 
+    /// Whether `value` lies in `[first, last]`, or is within rounding errors
+    /// of either end. This is false for NaN.
     pub fn contains(&self, value: f64) -> bool {
-        let last = self.last();
-        (value >= self.first && value <= last) || value.is_close(self.first) || value.is_close(last)
+        value.is_close(self.clamp(value))
     }
 
-    pub fn subrange_in(&self, other: Range) -> Option<Range> {
+    /// Range made of the nodes of this range followed by those of `next`.
+    ///
+    /// This requires both ranges to have the same step and `next` to start
+    /// one step after the end of `self` (up to rounding errors), `None` is
+    /// returned otherwise.
+    ///
+    /// ```
+    /// # use music_mesa_tables::index::Range;
+    /// let low = Range::from_slice(&[0.0, 0.5, 1.0]).unwrap();
+    /// let high = Range::from_slice(&[1.5, 2.0]).unwrap();
+    /// let full = low.try_concat(high).unwrap();
+    /// assert_eq!(full.describe(), (0.0, 2.0, 0.5, 5));
+    /// assert!(high.try_concat(low).is_none());
+    /// ```
+    pub fn try_concat(&self, next: Range) -> Option<Range> {
+        let contiguous = next.first.is_close(self.last() + self.step);
+        if !contiguous || !next.step.is_close(self.step) {
+            return None;
+        }
+        Some(Self {
+            first: self.first,
+            step: self.step,
+            n_values: self.n_values + next.n_values,
+        })
+    }
+
+    /// Overlap of this range with `other`.
+    ///
+    /// The overlap is made of the nodes of `self` that lie within
+    /// `[other.first(), other.last()]` (up to rounding errors), it therefore
+    /// has the step of `self` and its nodes need not be nodes of `other`.
+    /// Returns `None` if fewer than two nodes of `self` are in `other`, as a
+    /// [`Range`] needs at least two values.
+    ///
+    /// ```
+    /// # use music_mesa_tables::index::Range;
+    /// let coarse = Range::from_slice(&[0.0, 0.2, 0.4, 0.6, 0.8]).unwrap();
+    /// let fine = Range::from_slice(&[0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9]).unwrap();
+    /// let overlap = coarse.subrange_in(fine).unwrap();
+    /// assert_eq!(overlap.range.describe(), (0.4, 0.8, 0.2, 3));
+    /// assert_eq!((overlap.self_offset, overlap.other_offset), (2, 1));
+    /// ```
+    pub fn subrange_in(&self, other: Range) -> Option<RangeOverlap> {
         let (ifirst, first) = self
             .into_iter()
             .enumerate()
@@ -142,10 +418,16 @@ impl Range {
                 .filter(|&i| other.contains(self.at(i)))
                 .count();
         if n_values >= 2 {
-            Some(Self {
-                first,
-                n_values,
-                step: self.step,
+            let other_offset =
+                float::floor(other.fractional_index(first) + 1e-12).max(0.0) as usize;
+            Some(RangeOverlap {
+                range: Self {
+                    first,
+                    n_values,
+                    step: self.step,
+                },
+                self_offset: ifirst,
+                other_offset: other_offset.min(other.n_values - 1),
             })
         } else {
             None
@@ -168,17 +450,148 @@ impl Range {
         self.n_values
     }
 
+    /// Summary of the range as `(first, last, step, n_values)`, handy to check
+    /// the axes inferred when loading tables.
+    pub fn describe(&self) -> (f64, f64, f64, usize) {
+        (self.first, self.last(), self.step, self.n_values)
+    }
+
+    /// Position along the range and value of the maximum of `values`, which
+    /// are sampled on the nodes of the range.
+    ///
+    /// The discrete maximum is refined with the parabola through it and its
+    /// two neighbours, maxima on the first or last node are not refined. NaN
+    /// values are ignored, `None` is returned if all values are NaN.
+    ///
+    /// ```
+    /// # use music_mesa_tables::index::Range;
+    /// let range = Range::linspace(0.0, 4.0, 5).unwrap();
+    /// let values: Vec<_> = range.into_iter().map(|x| -(x - 1.3) * (x - 1.3)).collect();
+    /// let (at, max) = range.argmax(&values).unwrap();
+    /// assert!((at - 1.3).abs() < 1e-12 && max.abs() < 1e-12);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `values` doesn't have as many elements as the range.
+    pub fn argmax(&self, values: &[f64]) -> Option<(f64, f64)> {
+        assert_eq!(values.len(), self.n_values);
+        let (i, &max) = values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| !v.is_nan())
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        if i == 0 || i == self.n_values - 1 {
+            return Some((self.at(i), max));
+        }
+        let (left, right) = (values[i - 1], values[i + 1]);
+        let curvature = left - 2.0 * max + right;
+        if curvature.is_nan() || curvature >= 0.0 {
+            // flat neighbourhood, or a NaN neighbour
+            return Some((self.at(i), max));
+        }
+        let offset = (0.5 * (left - right) / curvature).clamp(-0.5, 0.5);
+        let value = max - 0.25 * (left - right) * offset;
+        Some((self.at(i) + offset * self.step, value))
+    }
+
+    /// Same as [`Range::argmax`], for the minimum of `values`.
+    pub fn argmin(&self, values: &[f64]) -> Option<(f64, f64)> {
+        let negated: Vec<_> = values.iter().map(|v| -v).collect();
+        self.argmax(&negated).map(|(at, min)| (at, -min))
+    }
+
+    /// Real-valued position of `value` in the range, `(value - first) / step`.
+    pub fn fractional_index(&self, value: f64) -> f64 {
+        (value - self.first) / self.step
+    }
+
+    /// Indices of the nodes bracketing `value`, `(i, i + 1)` inside a cell
+    /// and `(i, i)` on a node.
+    ///
+    /// This is [`idx_lin`](LinearInterpolable::idx_lin) without the
+    /// distinction between exact and interior values, see
+    /// [`IdxLin::bracket`].
+    ///
+    /// ```
+    /// # use music_mesa_tables::index::Range;
+    /// let range = Range::from_slice(&[0.0, 0.5, 1.0]).unwrap();
+    /// assert_eq!(range.bracket(0.7).unwrap(), (1, 2));
+    /// assert_eq!(range.bracket(0.5).unwrap(), (1, 1));
+    /// assert!(range.bracket(1.2).is_err());
+    /// ```
+    pub fn bracket(&self, value: f64) -> Result<(usize, usize), OutOfBoundsError> {
+        self.idx_lin(value).map(IdxLin::bracket)
+    }
+
+    /// Index of the node closest to `value`.
+    pub fn nearest_index(&self, value: f64) -> Result<usize, OutOfBoundsError> {
+        let last = self.last();
+        if !self.contains(value) {
+            return Err(OutOfBoundsError::new(value, (self.first, last)));
+        }
+        let index = float::round(self.fractional_index(value)).max(0.0) as usize;
+        Ok(index.min(self.n_values - 1))
+    }
+
+    /// Clamp `value` to `[first, last]`.
+    pub fn clamp(&self, value: f64) -> f64 {
+        value.clamp(self.first, self.last())
+    }
+
+    /// Subrange in which spline stencils can be built, if any.
+    pub(crate) fn spline_domain(&self) -> Option<Range> {
+        (self.n_values >= 4).then(|| Self {
+            first: self.at(1),
+            step: self.step,
+            n_values: self.n_values - 2,
+        })
+    }
+
+    /// Interval `(at(1), at(n - 2))` in which [`Range::spline_stencil`]
+    /// succeeds, `None` if the range has fewer than 4 values.
+    ///
+    /// ```
+    /// # use music_mesa_tables::index::Range;
+    /// let range = Range::linspace(0.0, 1.0, 5).unwrap();
+    /// assert_eq!(range.spline_valid_range(), Some((0.25, 0.75)));
+    /// assert!(range.spline_stencil(0.2).is_err());
+    /// assert_eq!(Range::linspace(0.0, 1.0, 3).unwrap().spline_valid_range(), None);
+    /// ```
+    pub fn spline_valid_range(&self) -> Option<(f64, f64)> {
+        self.spline_domain()
+            .map(|domain| (domain.first(), domain.last()))
+    }
+
     pub fn spline_stencil(&self, value: f64) -> Result<SplineStencil, OutOfBoundsError> {
+        self.spline_stencil_with(value, SplineBoundary::Inner)
+    }
+
+    /// Spline stencil at `value`, with outer cells handled as `boundary`.
+    pub fn spline_stencil_with(
+        &self,
+        value: f64,
+        boundary: SplineBoundary,
+    ) -> Result<SplineStencil, OutOfBoundsError> {
+        if boundary == SplineBoundary::Clamped {
+            return self.clamped_spline_stencil(value);
+        }
+        if self.n_values < 4 {
+            return Err(OutOfBoundsError::new(value, (self.first, self.last())));
+        }
         let lside = self.at(1);
         let rside = self.at(self.n_values - 2);
-        if self.n_values < 4 {
-            Err(OutOfBoundsError { value })
-        } else if value < lside || value >= rside {
-            Err(OutOfBoundsError { value })
+        // same tolerance on the edges of the domain as `idx_lin`
+        if !((lside..=rside).contains(&value) || value.is_close(lside) || value.is_close(rside)) {
+            Err(OutOfBoundsError::new(value, (lside, rside)))
         } else {
-            let iguess = ((value - self.first) / self.step).floor() as usize;
+            // rounding can put values on the first node of the domain in the
+            // cell before it, and the last node of the domain uses the same
+            // stencil as its cell
+            let iguess = (float::floor((value - self.first) / self.step) as usize)
+                .clamp(1, self.n_values - 3);
             Ok(SplineStencil {
-                ileft: iguess - 1,
+                nodes: [iguess - 1, iguess, iguess + 1, iguess + 2],
                 xs: [
                     self.at(iguess - 1),
                     self.at(iguess),
@@ -186,9 +599,29 @@ impl Range {
                     self.at(iguess + 2),
                 ],
                 at: value,
+                tension: 0.0,
             })
         }
     }
+
+    fn clamped_spline_stencil(&self, value: f64) -> Result<SplineStencil, OutOfBoundsError> {
+        if !self.contains(value) {
+            return Err(OutOfBoundsError::new(value, (self.first, self.last())));
+        }
+        let ileft = (float::floor(self.fractional_index(value)) as usize).min(self.n_values - 2);
+        let nodes = [
+            ileft.saturating_sub(1),
+            ileft,
+            ileft + 1,
+            (ileft + 2).min(self.n_values - 1),
+        ];
+        Ok(SplineStencil {
+            nodes,
+            xs: nodes.map(|i| self.at(i)),
+            at: value,
+            tension: 0.0,
+        })
+    }
 }
 
 impl Indexable for Range {
@@ -207,13 +640,13 @@ impl LinearInterpolable for Range {
             Ok(IdxLin::Exact(0))
         } else if value.is_close(self.last()) {
             Ok(IdxLin::Exact(self.n_values - 1))
-        } else if value < self.first || value > self.last() {
-            Err(OutOfBoundsError { value })
+        } else if !(self.first..=self.last()).contains(&value) {
+            Err(OutOfBoundsError::new(value, (self.first, self.last())))
         } else {
-            let iguess = ((value - self.first) / self.step).floor() as usize;
+            let iguess = float::floor((value - self.first) / self.step) as usize;
             if value.is_close(self.at(iguess)) {
                 Ok(IdxLin::Exact(iguess))
-            } else if self.get(iguess + 1).map_or(false, |v| v.is_close(value)) {
+            } else if self.get(iguess + 1).is_some_and(|v| v.is_close(value)) {
                 Ok(IdxLin::Exact(iguess + 1))
             } else {
                 Ok(IdxLin::Between(iguess, iguess + 1))
@@ -252,8 +685,8 @@ impl LinearInterpolable for CustomRange {
             Ok(IdxLin::Exact(0))
         } else if value.is_close(self.0[ilast]) {
             Ok(IdxLin::Exact(self.0.len() - 1))
-        } else if value < self.0[0] || value > self.0[ilast] {
-            Err(OutOfBoundsError { value })
+        } else if !(self.0[0]..=self.0[ilast]).contains(&value) {
+            Err(OutOfBoundsError::new(value, (self.0[0], self.0[ilast])))
         } else {
             // This could be implemented with a dichotomy, but in practice this
             // is only used once on ranges with few elements (metallicity of
@@ -267,7 +700,7 @@ impl LinearInterpolable for CustomRange {
                 - 1;
             if value.is_close(self.at(iguess)) {
                 Ok(IdxLin::Exact(iguess))
-            } else if self.get(iguess + 1).map_or(false, |v| v.is_close(value)) {
+            } else if self.get(iguess + 1).is_some_and(|v| v.is_close(value)) {
                 Ok(IdxLin::Exact(iguess + 1))
             } else {
                 Ok(IdxLin::Between(iguess, iguess + 1))
@@ -275,3 +708,225 @@ impl LinearInterpolable for CustomRange {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::is_close::IsClose;
+
+    use super::{CustomRange, Indexable, LinearInterpolable, Range};
+
+    #[test]
+    fn subrange_identical() {
+        let range = Range::new(0.0, 0.2, 5);
+        let overlap = range.subrange_in(range).unwrap();
+        assert_eq!(overlap.range.describe(), range.describe());
+        assert_eq!((overlap.self_offset, overlap.other_offset), (0, 0));
+    }
+
+    #[test]
+    fn subrange_partial() {
+        let left = Range::new(0.0, 0.2, 6);
+        let right = Range::new(0.4, 0.2, 5);
+        let overlap = left.subrange_in(right).unwrap();
+        assert_eq!(overlap.range.n_values(), 4);
+        assert_eq!((overlap.self_offset, overlap.other_offset), (2, 0));
+
+        let overlap = right.subrange_in(left).unwrap();
+        assert_eq!(overlap.range.n_values(), 4);
+        assert_eq!((overlap.self_offset, overlap.other_offset), (0, 2));
+    }
+
+    #[test]
+    fn subrange_different_steps() {
+        let coarse = Range::new(0.0, 0.2, 5);
+        let fine = Range::new(0.1, 0.1, 10);
+        let overlap = coarse.subrange_in(fine).unwrap();
+        assert_eq!(overlap.range.n_values(), 4);
+        assert_eq!((overlap.self_offset, overlap.other_offset), (1, 1));
+    }
+
+    #[test]
+    fn subrange_disjoint() {
+        let left = Range::new(0.0, 0.1, 4);
+        let right = Range::new(0.5, 0.1, 4);
+        assert!(left.subrange_in(right).is_none());
+        assert!(right.subrange_in(left).is_none());
+        // a single shared node is not a range
+        let touching = Range::new(0.3, 0.1, 4);
+        assert!(left.subrange_in(touching).is_none());
+    }
+
+    #[test]
+    fn linspace_matches_from_slice() {
+        let values = [10.5, 10.525, 10.55, 10.575, 10.6];
+        let from_slice = Range::from_slice(&values).unwrap();
+        let linspace = Range::linspace(10.5, 10.6, 5).unwrap();
+        assert_eq!(linspace.describe(), from_slice.describe());
+        assert!(Range::linspace(0.0, 1.0, 1).is_err());
+        assert!(Range::linspace(0.0, 0.0, 3).is_err());
+        assert!(Range::linspace(f64::NAN, 1.0, 3).is_err());
+    }
+
+    #[test]
+    fn spline_stencil_at_lower_guard() {
+        let range = Range::new(-5.0, 0.01, 10);
+        let lside = range.at(1);
+        assert!((lside - range.first()) / range.step() < 1.0);
+        let stencil = range.spline_stencil(lside).unwrap();
+        assert_eq!(stencil.nodes, [0, 1, 2, 3]);
+        assert!(range.spline_stencil(f64::NAN).is_err());
+        assert!(Range::new(0.0, 1.0, 3).spline_stencil(1.0).is_err());
+    }
+
+    #[test]
+    fn concat_contiguous() {
+        let low = Range::linspace(-1.0, 0.0, 11).unwrap();
+        let high = Range::linspace(0.1, 2.0, 20).unwrap();
+        let full = low.try_concat(high).unwrap();
+        assert!(full.is_close(Range::linspace(-1.0, 2.0, 31).unwrap()));
+    }
+
+    #[test]
+    fn concat_overlapping() {
+        let low = Range::linspace(0.0, 1.0, 11).unwrap();
+        let high = Range::linspace(1.0, 2.0, 11).unwrap();
+        assert!(low.try_concat(high).is_none());
+        let gap = Range::linspace(1.2, 2.0, 9).unwrap();
+        assert!(low.try_concat(gap).is_none());
+    }
+
+    #[test]
+    fn concat_step_mismatch() {
+        let low = Range::linspace(0.0, 1.0, 11).unwrap();
+        let high = Range::linspace(1.1, 2.1, 6).unwrap();
+        assert!(low.try_concat(high).is_none());
+    }
+
+    #[test]
+    fn bracket_values() {
+        let range = Range::new(1.0, 0.5, 5);
+        assert_eq!(range.bracket(1.0).unwrap(), (0, 0));
+        assert_eq!(range.bracket(1.7).unwrap(), (1, 2));
+        assert_eq!(range.bracket(2.0 + 1e-14).unwrap(), (2, 2));
+        assert_eq!(range.bracket(3.0).unwrap(), (4, 4));
+        assert!(range.bracket(0.9).is_err());
+        assert!(range.bracket(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn refined_extrema() {
+        let range = Range::new(-1.0, 0.5, 7);
+        let parabola = |x: f64| 2.0 - 3.0 * (x - 0.6) * (x - 0.6);
+        let values: Vec<_> = range.into_iter().map(parabola).collect();
+        let (at, max) = range.argmax(&values).unwrap();
+        assert!(at.is_close(0.6));
+        assert!(max.is_close(2.0));
+        let negated: Vec<_> = values.iter().map(|v| -v).collect();
+        let (at, min) = range.argmin(&negated).unwrap();
+        assert!(at.is_close(0.6));
+        assert!(min.is_close(-2.0));
+
+        // extrema on the edges are not refined, NaN are ignored
+        let values = [f64::NAN, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        assert_eq!(range.argmax(&values), Some((2.0, 6.0)));
+        assert_eq!(range.argmin(&values), Some((-0.5, 1.0)));
+        assert_eq!(range.argmax(&[f64::NAN; 7]), None);
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn range_from_array() {
+        use super::RangeError;
+        use ndarray::{s, Array1};
+
+        let values = Array1::linspace(-1.0, 2.0, 7);
+        let range = Range::try_from(values.view()).unwrap();
+        assert!(range.first().is_close(-1.0));
+        assert!(range.last().is_close(2.0));
+        assert_eq!(range.n_values(), 7);
+        let strided = Range::try_from(values.slice(s![..;2])).unwrap();
+        assert!(strided.step().is_close(1.0));
+        assert_eq!(strided.n_values(), 4);
+        assert!(Range::try_from(values).is_ok());
+
+        let uneven = Array1::from_vec(vec![0.0, 1.0, 3.0]);
+        assert!(matches!(
+            Range::try_from(uneven),
+            Err(RangeError::NotLinear)
+        ));
+        assert!(matches!(
+            Range::try_from(Array1::from_vec(vec![1.0])),
+            Err(RangeError::FewerThanTwoValues)
+        ));
+    }
+
+    #[test]
+    fn consistent_near_zero_edge() {
+        use super::{IdxLin, LinearInterpolable, SplineBoundary};
+
+        for range in [Range::new(0.0, 0.2, 5), Range::new(0.0, 1.0, 6)] {
+            for value in [0.0, -0.0, 1e-16, -1e-16, 5e-13, -5e-13] {
+                assert!(matches!(range.idx_lin(value), Ok(IdxLin::Exact(0))));
+                assert!(range.contains(value));
+                assert_eq!(range.nearest_index(value).unwrap(), 0);
+                assert_eq!(range.bracket(value).unwrap(), (0, 0));
+                let stencil = range
+                    .spline_stencil_with(value, SplineBoundary::Clamped)
+                    .unwrap();
+                assert_eq!(stencil.nodes, [0, 0, 1, 2]);
+            }
+            assert!(matches!(range.idx_lin(1e-11), Ok(IdxLin::Between(0, 1))));
+            for value in [-1e-11, f64::NAN] {
+                assert!(range.idx_lin(value).is_err());
+                assert!(!range.contains(value));
+                assert!(range.nearest_index(value).is_err());
+                assert!(range
+                    .spline_stencil_with(value, SplineBoundary::Clamped)
+                    .is_err());
+            }
+            // same tolerance on the edges of the inner spline domain
+            let lside = range.at(1);
+            for value in [lside, lside - 1e-16, lside + 1e-16] {
+                assert_eq!(range.spline_stencil(value).unwrap().nodes, [0, 1, 2, 3]);
+            }
+            assert!(range.spline_stencil(lside - 1e-11).is_err());
+        }
+    }
+
+    #[test]
+    fn contains_up_to_rounding_errors() {
+        let range = Range::new(-1.0, 0.5, 5);
+        for value in [-1.0, 0.0, 0.7, 1.0] {
+            assert!(range.contains(value));
+        }
+        for (end, outward) in [(-1.0, -1.0), (1.0, 1.0)] {
+            assert!(range.contains(end + outward * 1e-13));
+            assert!(!range.contains(end + outward * 1e-11));
+        }
+        for value in [-3.0, 2.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(!range.contains(value));
+        }
+    }
+
+    #[test]
+    fn spline_stencil_right_edge() {
+        // the domain of spline stencils is [at(1), at(n_values - 2)], both
+        // ends included, the last node uses the stencil of the last cell
+        let range = Range::new(0.0, 1.0, 6);
+        for (value, node) in [(1.0, 1), (4.0, 2)] {
+            let weights = range.spline_stencil(value).unwrap().weights();
+            for (i, weight) in weights.into_iter().enumerate() {
+                assert!(weight.is_close(if i == node { 1.0 } else { 0.0 }));
+            }
+        }
+        assert!(range.spline_stencil(4.0 + 1e-9).is_err());
+        assert!(range.spline_stencil(1.0 - 1e-9).is_err());
+    }
+
+    #[test]
+    fn idx_lin_rejects_nan() {
+        assert!(Range::new(0.0, 1.0, 3).idx_lin(f64::NAN).is_err());
+        let custom = CustomRange::new(vec![0.0, 1.0, 3.0]).unwrap();
+        assert!(custom.idx_lin(f64::NAN).is_err());
+    }
+}
@@ -1,4 +1,16 @@
-use ndarray::{Array, ArrayBase, ArrayView, ArrayView1, ArrayView2, Axis, Data, Dimension};
+//! Interpolation kernels.
+//!
+//! The scalar kernels are available without the `ndarray` feature, the
+//! kernels operating on arrays require it.  Stencils are built by the axes
+//! of [`crate::index`], e.g. [`crate::index::Range::spline_stencil`].
+
+#[cfg(feature = "ndarray")]
+use ndarray::{
+    Array, ArrayBase, ArrayView, ArrayView1, ArrayView2, Axis, Data, DataMut, Dimension,
+};
+
+#[cfg(feature = "ndarray")]
+use crate::index::{Indexable, Range};
 
 #[derive(Copy, Clone)]
 pub struct LinearInterpolator {
@@ -17,6 +29,7 @@ impl LinearInterpolator {
         left * self.left_coef + right * (1.0 - self.left_coef)
     }
 
+    #[cfg(feature = "ndarray")]
     pub fn interp<D, S1, S2>(
         &self,
         left: ArrayBase<S1, D>,
@@ -28,12 +41,24 @@ impl LinearInterpolator {
         D: Dimension,
     {
         let mut left: Array<f64, D> = left.to_owned();
-        left *= self.left_coef;
-        left.scaled_add(1.0 - self.left_coef, &right);
+        self.interp_into(&mut left, right.view());
         left
     }
+
+    /// Same as [`LinearInterpolator::interp`], overwriting `left` with the
+    /// result instead of allocating a new array.
+    #[cfg(feature = "ndarray")]
+    pub fn interp_into<D, S>(&self, left: &mut ArrayBase<S, D>, right: ArrayView<'_, f64, D>)
+    where
+        S: DataMut<Elem = f64>,
+        D: Dimension,
+    {
+        *left *= self.left_coef;
+        left.scaled_add(1.0 - self.left_coef, &right);
+    }
 }
 
+#[derive(Copy, Clone)]
 pub enum LinearStencil {
     Exact {
         i: usize,
@@ -46,6 +71,7 @@ pub enum LinearStencil {
     },
 }
 
+#[cfg(feature = "ndarray")]
 impl LinearStencil {
     pub fn apply_to(&self, arr: ArrayView1<'_, f64>) -> f64 {
         match self {
@@ -81,44 +107,104 @@ impl LinearStencil {
     }
 }
 
-fn low_level_spline(x: [f64; 4], y: [f64; 4], at: f64) -> f64 {
-    let dy_dx_left = (y[2] - y[0]) / (x[2] - x[0]);
-    let dy_dx_right = (y[3] - y[1]) / (x[3] - x[1]);
-    let a = dy_dx_left * (x[2] - x[1]) - (y[2] - y[1]);
-    let b = -dy_dx_right * (x[2] - x[1]) + (y[2] - y[1]);
+/// Cardinal spline between the two middle nodes, `tension` scales down the
+/// slopes at the nodes: 0 gives a Catmull-Rom spline, 1 zero slopes.
+pub fn low_level_spline(x: [f64; 4], y: [f64; 4], at: f64, tension: f64) -> f64 {
+    let (a, b) = spline_slope_terms(x, y, tension);
     let t = (at - x[1]) / (x[2] - x[1]);
     (1.0 - t) * y[1] + t * y[2] + t * (1.0 - t) * (a * (1.0 - t) + b * t)
 }
 
+/// Deviations `a` and `b` of the spline from linear interpolation between
+/// the two middle nodes.
+fn spline_slope_terms(x: [f64; 4], y: [f64; 4], tension: f64) -> (f64, f64) {
+    let dy_dx_left = (1.0 - tension) * (y[2] - y[0]) / (x[2] - x[0]);
+    let dy_dx_right = (1.0 - tension) * (y[3] - y[1]) / (x[3] - x[1]);
+    let a = dy_dx_left * (x[2] - x[1]) - (y[2] - y[1]);
+    let b = -dy_dx_right * (x[2] - x[1]) + (y[2] - y[1]);
+    (a, b)
+}
+
 /// Centered cubic spline interpolator.
+///
+/// The four `nodes` are in increasing order, the first or last one may be
+/// repeated in the outer cells of an axis.
+#[derive(Copy, Clone)]
 pub struct SplineStencil {
-    pub ileft: usize,
-    pub xs: [f64; 4],
-    pub at: f64,
+    pub(crate) nodes: [usize; 4],
+    pub(crate) xs: [f64; 4],
+    pub(crate) at: f64,
+    pub(crate) tension: f64,
 }
 
 impl SplineStencil {
+    #[cfg(feature = "ndarray")]
     pub fn apply_to(&self, arr: ArrayView1<'_, f64>) -> f64 {
-        let i = self.ileft;
-        let y: [f64; 4] = [arr[i], arr[i + 1], arr[i + 2], arr[i + 3]];
-        low_level_spline(self.xs, y, self.at)
+        self.interp(self.nodes.map(|i| arr[i]))
     }
 
-    pub(crate) fn slice_view<D: Dimension>(
-        &self,
-        axis: Axis,
-        arr: &mut ArrayView<'_, f64, D>,
-    ) -> Self {
-        let i0 = self.ileft;
-        arr.slice_axis_inplace(axis, (i0..i0 + 4).into());
-        Self {
-            ileft: 0,
-            xs: self.xs,
-            at: self.at,
-        }
+    /// Index of the first node of the stencil.
+    ///
+    /// This was a field before clamped boundaries allowed repeated nodes, the
+    /// nodes are not necessarily `ileft..ileft + 4` anymore.
+    pub fn ileft(&self) -> usize {
+        self.nodes[0]
+    }
+
+    /// Indices of the four nodes of the stencil.
+    pub fn nodes(&self) -> [usize; 4] {
+        self.nodes
+    }
+
+    /// Coordinates of the four nodes of the stencil.
+    pub fn xs(&self) -> [f64; 4] {
+        self.xs
+    }
+
+    /// Interpolate from the values at the four nodes of the stencil.
+    pub fn interp(&self, ys: [f64; 4]) -> f64 {
+        low_level_spline(self.xs, ys, self.at, self.tension)
+    }
+
+    /// Coefficients `[c0, c1, c2, c3]` of the cubic polynomial through the
+    /// values `ys` at the four nodes of the stencil.
+    ///
+    /// The polynomial is expressed in the local coordinate
+    /// `t = (x - xs[1]) / (xs[2] - xs[1])`, which is 0 on the second node and
+    /// 1 on the third one, the interpolated value at `x` is then
+    /// `c0 + t * (c1 + t * (c2 + t * c3))`.  The coefficients do not depend
+    /// on the position `at` of the stencil within its cell.
+    pub fn coefficients(&self, ys: [f64; 4]) -> [f64; 4] {
+        let (a, b) = spline_slope_terms(self.xs, ys, self.tension);
+        [ys[1], ys[2] - ys[1] + a, b - 2.0 * a, a - b]
+    }
+
+    /// Derivative of the interpolant at `at` with respect to the coordinate,
+    /// from the values `ys` at the four nodes of the stencil.
+    pub fn derivative(&self, ys: [f64; 4]) -> f64 {
+        let [_, c1, c2, c3] = self.coefficients(ys);
+        let width = self.xs[2] - self.xs[1];
+        let t = (self.at - self.xs[1]) / width;
+        (c1 + t * (2.0 * c2 + 3.0 * t * c3)) / width
+    }
+
+    /// Same stencil with another spline tension.
+    pub fn with_tension(self, tension: f64) -> Self {
+        Self { tension, ..self }
+    }
+
+    /// Weights of the four nodes of the stencil, the interpolated value is
+    /// the weighted sum of the values at the nodes.
+    pub fn weights(&self) -> [f64; 4] {
+        core::array::from_fn(|i| {
+            let mut ys = [0.0; 4];
+            ys[i] = 1.0;
+            self.interp(ys)
+        })
     }
 }
 
+#[cfg(feature = "ndarray")]
 pub(crate) fn lin_interp_2d(
     x_st: LinearStencil,
     y_st: LinearStencil,
@@ -142,37 +228,137 @@ pub(crate) fn lin_interp_2d(
     }
 }
 
+#[cfg(feature = "ndarray")]
 pub(crate) fn cubic_spline_2d(
     x_st: SplineStencil,
     y_st: SplineStencil,
     z: ArrayView2<'_, f64>,
 ) -> f64 {
     let SplineStencil {
-        ileft: iy0,
+        nodes: iys,
         xs: ys,
         at: at_y,
+        tension,
     } = y_st;
-    let z_at_ys = [
-        x_st.apply_to(z.index_axis(Axis(1), iy0)),
-        x_st.apply_to(z.index_axis(Axis(1), iy0 + 1)),
-        x_st.apply_to(z.index_axis(Axis(1), iy0 + 2)),
-        x_st.apply_to(z.index_axis(Axis(1), iy0 + 3)),
-    ];
-    low_level_spline(ys, z_at_ys, at_y)
+    let z_at_ys = iys.map(|iy| x_st.apply_to(z.index_axis(Axis(1), iy)));
+    low_level_spline(ys, z_at_ys, at_y, tension)
+}
+
+/// Check the extreme values of a batch of queries with `check`.
+///
+/// Non-finite values are checked on their own since they are not ordered,
+/// `check` is expected to reject them.
+#[cfg(feature = "ndarray")]
+pub(crate) fn check_batch<T, E>(
+    values: &[f64],
+    check: impl Fn(f64) -> Result<T, E>,
+) -> Result<(), E> {
+    if let Some(&first) = values.first() {
+        let (mut min, mut max) = (first, first);
+        for &value in values {
+            if !value.is_finite() {
+                check(value)?;
+            }
+            (min, max) = (min.min(value), max.max(value));
+        }
+        check(min)?;
+        check(max)?;
+    }
+    Ok(())
+}
+
+/// Left node and fractional position in `[0, 1]` of `value` in its cell,
+/// `value` is assumed to be within `[first, last]` up to rounding errors.
+#[cfg(feature = "ndarray")]
+fn lin_cell(axis: Range, value: f64) -> (usize, f64) {
+    let fidx = axis.fractional_index(value).max(0.0);
+    let ileft = (fidx.floor() as usize).min(axis.n_values() - 2);
+    (ileft, (fidx - ileft as f64).min(1.0))
+}
+
+/// Batched equivalent of [`lin_interp_2d`] on the `(x_axis, y_axis)` grid.
+///
+/// Queries are assumed to be within the grid, callers are expected to check
+/// the bounds once for the whole batch. This avoids building a stencil per
+/// point, but the whole batch is materialized in memory.
+#[cfg(feature = "ndarray")]
+pub(crate) fn lin_interp_2d_batch(
+    x_axis: Range,
+    y_axis: Range,
+    xs: &[f64],
+    ys: &[f64],
+    z: ArrayView2<'_, f64>,
+) -> Vec<f64> {
+    assert_eq!(xs.len(), ys.len());
+    xs.iter()
+        .zip(ys)
+        .map(|(&x, &y)| {
+            let (ix, tx) = lin_cell(x_axis, x);
+            let (iy, ty) = lin_cell(y_axis, y);
+            let z_at_iyl = (1.0 - tx) * z[[ix, iy]] + tx * z[[ix + 1, iy]];
+            let z_at_iyr = (1.0 - tx) * z[[ix, iy + 1]] + tx * z[[ix + 1, iy + 1]];
+            (1.0 - ty) * z_at_iyl + ty * z_at_iyr
+        })
+        .collect()
+}
+
+/// First node of the spline stencil around `value`, assumed to be within
+/// `[at(1), at(n - 2)]`.
+#[cfg(feature = "ndarray")]
+fn spline_start(axis: Range, value: f64) -> usize {
+    let fidx = axis.fractional_index(value).max(1.0);
+    (fidx.floor() as usize).min(axis.n_values() - 3) - 1
+}
+
+/// Batched equivalent of [`cubic_spline_2d`] on the `(x_axis, y_axis)` grid.
+///
+/// As for [`lin_interp_2d_batch`], queries are assumed to be in the domain of
+/// validity of the spline stencils.
+#[cfg(feature = "ndarray")]
+pub(crate) fn cubic_spline_2d_batch(
+    x_axis: Range,
+    y_axis: Range,
+    xs: &[f64],
+    ys: &[f64],
+    z: ArrayView2<'_, f64>,
+    tension: f64,
+) -> Vec<f64> {
+    assert_eq!(xs.len(), ys.len());
+    xs.iter()
+        .zip(ys)
+        .map(|(&x, &y)| {
+            let ix = spline_start(x_axis, x);
+            let iy = spline_start(y_axis, y);
+            let x_nodes = std::array::from_fn(|i| x_axis.at(ix + i));
+            let y_nodes = std::array::from_fn(|i| y_axis.at(iy + i));
+            let z_at_ys = std::array::from_fn(|j| {
+                let z_col = std::array::from_fn(|i| z[[ix + i, iy + j]]);
+                low_level_spline(x_nodes, z_col, x, tension)
+            });
+            low_level_spline(y_nodes, z_at_ys, y, tension)
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    #[cfg(feature = "ndarray")]
+    use crate::index::{Indexable, Range, SplineBoundary};
     use crate::is_close::IsClose;
 
+    #[cfg(feature = "ndarray")]
+    use ndarray::{arr2, Array1};
+
     use super::low_level_spline;
+    #[cfg(feature = "ndarray")]
+    use super::LinearInterpolator;
 
     fn low_level_spline_analytic<F: Fn(f64) -> f64>(f: F) {
         let xs = [-1., 0., 1., 2.];
         let ys = xs.map(&f);
         assert!((0..=10)
             .map(|i| i as f64 / 10.0)
-            .all(|at| { dbg!(low_level_spline(xs, ys, at)).is_close(dbg!(f(at))) }));
+            .all(|at| { dbg!(low_level_spline(xs, ys, at, 0.0)).is_close(dbg!(f(at))) }));
     }
 
     #[test]
@@ -180,4 +366,122 @@ mod tests {
         low_level_spline_analytic(|x| 3.0 * x * x - 2.0 * x + 5.0);
         low_level_spline_analytic(|x| 42.0 * x - 7.0);
     }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn interp_in_place() {
+        let lin = LinearInterpolator::new(0.0, 4.0, 1.0);
+        let mut left = arr2(&[[1.0, 2.0], [3.0, 4.0]]);
+        let right = arr2(&[[5.0, 6.0], [7.0, 8.0]]);
+        let expected = lin.interp(left.view(), right.view());
+        lin.interp_into(&mut left, right.view());
+        assert_eq!(left, expected);
+        assert_eq!(left, arr2(&[[2.0, 3.0], [4.0, 5.0]]));
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn clamped_spline_at_edges() {
+        let cubic = |x: f64| x * x * x - 2.0 * x * x + x + 1.0;
+        let axis = Range::new(0.0, 0.1, 11);
+        let ys = Array1::from_iter((0..11).map(|i| cubic(axis.at(i))));
+        for at in [0.02, 0.05, 0.08, 0.92, 0.95, 0.98] {
+            assert!(axis.spline_stencil(at).is_err());
+            let stencil = axis
+                .spline_stencil_with(at, SplineBoundary::Clamped)
+                .unwrap();
+            let spline_err = (stencil.apply_to(ys.view()) - cubic(at)).abs();
+            let (x0, x1) = (stencil.xs[1], stencil.xs[2]);
+            let lin = LinearInterpolator::new(x0, x1, at);
+            let lin_err = (lin.interp_scalar(cubic(x0), cubic(x1)) - cubic(at)).abs();
+            assert!(spline_err < 3e-3);
+            assert!(spline_err < lin_err);
+        }
+        for (i, at) in [(0, 0.0), (10, 1.0)] {
+            let stencil = axis
+                .spline_stencil_with(at, SplineBoundary::Clamped)
+                .unwrap();
+            assert!(stencil.apply_to(ys.view()).is_close(ys[i]));
+        }
+        for at in [0.1, 0.35, 0.9] {
+            let inner = axis.spline_stencil(at).unwrap().apply_to(ys.view());
+            let clamped = axis
+                .spline_stencil_with(at, SplineBoundary::Clamped)
+                .unwrap()
+                .apply_to(ys.view());
+            assert!(inner.is_close(clamped));
+        }
+        assert!(axis
+            .spline_stencil_with(1.01, SplineBoundary::Clamped)
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn clamped_spline_exact_for_linear() {
+        let axis = Range::new(1.0, 0.5, 3);
+        let ys = Array1::from_iter((0..3).map(|i| 4.0 * axis.at(i) - 3.0));
+        for at in [1.0, 1.2, 1.5, 1.9, 2.0] {
+            let stencil = axis
+                .spline_stencil_with(at, SplineBoundary::Clamped)
+                .unwrap();
+            assert!(stencil.apply_to(ys.view()).is_close(4.0 * at - 3.0));
+        }
+    }
+
+    #[test]
+    fn spline_tension() {
+        let xs = [-1., 0., 1., 2.];
+        let quad = xs.map(|x| 3.0 * x * x - 2.0 * x + 5.0);
+        for at in [0.1, 0.25, 0.8] {
+            let catmull_rom = low_level_spline(xs, quad, at, 0.0);
+            assert!(catmull_rom.is_close(3.0 * at * at - 2.0 * at + 5.0));
+        }
+        // on linear data, the tension bends the spline away from the line by
+        // tension * t(1 - t)(2t - 1) * (y[2] - y[1])
+        let lin = xs.map(|x| 2.0 * x + 1.0);
+        for tension in [0.0, 0.25, 0.5, 1.0] {
+            for at in [0.25, 0.5, 0.75] {
+                let bend = tension * at * (1.0 - at) * (2.0 * at - 1.0) * 2.0;
+                let value = low_level_spline(xs, lin, at, tension);
+                assert!(value.is_close(2.0 * at + 1.0 + bend));
+            }
+        }
+        // zero slopes at the nodes with full tension
+        let eps = 1e-6;
+        let slope =
+            (low_level_spline(xs, lin, eps, 1.0) - low_level_spline(xs, lin, 0.0, 1.0)) / eps;
+        assert!(slope.abs() < 1e-4);
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn spline_coefficients() {
+        let axis = Range::new(0.0, 0.5, 8);
+        let ys = Array1::from_iter((0..8).map(|i| (axis.at(i) * 1.3).sin()));
+        for tension in [0.0, 0.4] {
+            for at in [1.2, 1.5, 2.0, 2.9] {
+                let stencil = axis.spline_stencil(at).unwrap().with_tension(tension);
+                let [c0, c1, c2, c3] = stencil.coefficients(stencil.nodes.map(|i| ys[i]));
+                let t = (at - stencil.xs[1]) / (stencil.xs[2] - stencil.xs[1]);
+                let value = c0 + t * (c1 + t * (c2 + t * c3));
+                assert!(value.is_close(stencil.apply_to(ys.view())));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn spline_derivative() {
+        let axis = Range::new(0.0, 0.5, 8);
+        let ys = Array1::from_iter((0..8).map(|i| (axis.at(i) * 1.3).sin()));
+        let eps = 1e-6;
+        for at in [1.2, 1.5, 2.0, 2.9] {
+            let value = |x| axis.spline_stencil(x).unwrap().apply_to(ys.view());
+            let stencil = axis.spline_stencil(at).unwrap();
+            let deriv = stencil.derivative(stencil.nodes.map(|i| ys[i]));
+            let finite_diff = (value(at + eps) - value(at - eps)) / (2.0 * eps);
+            assert!((deriv - finite_diff).abs() < 1e-6);
+        }
+    }
 }
@@ -0,0 +1,277 @@
+//! Inversion of EOS tables, finding the energy or volume at which a state
+//! variable reaches a given value.
+
+use ndarray::Array1;
+use thiserror::Error;
+
+use crate::{
+    eos_tables::{StateVar, VolumeEnergyTable},
+    index::OutOfBoundsError,
+};
+
+#[derive(Error, Debug)]
+pub enum InversionError {
+    #[error(transparent)]
+    OutOfBounds(#[from] OutOfBoundsError),
+    #[error("no log_energy in the table reaches {target} at log_volume {log_volume}")]
+    Unreachable { target: f64, log_volume: f64 },
+    #[error("no log_volume in the table reaches log_pressure {target} at log_energy {log_energy}")]
+    UnreachablePressure { target: f64, log_energy: f64 },
+}
+
+/// Find the `log_energy` at which `var` equals `target` at constant
+/// `log_volume`.
+///
+/// This is a bisection over the energy axis of the table, restricted to the
+/// domain of its interpolation method. `var` should therefore be monotonic
+/// with energy (e.g. temperature or entropy).  Tables too short to be
+/// interpolated are reported as [`InversionError::OutOfBounds`].
+pub fn invert_for_energy(
+    table: &VolumeEnergyTable,
+    var: StateVar,
+    target: f64,
+    log_volume: f64,
+) -> Result<f64, InversionError> {
+    let (domain, _) = table.method_domain();
+    let (mut lo, mut hi) = (domain.first(), domain.last());
+    let f_lo = table.at(lo, log_volume, var)? - target;
+    let f_hi = table.at(hi, log_volume, var)? - target;
+    if f_lo == 0.0 {
+        return Ok(lo);
+    } else if f_hi == 0.0 {
+        return Ok(hi);
+    } else if f_lo.signum() == f_hi.signum() {
+        return Err(InversionError::Unreachable { target, log_volume });
+    }
+    let increasing = f_hi > 0.0;
+    while hi - lo > 1e-12 {
+        let mid = 0.5 * (lo + hi);
+        let above = table.at(mid, log_volume, var)? > target;
+        if above == increasing {
+            hi = mid;
+        } else {
+            lo = mid;
+        }
+    }
+    Ok(0.5 * (lo + hi))
+}
+
+/// Find the `log_volume` at which the pressure is `target_log_pressure`
+/// at constant `log_energy`.
+///
+/// The target is first bracketed between two nodes of the volume axis,
+/// skipping regions where the table has no data, and then refined by a
+/// Newton iteration using `DPresDDensEcst` as the derivative (the log
+/// volume and log density only differ by a function of the energy),
+/// safeguarded by bisection. Together with [`invert_for_energy`], this
+/// gives the density and energy of a state from its pressure and
+/// temperature.
+pub fn invert_for_volume_at_pressure(
+    table: &VolumeEnergyTable,
+    log_energy: f64,
+    target_log_pressure: f64,
+) -> Result<f64, InversionError> {
+    let residual = |logv: f64| -> Result<(f64, f64), OutOfBoundsError> {
+        let [logp, dp_dd] = table.at_many(
+            log_energy,
+            logv,
+            [StateVar::LogPressure, StateVar::DPresDDensEcst],
+        )?;
+        Ok((logp - target_log_pressure, dp_dd))
+    };
+    let (_, domain) = table.method_domain();
+    let mut bracket = None;
+    let mut previous: Option<(f64, f64)> = None;
+    for logv in domain {
+        let (f, _) = residual(logv)?;
+        if f == 0.0 {
+            return Ok(logv);
+        } else if f.is_nan() {
+            previous = None;
+            continue;
+        }
+        if let Some((prev_logv, prev_f)) = previous {
+            if prev_f.signum() != f.signum() {
+                bracket = Some((prev_logv, logv, f > 0.0));
+                break;
+            }
+        }
+        previous = Some((logv, f));
+    }
+    let Some((mut lo, mut hi, increasing)) = bracket else {
+        return Err(InversionError::UnreachablePressure {
+            target: target_log_pressure,
+            log_energy,
+        });
+    };
+    let mut logv = 0.5 * (lo + hi);
+    for _ in 0..100 {
+        let (f, dp_dd) = residual(logv)?;
+        if f == 0.0 {
+            return Ok(logv);
+        } else if (f > 0.0) == increasing {
+            hi = logv;
+        } else {
+            lo = logv;
+        }
+        let newton = logv - f / dp_dd;
+        let next = if newton > lo && newton < hi {
+            newton
+        } else {
+            0.5 * (lo + hi)
+        };
+        if (next - logv).abs() < 1e-12 || hi - lo < 1e-12 {
+            return Ok(next);
+        }
+        logv = next;
+    }
+    Ok(logv)
+}
+
+/// Thermodynamic profile along an adiabat, see [`trace_adiabat`].
+pub struct Adiabat {
+    pub log_pressure: Array1<f64>,
+    pub log_temperature: Array1<f64>,
+    pub log_density: Array1<f64>,
+}
+
+/// Trace the adiabat of entropy `log_entropy` through the requested volumes.
+///
+/// This fails if the adiabat leaves the table at one of the volumes.
+pub fn trace_adiabat(
+    table: &VolumeEnergyTable,
+    log_entropy: f64,
+    log_volumes: &[f64],
+) -> Result<Adiabat, InversionError> {
+    let n_points = log_volumes.len();
+    let mut adiabat = Adiabat {
+        log_pressure: Array1::zeros(n_points),
+        log_temperature: Array1::zeros(n_points),
+        log_density: Array1::zeros(n_points),
+    };
+    for (i, &logv) in log_volumes.iter().enumerate() {
+        let loge = invert_for_energy(table, StateVar::LogEntropy, log_entropy, logv)?;
+        let [logp, logt, logd] = table.at_many(
+            loge,
+            logv,
+            [
+                StateVar::LogPressure,
+                StateVar::LogTemperature,
+                StateVar::LogDensity,
+            ],
+        )?;
+        adiabat.log_pressure[i] = logp;
+        adiabat.log_temperature[i] = logt;
+        adiabat.log_density[i] = logd;
+    }
+    Ok(adiabat)
+}
+
+#[cfg(all(test, feature = "embedded-tables"))]
+mod tests {
+    use ndarray::Array3;
+
+    use crate::{
+        eos_tables::{AllTables, StateVar, VolumeEnergyTable},
+        grid::InterpolationMethod,
+        index::Range,
+        is_close::IsClose,
+    };
+
+    use super::{invert_for_energy, invert_for_volume_at_pressure, trace_adiabat, InversionError};
+
+    #[test]
+    fn invert_temperature() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_h_frac(0.7)
+            .unwrap();
+        let (loge, logv) = (14.3, 5.6);
+        let logt = table.at(loge, logv, StateVar::LogTemperature).unwrap();
+        let found = invert_for_energy(&table, StateVar::LogTemperature, logt, logv).unwrap();
+        assert!((found - loge).abs() < 1e-8);
+        assert!(matches!(
+            invert_for_energy(&table, StateVar::LogTemperature, 50.0, logv),
+            Err(InversionError::Unreachable { .. })
+        ));
+    }
+
+    #[test]
+    fn adiabat_has_constant_entropy() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_h_frac(0.7)
+            .unwrap();
+        let (loge, logv) = (14.3, 5.6);
+        let [logs, logt] = table
+            .at_many(loge, logv, [StateVar::LogEntropy, StateVar::LogTemperature])
+            .unwrap();
+        let adiabat = trace_adiabat(&table, logs, &[5.0, 5.6, 6.2]).unwrap();
+        assert!((adiabat.log_temperature[1] - logt).abs() < 1e-8);
+        // denser material on an adiabat is hotter and has higher pressure
+        assert!(adiabat.log_density[0] < adiabat.log_density[2]);
+        assert!(adiabat.log_temperature[0] < adiabat.log_temperature[2]);
+        assert!(adiabat.log_pressure[0] < adiabat.log_pressure[2]);
+        assert!(
+            adiabat.log_density[1].is_close(table.at(loge, logv, StateVar::LogDensity).unwrap())
+        );
+    }
+
+    #[test]
+    fn invert_pressure() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_h_frac(0.7)
+            .unwrap();
+        let (loge, logv) = (14.3, 5.6);
+        let logp = table.at(loge, logv, StateVar::LogPressure).unwrap();
+        let found = invert_for_volume_at_pressure(&table, loge, logp).unwrap();
+        assert!((found - logv).abs() < 1e-8);
+        assert!(matches!(
+            invert_for_volume_at_pressure(&table, loge, 60.0),
+            Err(InversionError::UnreachablePressure { .. })
+        ));
+        assert!(matches!(
+            invert_for_volume_at_pressure(&table, 30.0, logp),
+            Err(InversionError::OutOfBounds(_))
+        ));
+    }
+
+    #[test]
+    fn invert_short_axes() {
+        // values equal to log_energy - 10 + log_volume, with consistent
+        // pressure derivatives
+        let values = Array3::from_shape_fn((3, 3, StateVar::COUNT), |(i, j, k)| {
+            if k == StateVar::DPresDDensEcst as usize {
+                1.0
+            } else {
+                (i + j) as f64
+            }
+        });
+        let table = VolumeEnergyTable::from_parts(
+            0.02,
+            0.7,
+            Range::new(10.0, 1.0, 3),
+            Range::new(0.0, 1.0, 3),
+            values,
+        )
+        .unwrap();
+        assert!(matches!(
+            invert_for_energy(&table, StateVar::LogTemperature, 0.5, 0.5),
+            Err(InversionError::OutOfBounds(_))
+        ));
+        assert!(matches!(
+            invert_for_volume_at_pressure(&table, 10.5, 0.5),
+            Err(InversionError::OutOfBounds(_))
+        ));
+        // linear interpolation reaches the edge cells of the axes
+        let table = table.with_method(InterpolationMethod::Linear);
+        let found = invert_for_energy(&table, StateVar::LogTemperature, 0.5, 0.25).unwrap();
+        assert!((found - 10.25).abs() < 1e-8);
+        let found = invert_for_volume_at_pressure(&table, 10.25, 0.5).unwrap();
+        assert!((found - 0.25).abs() < 1e-8);
+    }
+}
@@ -1,11 +1,79 @@
+//! Tables are immutable once built, and all table types are `Send + Sync`.
+//! States and opacities share them through an `Arc`, so they can be used
+//! from several threads without copying the tables.
+//!
+//! # Features
+//!
+//! - `std` (default): link the standard library, see below.
+//! - `embedded-tables` (default): bundle the MESA tables in the library.
+//! - `ndarray` (default): tables, states, and opacities, which all rely on
+//!   `ndarray` arrays.  It implies `std` and is implied by
+//!   `embedded-tables`.
+//! - `rayon`: parallel computations on arrays.
+//! - `async`: read tables from asynchronous readers, implies `std`.
+//!
+//! Without the `ndarray` feature, only the scalar core of the crate is
+//! available: the [`index`] axes ([`index::Range`], [`index::CustomRange`]
+//! and their stencils), the scalar 1-D kernels of [`interp`]
+//! ([`interp::LinearInterpolator::interp_scalar`],
+//! [`interp::SplineStencil::interp`], [`interp::low_level_spline`]), the
+//! `fort_unfmt` record readers, and the [`units`] conventions.
+//!
+//! This core builds without the `std` feature, as a `no_std` crate relying
+//! on `alloc`, with float functions from `libm`.  The `fort_unfmt` record
+//! readers and the conversions of errors to `std::io::Error` require `std`.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Stable hash of the embedded table data.
+///
+/// It changes whenever any of the embedded EOS or opacity tables does, it can
+/// therefore be recorded alongside results to identify the data they were
+/// computed with.  The hash is computed on the first call only.
+#[cfg(feature = "embedded-tables")]
+pub fn tables_fingerprint() -> u64 {
+    raw_tables::fingerprint()
+}
+
+/// Size in bytes of the embedded EOS table data, this is 0 when built
+/// without the `embedded-tables` feature.
+pub fn embedded_eos_bytes() -> usize {
+    raw_tables::eos_bytes()
+}
+
+/// Size in bytes of the embedded opacity table data, this is 0 when built
+/// without the `embedded-tables` feature.
+pub fn embedded_opacity_bytes() -> usize {
+    raw_tables::opacity_bytes()
+}
+
+#[cfg(feature = "embedded-tables")]
+pub mod cache;
+#[cfg(feature = "ndarray")]
+pub mod coords;
+#[cfg(feature = "ndarray")]
 pub mod eos_tables;
-mod fort_unfmt;
-mod index;
-mod interp;
+mod float;
+#[cfg(feature = "std")]
+pub mod fort_unfmt;
+#[cfg(feature = "ndarray")]
+pub mod grid;
+pub mod index;
+pub mod interp;
+#[cfg(feature = "ndarray")]
+pub mod inversion;
 mod is_close;
+#[cfg(feature = "ndarray")]
 pub mod opacity;
+#[cfg(feature = "ndarray")]
 pub mod opacity_tables;
 mod raw_tables;
+#[cfg(feature = "ndarray")]
 pub mod state;
+pub mod units;
+#[cfg(feature = "ndarray")]
+mod zip;
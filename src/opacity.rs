@@ -1,72 +1,774 @@
 use std::sync::Arc;
 
-use ndarray::{Array, Dimension, Zip};
+use ndarray::{Array, ArrayView, Dimension};
 
 use crate::{
     eos_tables::StateVar,
+    grid::{InterpolationMethod, OobPolicy},
+    index::OutOfBoundsError,
     is_close::IsClose,
-    opacity_tables::{AllTables, ConstMetalTables, RTempTable},
+    opacity_tables::{ConstMetalTables, RTempTable},
     state::{CstCompoState, CstMetalState},
+    units::{LOG_R_OFFSET, LOG_R_TEMP_COEF},
+    zip::try_zip_map,
 };
 
+#[cfg(feature = "rayon")]
+use ndarray::Zip;
+
+#[cfg(feature = "rayon")]
+use crate::zip::try_collect;
+
+#[cfg(feature = "embedded-tables")]
+use crate::cache;
+
+/// Convention relating the `log_r` axis of opacity tables to the density and
+/// temperature: `log_r = log_density + offset - temp_coef * log_temperature`.
+///
+/// The default is the OPAL definition `R = density / T6^3`, with `T6` the
+/// temperature in millions of Kelvins, see [`crate::units`].
+#[derive(Copy, Clone, Debug)]
+pub struct LogRConvention {
+    pub offset: f64,
+    pub temp_coef: f64,
+}
+
+impl Default for LogRConvention {
+    fn default() -> Self {
+        Self {
+            offset: LOG_R_OFFSET,
+            temp_coef: LOG_R_TEMP_COEF,
+        }
+    }
+}
+
+impl LogRConvention {
+    pub fn log_r(&self, log_density: f64, log_temperature: f64) -> f64 {
+        log_density + self.offset - self.temp_coef * log_temperature
+    }
+}
+
+/// Log opacity and log temperature of the elements of a state.
+type OpacityTemperature<D> = (Array<f64, D>, Array<f64, D>);
+
+/// Log opacity and its logarithmic derivatives with respect to the
+/// temperature and the density of the elements of a state.
+type OpacityDerivatives<D> = (Array<f64, D>, Array<f64, D>, Array<f64, D>);
+
+/// Clamp `log_kappa` to `log_floor` if any, NaN is left as is.
+fn apply_floor(log_kappa: f64, log_floor: Option<f64>) -> f64 {
+    match log_floor {
+        Some(floor) if log_kappa < floor => floor,
+        _ => log_kappa,
+    }
+}
+
 pub struct CstCompoOpacity<D: Dimension> {
     state: Arc<CstCompoState<D>>,
     table: RTempTable,
+    log_floor: Option<f64>,
+    log_r_convention: LogRConvention,
+    oob_policy: OobPolicy,
 }
 
 impl<D: Dimension> CstCompoOpacity<D> {
+    #[cfg(feature = "embedded-tables")]
     pub fn new(state: Arc<CstCompoState<D>>) -> Self {
-        let table = AllTables::default()
-            .take_at_metallicity(state.metallicity())
+        let table = cache::opacity_tables()
+            .at_metallicity(state.metallicity())
             .expect("metallicity is in range")
             .take_at_h_frac(state.h_frac())
             .expect("He fraction is in range");
-        Self { state, table }
+        Self {
+            state,
+            table,
+            log_floor: None,
+            log_r_convention: LogRConvention::default(),
+            oob_policy: OobPolicy::default(),
+        }
     }
 
     pub fn with_table(table: RTempTable, state: Arc<CstCompoState<D>>) -> Self {
         assert!(table.metallicity().is_close(state.metallicity()));
         assert!(table.h_frac().is_close(state.h_frac()));
-        Self { state, table }
+        Self {
+            state,
+            table,
+            log_floor: None,
+            log_r_convention: LogRConvention::default(),
+            oob_policy: OobPolicy::default(),
+        }
+    }
+
+    /// Table used for lookups, at the composition of the state.
+    pub fn table(&self) -> &RTempTable {
+        &self.table
+    }
+
+    /// Clamp the computed opacity to `floor` (in cgs units, not in log), e.g.
+    /// the electron scattering opacity.
+    pub fn with_floor(self, floor: f64) -> Self {
+        Self {
+            log_floor: Some(floor.log10()),
+            ..self
+        }
+    }
+
+    /// Use another convention to compute `log_r` from the state.
+    pub fn with_log_r_convention(self, log_r_convention: LogRConvention) -> Self {
+        Self {
+            log_r_convention,
+            ..self
+        }
+    }
+
+    /// Handle states outside of the tables according to `oob_policy`, in
+    /// both the EOS lookup of the temperature and the opacity lookup.
+    ///
+    /// With [`OobPolicy::Clamp`], a state outside of the EOS table gets the
+    /// temperature of the closest valid point, `log_r` is then computed from
+    /// the actual density and that temperature, and clamped again to the
+    /// opacity table if needed. With [`OobPolicy::Nan`], a NaN temperature
+    /// results in a NaN opacity, even with a floor.
+    pub fn with_oob_policy(self, oob_policy: OobPolicy) -> Self {
+        Self { oob_policy, ..self }
+    }
+
+    fn log_kappa_at(
+        &self,
+        log_density: f64,
+        log_temperature: f64,
+    ) -> Result<f64, OutOfBoundsError> {
+        let logr = self.log_r_convention.log_r(log_density, log_temperature);
+        let log_kappa = self
+            .table
+            .at_with_policy(log_temperature, logr, self.oob_policy)
+            .map_err(|e| e.with_state(log_density, log_temperature))?;
+        Ok(apply_floor(log_kappa, self.log_floor))
+    }
+
+    fn log_kappa_with_derivatives_at(
+        &self,
+        log_density: f64,
+        log_temperature: f64,
+    ) -> Result<(f64, f64, f64), OutOfBoundsError> {
+        let logr = self.log_r_convention.log_r(log_density, log_temperature);
+        let (log_kappa, dlogk_dlogt, dlogk_dlogr) = self
+            .oob_policy
+            .apply_or(
+                (log_temperature, logr),
+                |query| self.table.clamp(query),
+                |(logt, logr)| {
+                    let (log_kappa, dlogk_dlogt, dlogk_dlogr) =
+                        self.table.at_with_derivatives(logt, logr)?;
+                    let log_kappa = match self.table.method() {
+                        InterpolationMethod::Linear => log_kappa,
+                        _ => self.table.at(logt, logr)?,
+                    };
+                    Ok((log_kappa, dlogk_dlogt, dlogk_dlogr))
+                },
+                (f64::NAN, f64::NAN, f64::NAN),
+            )
+            .map_err(|e| e.with_state(log_density, log_temperature))?;
+        match self.log_floor {
+            Some(floor) if log_kappa < floor => Ok((floor, 0.0, 0.0)),
+            _ => Ok((
+                log_kappa,
+                dlogk_dlogt - self.log_r_convention.temp_coef * dlogk_dlogr,
+                dlogk_dlogr,
+            )),
+        }
     }
 
     pub fn log_opacity(&self) -> Array<f64, D> {
-        let logt = self.state.compute(StateVar::LogTemperature);
-        Zip::from(&logt)
-            .and(self.state.log_density())
-            .map_collect(|&logt, &logd| {
-                let logr = logd + 18.0 - 3.0 * logt;
-                self.table.at(logt, logr).expect("out of table")
-            })
+        self.try_log_opacity().expect("out of table")
+    }
+
+    /// Compute the opacity, failing if a state is outside of the table.
+    ///
+    /// The error records the density and temperature of the offending state
+    /// (see [`OutOfBoundsError::state`]) since the `log_r` looked up in the
+    /// table is derived from them.
+    pub fn try_log_opacity(&self) -> Result<Array<f64, D>, OutOfBoundsError> {
+        self.try_log_opacity_with_temperature()
+            .map(|(log_kappa, _)| log_kappa)
+    }
+
+    /// Compute the opacity together with the log temperature of the state it
+    /// is looked up at, `(log_opacity, log_temperature)`.
+    ///
+    /// The temperature is computed once, with the out of bounds policy of
+    /// the opacity.
+    pub fn log_opacity_with_temperature(&self) -> OpacityTemperature<D> {
+        self.try_log_opacity_with_temperature()
+            .expect("out of table")
+    }
+
+    /// Same as [`CstCompoOpacity::log_opacity_with_temperature`], failing if a
+    /// state is outside of the table.
+    pub fn try_log_opacity_with_temperature(
+        &self,
+    ) -> Result<OpacityTemperature<D>, OutOfBoundsError> {
+        let logt = self
+            .state
+            .try_compute_with_policy(StateVar::LogTemperature, self.oob_policy)?;
+        let log_kappa = try_zip_map([logt.view(), self.state.log_density()], |[logt, logd]| {
+            self.log_kappa_at(logd, logt)
+        })?;
+        Ok((log_kappa, logt))
+    }
+
+    /// Compute the opacity together with its logarithmic derivatives,
+    /// `(log_opacity, (dln(kappa)/dln(T))_rho, (dln(kappa)/dln(rho))_T)`.
+    ///
+    /// The opacity is interpolated with the method of the table, as in
+    /// [`CstCompoOpacity::log_opacity`], while the derivatives are slopes
+    /// between nodes, see [`RTempTable::at_with_derivatives`]. With
+    /// [`OobPolicy::Clamp`], the derivatives are those at the clamped point.
+    /// They are zero where the opacity is clamped to the floor.
+    pub fn log_opacity_with_derivatives(&self) -> OpacityDerivatives<D> {
+        self.try_log_opacity_with_derivatives()
+            .expect("out of table")
+    }
+
+    /// Same as [`CstCompoOpacity::log_opacity_with_derivatives`], failing if
+    /// a state is outside of the table.
+    pub fn try_log_opacity_with_derivatives(
+        &self,
+    ) -> Result<OpacityDerivatives<D>, OutOfBoundsError> {
+        let logt = self
+            .state
+            .try_compute_with_policy(StateVar::LogTemperature, self.oob_policy)?;
+        let values = try_zip_map([logt.view(), self.state.log_density()], |[logt, logd]| {
+            self.log_kappa_with_derivatives_at(logd, logt)
+        })?;
+        Ok((
+            values.map(|values| values.0),
+            values.map(|values| values.1),
+            values.map(|values| values.2),
+        ))
+    }
+
+    /// Compute the opacity, distributing the work over threads.
+    #[cfg(feature = "rayon")]
+    pub fn par_log_opacity(&self) -> Array<f64, D> {
+        self.try_par_log_opacity().expect("out of table")
+    }
+
+    /// Same as [`CstCompoOpacity::par_log_opacity`], failing if a state is
+    /// outside of the table.
+    #[cfg(feature = "rayon")]
+    pub fn try_par_log_opacity(&self) -> Result<Array<f64, D>, OutOfBoundsError> {
+        let logt = match self.oob_policy {
+            OobPolicy::Error => self.state.try_par_compute(StateVar::LogTemperature)?,
+            policy => self
+                .state
+                .try_compute_with_policy(StateVar::LogTemperature, policy)?,
+        };
+        try_collect(
+            Zip::from(&logt)
+                .and(self.state.log_density())
+                .par_map_collect(|&logt, &logd| self.log_kappa_at(logd, logt)),
+        )
+    }
+
+    /// Derivative of the log opacity with respect to the log density at
+    /// constant temperature, see [`RTempTable::dlogkappa_dlogrho_fixed_t`].
+    ///
+    /// This is zero where the opacity is clamped to the floor.
+    pub fn dlogkappa_dlogrho(&self) -> Array<f64, D> {
+        self.try_dlogkappa_dlogrho().expect("out of table")
+    }
+
+    /// Same as [`CstCompoOpacity::dlogkappa_dlogrho`], handling states
+    /// outside of the table according to the out of bounds policy.
+    pub fn try_dlogkappa_dlogrho(&self) -> Result<Array<f64, D>, OutOfBoundsError> {
+        self.try_log_opacity_with_derivatives()
+            .map(|(_, _, dlogk_dlogrho)| dlogk_dlogrho)
     }
 }
 
 pub struct CstMetalOpacity<D: Dimension> {
     state: Arc<CstMetalState<D>>,
     table: ConstMetalTables,
+    log_floor: Option<f64>,
+    log_r_convention: LogRConvention,
+    oob_policy: OobPolicy,
 }
 
 impl<D: Dimension> CstMetalOpacity<D> {
+    #[cfg(feature = "embedded-tables")]
     pub fn new(state: Arc<CstMetalState<D>>) -> Self {
-        let table = AllTables::default()
-            .take_at_metallicity(state.metallicity())
+        let table = cache::opacity_tables()
+            .at_metallicity(state.metallicity())
             .expect("metallicity is in range");
-        Self { state, table }
+        Self {
+            state,
+            table,
+            log_floor: None,
+            log_r_convention: LogRConvention::default(),
+            oob_policy: OobPolicy::default(),
+        }
     }
 
     pub fn with_table(table: ConstMetalTables, state: Arc<CstMetalState<D>>) -> Self {
         assert!(table.metallicity().is_close(state.metallicity()));
-        Self { state, table }
+        Self {
+            state,
+            table,
+            log_floor: None,
+            log_r_convention: LogRConvention::default(),
+            oob_policy: OobPolicy::default(),
+        }
+    }
+
+    /// Clamp the computed opacity to `floor` (in cgs units, not in log), e.g.
+    /// the electron scattering opacity.
+    pub fn with_floor(self, floor: f64) -> Self {
+        Self {
+            log_floor: Some(floor.log10()),
+            ..self
+        }
+    }
+
+    /// Use another convention to compute `log_r` from the state.
+    pub fn with_log_r_convention(self, log_r_convention: LogRConvention) -> Self {
+        Self {
+            log_r_convention,
+            ..self
+        }
+    }
+
+    /// Handle states outside of the tables according to `oob_policy`, in
+    /// both the EOS lookup of the temperature and the opacity lookup.
+    ///
+    /// With [`OobPolicy::Clamp`], a state outside of the EOS table gets the
+    /// temperature of the closest valid point, `log_r` is then computed from
+    /// the actual density and that temperature, and clamped again to the
+    /// opacity table if needed. With [`OobPolicy::Nan`], a NaN temperature
+    /// results in a NaN opacity, even with a floor.
+    pub fn with_oob_policy(self, oob_policy: OobPolicy) -> Self {
+        Self { oob_policy, ..self }
+    }
+
+    fn log_kappa_at(
+        &self,
+        h_frac: f64,
+        log_density: f64,
+        log_temperature: f64,
+    ) -> Result<f64, OutOfBoundsError> {
+        let logr = self.log_r_convention.log_r(log_density, log_temperature);
+        let log_kappa = self
+            .table
+            .at_with_policy(h_frac, log_temperature, logr, self.oob_policy)
+            .map_err(|e| e.with_state(log_density, log_temperature))?;
+        Ok(apply_floor(log_kappa, self.log_floor))
     }
 
     pub fn log_opacity(&self) -> Array<f64, D> {
-        let logt = self.state.compute(StateVar::LogTemperature);
-        Zip::from(&logt)
-            .and(self.state.log_density())
-            .and(self.state.h_frac())
-            .map_collect(|&logt, &logd, &h_frac| {
-                let logr = logd + 18.0 - 3.0 * logt;
-                self.table.at(h_frac, logt, logr).expect("out of table")
+        self.try_log_opacity().expect("out of table")
+    }
+
+    /// Compute the opacity, failing if a state is outside of the table, see
+    /// [`CstCompoOpacity::try_log_opacity`].
+    pub fn try_log_opacity(&self) -> Result<Array<f64, D>, OutOfBoundsError> {
+        let logt = self
+            .state
+            .try_compute_with_policy(StateVar::LogTemperature, self.oob_policy)?;
+        self.try_log_opacity_at(logt.view())
+    }
+
+    /// Compute the opacity from the already known temperature of the state,
+    /// e.g. when it is also needed for other purposes.
+    pub fn try_log_opacity_at(
+        &self,
+        log_temperature: ArrayView<'_, f64, D>,
+    ) -> Result<Array<f64, D>, OutOfBoundsError> {
+        try_zip_map(
+            [
+                log_temperature.view(),
+                self.state.log_density(),
+                self.state.h_frac(),
+            ],
+            |[logt, logd, h_frac]| self.log_kappa_at(h_frac, logd, logt),
+        )
+    }
+
+    fn dlogkappa_dx_at(
+        &self,
+        h_frac: f64,
+        log_density: f64,
+        log_temperature: f64,
+    ) -> Result<f64, OutOfBoundsError> {
+        let logr = self.log_r_convention.log_r(log_density, log_temperature);
+        let policy = self.oob_policy;
+        let floored = match self.log_floor {
+            Some(floor) => {
+                self.table
+                    .at_with_policy(h_frac, log_temperature, logr, policy)
+                    .map_err(|e| e.with_state(log_density, log_temperature))?
+                    < floor
+            }
+            None => false,
+        };
+        if floored {
+            return Ok(0.0);
+        }
+        self.table
+            .dlogkappa_dh_frac_with_policy(h_frac, log_temperature, logr, policy)
+            .map_err(|e| e.with_state(log_density, log_temperature))
+    }
+
+    /// Derivative of the log opacity with respect to the hydrogen fraction,
+    /// consistent with the interpolation of [`CstMetalOpacity::log_opacity`],
+    /// see [`ConstMetalTables::dlogkappa_dh_frac`].
+    ///
+    /// This is zero where the opacity is clamped to the floor.
+    pub fn dlogkappa_dx(&self) -> Array<f64, D> {
+        self.try_dlogkappa_dx().expect("out of table")
+    }
+
+    /// Same as [`CstMetalOpacity::dlogkappa_dx`], handling states outside of
+    /// the tables according to the out of bounds policy.
+    pub fn try_dlogkappa_dx(&self) -> Result<Array<f64, D>, OutOfBoundsError> {
+        let logt = self
+            .state
+            .try_compute_with_policy(StateVar::LogTemperature, self.oob_policy)?;
+        try_zip_map(
+            [logt.view(), self.state.log_density(), self.state.h_frac()],
+            |[logt, logd, h_frac]| self.dlogkappa_dx_at(h_frac, logd, logt),
+        )
+    }
+
+    /// Compute the opacity, distributing the work over threads.
+    #[cfg(feature = "rayon")]
+    pub fn par_log_opacity(&self) -> Array<f64, D> {
+        self.try_par_log_opacity().expect("out of table")
+    }
+
+    /// Same as [`CstMetalOpacity::par_log_opacity`], failing if a state is
+    /// outside of the tables.
+    #[cfg(feature = "rayon")]
+    pub fn try_par_log_opacity(&self) -> Result<Array<f64, D>, OutOfBoundsError> {
+        let logt = match self.oob_policy {
+            OobPolicy::Error => self.state.try_par_compute(StateVar::LogTemperature)?,
+            policy => self
+                .state
+                .try_compute_with_policy(StateVar::LogTemperature, policy)?,
+        };
+        try_collect(
+            Zip::from(&logt)
+                .and(self.state.log_density())
+                .and(self.state.h_frac())
+                .par_map_collect(|&logt, &logd, &h_frac| self.log_kappa_at(h_frac, logd, logt)),
+        )
+    }
+}
+
+#[cfg(all(test, feature = "embedded-tables"))]
+mod tests {
+    use std::sync::Arc;
+
+    use ndarray::arr1;
+
+    use crate::{
+        eos_tables::{AllTables, StateVar},
+        grid::{InterpolationMethod, OobPolicy},
+        is_close::IsClose,
+        state::{CstCompoState, CstMetalState},
+    };
+
+    use super::{CstCompoOpacity, CstMetalOpacity, LogRConvention};
+
+    #[test]
+    fn log_r_out_of_table() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_he_frac(0.28)
+            .unwrap();
+        let density = arr1(&[3.5, 10.3]);
+        let energy = arr1(&[5.7e14, 4.5e15]);
+        let state = Arc::new(CstCompoState::new(
+            table.into(),
+            density.view(),
+            energy.view(),
+        ));
+        assert!(CstCompoOpacity::new(state.clone())
+            .try_log_opacity()
+            .is_ok());
+        let err = CstCompoOpacity::new(state)
+            .with_log_r_convention(LogRConvention {
+                offset: 50.0,
+                ..Default::default()
             })
+            .try_log_opacity()
+            .unwrap_err();
+        assert_eq!(err.axis(), Some("log_r"));
+        let (logd, _) = err.state().unwrap();
+        assert!((logd - 3.5_f64.log10()).abs() < 1e-12);
+        assert!(err.to_string().contains("log_temperature"));
+    }
+
+    #[test]
+    fn opacity_from_known_temperature() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let he_frac = arr1(&[0.28, 0.3]);
+        let density = arr1(&[3.5, 10.3]);
+        let energy = arr1(&[5.7e14, 4.5e15]);
+        let state = Arc::new(CstMetalState::new(
+            table.into(),
+            he_frac.view(),
+            density.view(),
+            energy.view(),
+        ));
+        let logt = state.compute(StateVar::LogTemperature);
+        let opacity = CstMetalOpacity::new(state);
+        assert_eq!(
+            opacity.try_log_opacity_at(logt.view()).unwrap(),
+            opacity.log_opacity()
+        );
+    }
+
+    #[test]
+    fn oob_policy_across_eos_and_opacity() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_he_frac(0.28)
+            .unwrap();
+        let density = arr1(&[3.5, 3.5]);
+        let energy = arr1(&[5.7e14, 1e30]);
+        let state = Arc::new(CstCompoState::new(
+            table.into(),
+            density.view(),
+            energy.view(),
+        ));
+        assert!(CstCompoOpacity::new(state.clone())
+            .try_log_opacity()
+            .is_err());
+        let nan = CstCompoOpacity::new(state.clone())
+            .with_floor(1e-3)
+            .with_oob_policy(OobPolicy::Nan)
+            .log_opacity();
+        assert!(nan[0].is_finite());
+        assert!(nan[1].is_nan());
+        let clamped = CstCompoOpacity::new(state.clone())
+            .with_oob_policy(OobPolicy::Clamp)
+            .log_opacity();
+        assert_eq!(clamped[0], nan[0]);
+        assert!(clamped[1].is_finite());
+
+        // the state is in the EOS table but log_r is above the opacity table
+        let logt = state
+            .try_compute_with_policy(StateVar::LogTemperature, OobPolicy::Nan)
+            .unwrap();
+        let opacity = CstCompoOpacity::new(state.clone()).with_log_r_convention(LogRConvention {
+            offset: 50.0,
+            ..Default::default()
+        });
+        let max_log_r = opacity.table().grid().y().last();
+        let expected = opacity.table().at(logt[0], max_log_r).unwrap();
+        let clamped = opacity.with_oob_policy(OobPolicy::Clamp).log_opacity();
+        assert_eq!(clamped[0], expected);
+        assert!(clamped[1].is_finite());
+        let nan = CstCompoOpacity::new(state)
+            .with_log_r_convention(LogRConvention {
+                offset: 50.0,
+                ..Default::default()
+            })
+            .with_oob_policy(OobPolicy::Nan)
+            .log_opacity();
+        assert!(nan.iter().all(|v| v.is_nan()));
+    }
+
+    #[test]
+    fn density_derivative_matches_finite_difference() {
+        let table = Arc::new(
+            AllTables::default()
+                .take_at_metallicity(0.02)
+                .unwrap()
+                .take_at_he_frac(0.28)
+                .unwrap(),
+        );
+        let density = arr1(&[3.5, 10.3]);
+        let energy = arr1(&[5.7e14, 4.5e15]);
+        let state = Arc::new(CstCompoState::new(table, density.view(), energy.view()));
+        let opacity = CstCompoOpacity::new(state.clone());
+        let deriv = opacity.dlogkappa_dlogrho();
+        let logt = state.compute(StateVar::LogTemperature);
+        let convention = LogRConvention::default();
+        for i in 0..2 {
+            let logd = state.log_density()[i];
+            let step = 1e-4;
+            let at = |logd| {
+                opacity
+                    .table()
+                    .at(logt[i], convention.log_r(logd, logt[i]))
+                    .unwrap()
+            };
+            let finite_diff = (at(logd + step) - at(logd - step)) / (2.0 * step);
+            assert!((deriv[i] - finite_diff).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn opacity_with_temperature() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_he_frac(0.28)
+            .unwrap();
+        let density = arr1(&[3.5, 10.3, 1e-6]);
+        let energy = arr1(&[5.7e14, 4.5e15, 1e12]);
+        let state = Arc::new(CstCompoState::new(
+            table.into(),
+            density.view(),
+            energy.view(),
+        ));
+        let logt = state.compute(StateVar::LogTemperature);
+        let opacity = CstCompoOpacity::new(state).with_floor(1e-3);
+        let (log_kappa, log_temperature) = opacity.log_opacity_with_temperature();
+        assert_eq!(log_temperature, logt);
+        assert_eq!(log_kappa, opacity.log_opacity());
+    }
+
+    #[test]
+    fn opacity_with_derivatives() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_he_frac(0.28)
+            .unwrap();
+        let density = arr1(&[3.5, 10.3, 1e-6]);
+        let energy = arr1(&[5.7e14, 4.5e15, 1e12]);
+        let state = Arc::new(CstCompoState::new(
+            table.into(),
+            density.view(),
+            energy.view(),
+        ));
+        let logt = state.compute(StateVar::LogTemperature);
+        let opacity = CstCompoOpacity::new(state.clone()).with_floor(1e-3);
+        let (log_kappa, dlogk_dlogt, dlogk_dlogrho) = opacity.log_opacity_with_derivatives();
+        assert_eq!(log_kappa, opacity.log_opacity());
+        assert_eq!(dlogk_dlogrho, opacity.dlogkappa_dlogrho());
+        let convention = LogRConvention::default();
+        for i in 0..3 {
+            let logd = state.log_density()[i];
+            let logr = convention.log_r(logd, logt[i]);
+            let (_, dt_r, dr_t) = opacity.table().at_with_derivatives(logt[i], logr).unwrap();
+            if log_kappa[i] > (1e-3_f64).log10() {
+                assert!(dlogk_dlogt[i].is_close(dt_r - 3.0 * dr_t));
+            } else {
+                assert_eq!(dlogk_dlogt[i], 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn opacity_with_derivatives_follows_method() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_he_frac(0.28)
+            .unwrap();
+        let density = arr1(&[3.5, 10.3]);
+        let energy = arr1(&[5.7e14, 4.5e15]);
+        let state = Arc::new(CstCompoState::new(
+            table.into(),
+            density.view(),
+            energy.view(),
+        ));
+        let linear = CstCompoOpacity::new(state.clone());
+        let cubic_table = linear
+            .table()
+            .clone()
+            .with_method(InterpolationMethod::Cubic);
+        let cubic = CstCompoOpacity::with_table(cubic_table, state);
+        let (log_kappa, dlogk_dlogt, dlogk_dlogrho) = cubic.log_opacity_with_derivatives();
+        assert_eq!(log_kappa, cubic.log_opacity());
+        assert_ne!(log_kappa, linear.log_opacity());
+        let (_, lin_dlogt, lin_dlogrho) = linear.log_opacity_with_derivatives();
+        assert_eq!(dlogk_dlogt, lin_dlogt);
+        assert_eq!(dlogk_dlogrho, lin_dlogrho);
+    }
+
+    #[test]
+    fn h_frac_derivative_follows_oob_policy() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let he_frac = arr1(&[0.28, 0.28]);
+        let density = arr1(&[3.5, 3.5]);
+        let energy = arr1(&[5.7e14, 1e30]);
+        let state = Arc::new(CstMetalState::new(
+            table.into(),
+            he_frac.view(),
+            density.view(),
+            energy.view(),
+        ));
+        let opacity = CstMetalOpacity::new(state.clone());
+        assert!(opacity.try_dlogkappa_dx().is_err());
+        let nan = opacity
+            .with_floor(1e-3)
+            .with_oob_policy(OobPolicy::Nan)
+            .try_dlogkappa_dx()
+            .unwrap();
+        assert!(nan[0].is_finite());
+        assert!(nan[1].is_nan());
+        let clamped = CstMetalOpacity::new(state)
+            .with_oob_policy(OobPolicy::Clamp)
+            .try_dlogkappa_dx()
+            .unwrap();
+        assert_eq!(clamped[0], nan[0]);
+        assert!(clamped[1].is_finite());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_opacity_reports_errors() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let he_frac = arr1(&[0.28, 0.28]);
+        let density = arr1(&[3.5, 3.5]);
+        let energy = arr1(&[5.7e14, 1e30]);
+        let state = Arc::new(CstMetalState::new(
+            table.into(),
+            he_frac.view(),
+            density.view(),
+            energy.view(),
+        ));
+        assert!(CstMetalOpacity::new(state.clone())
+            .try_par_log_opacity()
+            .is_err());
+        let opacity = CstMetalOpacity::new(state).with_oob_policy(OobPolicy::Nan);
+        let parallel = opacity.try_par_log_opacity().unwrap();
+        assert_eq!(parallel[0], opacity.log_opacity()[0]);
+        assert!(parallel[1].is_nan());
+    }
+
+    #[test]
+    fn density_derivative_follows_oob_policy() {
+        let table = Arc::new(
+            AllTables::default()
+                .take_at_metallicity(0.02)
+                .unwrap()
+                .take_at_he_frac(0.28)
+                .unwrap(),
+        );
+        let density = arr1(&[3.5, 3.5]);
+        let energy = arr1(&[5.7e14, 1e30]);
+        let state = Arc::new(CstCompoState::new(table, density.view(), energy.view()));
+        let opacity = CstCompoOpacity::new(state.clone());
+        assert!(opacity.try_dlogkappa_dlogrho().is_err());
+        let nan = opacity
+            .with_oob_policy(OobPolicy::Nan)
+            .try_dlogkappa_dlogrho()
+            .unwrap();
+        assert!(nan[0].is_finite());
+        assert!(nan[1].is_nan());
+        let clamped = CstCompoOpacity::new(state)
+            .with_oob_policy(OobPolicy::Clamp)
+            .try_dlogkappa_dlogrho()
+            .unwrap();
+        assert_eq!(clamped[0], nan[0]);
+        assert!(clamped[1].is_finite());
     }
 }
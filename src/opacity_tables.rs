@@ -1,111 +1,420 @@
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 
-use ndarray::{s, Array2, Array3, Array4, ArrayView2, ArrayView3, Axis};
+use ndarray::{s, Array1, Array2, Array3, ArrayView2, ArrayView3, Axis};
 
 use crate::{
-    fort_unfmt::read_fort_record,
-    index::{CustomRange, IdxLin, Indexable, LinearInterpolable, OutOfBoundsError, Range},
-    interp::{lin_interp_2d, LinearInterpolator, LinearStencil},
-    raw_tables::opacity::{RawOpacityTable, RAW_TABLES},
+    fort_unfmt::{read_fort_record, read_fort_record_widened},
+    grid::{Grid2D, InterpolationMethod, LookupMode, OobPolicy, ShapeError, SplineValidRanges},
+    index::{
+        CustomRange, IdxLin, Indexable, LinearInterpolable, MetalAxis, OutOfBoundsError, Range,
+    },
+    interp::{lin_interp_2d, LinearInterpolator, LinearStencil, SplineStencil},
+    opacity::LogRConvention,
 };
 
+#[cfg(feature = "embedded-tables")]
+use crate::raw_tables::opacity::{RawOpacityTable, RAW_TABLES};
+
+#[cfg(feature = "async")]
+use crate::fort_unfmt::read_raw_record_async;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
 /// The full opacity table.
 pub struct AllTables {
-    metallicities: CustomRange,
-    h_fracs: Range,
-    log_temperature: Range,
-    log_r: Range,
-    values: Array4<f64>,
+    metallicities: MetalAxis<CustomRange>,
+    tables: Vec<ConstMetalTables>,
+    metadata: Vec<u8>,
+}
+
+/// Check that an array of `shape` fits in memory.
+fn check_size(shape: &[usize]) -> io::Result<()> {
+    let n_values = shape
+        .iter()
+        .try_fold(1_usize, |acc, &n| acc.checked_mul(n))
+        .filter(|&n| n <= isize::MAX as usize / std::mem::size_of::<f64>());
+    if n_values.is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("opacity table shape {shape:?} is too large"),
+        ));
+    }
+    Ok(())
+}
+
+/// Decode the shape record, as `nz`, `nx`, `nt`, `nr` (the file stores `nr`
+/// before `nt`).
+fn read_shape<R: Read>(reader: R) -> io::Result<[usize; 4]> {
+    let mut shape = [0_u32; 4];
+    read_fort_record(reader, &mut shape)?;
+    shape.swap(2, 3);
+    let shape = shape.map(|e| e as usize);
+    check_size(&shape)?;
+    Ok(shape)
+}
+
+/// Decode the record of the number of hydrogen fractions at each
+/// metallicity, for tables with a ragged hydrogen fraction grid.
+fn read_n_h_fracs<R: Read>(reader: R, shape: [usize; 4]) -> io::Result<Vec<usize>> {
+    let mut n_h_fracs = vec![0_u32; shape[0]];
+    read_fort_record(reader, &mut n_h_fracs)?;
+    let n_h_fracs: Vec<_> = n_h_fracs.into_iter().map(|n| n as usize).collect();
+    check_size(&[n_h_fracs.iter().sum(), shape[2], shape[3]])?;
+    Ok(n_h_fracs)
+}
+
+fn metallicity_axis(z_range: &[f64]) -> io::Result<MetalAxis<CustomRange>> {
+    Ok(if let [single] = z_range[..] {
+        MetalAxis::Single(single)
+    } else {
+        MetalAxis::Grid(CustomRange::new(z_range.to_vec())?)
+    })
+}
+
+/// Check that the hydrogen fractions at adjacent metallicities overlap,
+/// which is necessary to interpolate between them.
+fn check_h_frac_overlaps(z_range: &[f64], h_fracs: &[Range]) -> io::Result<()> {
+    for (z_pair, pair) in z_range.windows(2).zip(h_fracs.windows(2)) {
+        if pair[0].subrange_in(pair[1]).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "hydrogen fractions at metallicities {} and {} don't overlap",
+                    z_pair[0], z_pair[1]
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Opacities along log R at the given hydrogen fraction and temperature
+/// indices, to be filled from a single record.
+fn log_r_slice(values: &mut Array3<f64>, i_x: usize, i_t: usize) -> io::Result<&mut [f64]> {
+    values
+        .slice_mut(s![i_x, i_t, ..])
+        .into_slice()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "opacity values are not contiguous along log R",
+            )
+        })
 }
 
 fn read_range<R: Read>(reader: &mut R, size: usize) -> io::Result<Range> {
     let mut range_vals = vec![0.0; size];
-    read_fort_record(reader, &mut range_vals)?;
-    Range::from_slice(&range_vals).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    read_fort_record_widened(reader, &mut range_vals)?;
+    Ok(Range::from_slice(&range_vals)?)
 }
 
-impl AllTables {
-    fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
-        let mut shape = [0_u32; 4]; // nz, nx, nt, nr
-        read_fort_record(&mut reader, &mut shape)?;
-        shape.swap(2, 3); // nr, nt in file header
-        let shape = shape.map(|e| e as usize);
-
-        let mut z_range = vec![0.0; shape[0]];
-        read_fort_record(&mut reader, &mut z_range)?;
-        let metallicities = CustomRange::new(z_range).unwrap();
-
-        let h_fracs = read_range(&mut reader, shape[1])?;
-        let log_temperature = read_range(&mut reader, shape[2])?;
-        let log_r = read_range(&mut reader, shape[3])?;
-
-        let mut values = Array4::zeros(shape);
-        for i_z in 0..metallicities.n_values() {
-            for i_x in 0..h_fracs.n_values() {
-                for i_t in 0..log_temperature.n_values() {
-                    let mut slc = values.slice_mut(s![i_z, i_x, i_t, ..]);
-                    let raw_slc = slc.as_slice_mut().expect("values should be contiguous");
-                    read_fort_record(&mut reader, raw_slc)?;
+/// Next record expected by a [`TablesDecoder`].
+#[derive(Clone, Copy)]
+enum Step {
+    Shape,
+    Metallicities,
+    NHFracs,
+    HFracs,
+    LogTemperature,
+    LogR {
+        log_temperature: Range,
+    },
+    Values {
+        log_temperature: Range,
+        log_r: Range,
+        i_x: usize,
+        i_t: usize,
+    },
+    Done,
+}
+
+/// Decoder of the records of [`AllTables`], fed one record at a time.
+///
+/// [`AllTables::read_from`] and [`AllTables::read_from_async`] share this
+/// decoder and only differ in how they get the records.
+struct TablesDecoder {
+    step: Step,
+    shape: [usize; 4],
+    z_range: Vec<f64>,
+    metallicities: Option<MetalAxis<CustomRange>>,
+    n_h_fracs: Vec<usize>,
+    h_fracs: Vec<Range>,
+    values: Array3<f64>,
+    tables: Vec<ConstMetalTables>,
+}
+
+impl TablesDecoder {
+    fn new() -> Self {
+        Self {
+            step: Step::Shape,
+            shape: [0; 4],
+            z_range: Vec::new(),
+            metallicities: None,
+            n_h_fracs: Vec::new(),
+            h_fracs: Vec::new(),
+            values: Array3::zeros((0, 0, 0)),
+            tables: Vec::new(),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        matches!(self.step, Step::Done)
+    }
+
+    /// Decode the next record, read from `record`.
+    fn decode_record<R: Read>(&mut self, mut record: R) -> io::Result<()> {
+        self.step = match self.step {
+            Step::Shape => {
+                self.shape = read_shape(record)?;
+                Step::Metallicities
+            }
+            Step::Metallicities => {
+                self.z_range = vec![0.0; self.shape[0]];
+                read_fort_record_widened(record, &mut self.z_range)?;
+                self.metallicities = Some(metallicity_axis(&self.z_range)?);
+                if self.shape[1] == 0 {
+                    Step::NHFracs
+                } else {
+                    Step::HFracs
                 }
             }
+            Step::NHFracs => {
+                self.n_h_fracs = read_n_h_fracs(record, self.shape)?;
+                Step::HFracs
+            }
+            Step::HFracs => {
+                if self.shape[1] == 0 {
+                    let nx = self.n_h_fracs[self.h_fracs.len()];
+                    self.h_fracs.push(read_range(&mut record, nx)?);
+                } else {
+                    self.h_fracs = vec![read_range(&mut record, self.shape[1])?; self.shape[0]];
+                }
+                if self.h_fracs.len() < self.shape[0] {
+                    Step::HFracs
+                } else {
+                    check_h_frac_overlaps(&self.z_range, &self.h_fracs)?;
+                    Step::LogTemperature
+                }
+            }
+            Step::LogTemperature => Step::LogR {
+                log_temperature: read_range(&mut record, self.shape[2])?,
+            },
+            Step::LogR { log_temperature } => {
+                let log_r = read_range(&mut record, self.shape[3])?;
+                #[cfg(feature = "log")]
+                log::debug!(
+                    "opacity table axes: h_fracs {:?}, log_temperature {:?}, log_r {:?}",
+                    self.h_fracs.iter().map(Range::describe).collect::<Vec<_>>(),
+                    log_temperature.describe(),
+                    log_r.describe()
+                );
+                self.next_table(log_temperature, log_r)
+            }
+            Step::Values {
+                log_temperature,
+                log_r,
+                i_x,
+                i_t,
+            } => {
+                read_fort_record_widened(record, log_r_slice(&mut self.values, i_x, i_t)?)?;
+                let (i_x, i_t) = if i_t + 1 < log_temperature.n_values() {
+                    (i_x, i_t + 1)
+                } else {
+                    (i_x + 1, 0)
+                };
+                if i_x < self.values.len_of(Axis(0)) {
+                    Step::Values {
+                        log_temperature,
+                        log_r,
+                        i_x,
+                        i_t,
+                    }
+                } else {
+                    let i_z = self.tables.len();
+                    let values = std::mem::replace(&mut self.values, Array3::zeros((0, 0, 0)));
+                    self.tables.push(ConstMetalTables {
+                        metallicity: self.z_range[i_z],
+                        h_fracs: self.h_fracs[i_z],
+                        log_temperature,
+                        log_r,
+                        values,
+                    });
+                    self.next_table(log_temperature, log_r)
+                }
+            }
+            Step::Done => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "opacity tables are already decoded",
+                ))
+            }
+        };
+        Ok(())
+    }
+
+    /// Allocate the values of the next table, or finish decoding after the
+    /// last one.
+    fn next_table(&mut self, log_temperature: Range, log_r: Range) -> Step {
+        match self.h_fracs.get(self.tables.len()) {
+            Some(h_fracs) => {
+                self.values = Array3::zeros((
+                    h_fracs.n_values(),
+                    log_temperature.n_values(),
+                    log_r.n_values(),
+                ));
+                Step::Values {
+                    log_temperature,
+                    log_r,
+                    i_x: 0,
+                    i_t: 0,
+                }
+            }
+            None => Step::Done,
         }
+    }
 
-        Ok(Self {
-            metallicities,
-            h_fracs,
-            log_temperature,
-            log_r,
-            values,
-        })
+    fn finish(self, metadata: Vec<u8>) -> AllTables {
+        AllTables {
+            metallicities: self
+                .metallicities
+                .expect("metallicities are decoded before the values"),
+            tables: self.tables,
+            metadata,
+        }
+    }
+}
+
+impl AllTables {
+    /// Decode tables from their raw binary representation.
+    ///
+    /// The content is a sequence of Fortran unformatted records: the shape
+    /// (`nz`, `nx`, `nr`, `nt` as `u32`), the metallicities, the hydrogen
+    /// fractions, the log temperatures, the log R, and then one record of
+    /// opacities along log R per metallicity, hydrogen fraction, and
+    /// temperature. Floating point records can be either `f64` or `f32`.
+    ///
+    /// When `nx` is 0, the hydrogen fractions differ between metallicities:
+    /// they are given by a record of the number of hydrogen fractions at
+    /// each metallicity (as `u32`) followed by one record of hydrogen
+    /// fractions per metallicity. Those at adjacent metallicities must
+    /// overlap.
+    ///
+    /// Malformed content, e.g. a shape that doesn't match the records that
+    /// follow or axes that are not increasing, is reported as an
+    /// [`io::ErrorKind::InvalidData`] error. Reading stops after the last
+    /// record, any trailing bytes are kept as [`AllTables::metadata`].
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut decoder = TablesDecoder::new();
+        while !decoder.is_done() {
+            decoder.decode_record(&mut reader)?;
+        }
+        let mut metadata = Vec::new();
+        reader.read_to_end(&mut metadata)?;
+        Ok(decoder.finish(metadata))
+    }
+
+    /// Same as [`AllTables::read_from`], reading records from an async
+    /// source. Records are decoded as they arrive.
+    #[cfg(feature = "async")]
+    pub async fn read_from_async<R: AsyncRead + Unpin>(mut reader: R) -> io::Result<Self> {
+        let mut decoder = TablesDecoder::new();
+        while !decoder.is_done() {
+            let record = read_raw_record_async(&mut reader).await?;
+            decoder.decode_record(record.as_slice())?;
+        }
+        let mut metadata = Vec::new();
+        reader.read_to_end(&mut metadata).await?;
+        Ok(decoder.finish(metadata))
+    }
+
+    /// Bytes found after the last record when reading the tables.
+    pub fn metadata(&self) -> &[u8] {
+        &self.metadata
+    }
+
+    /// Same as [`AllTables::take_at_metallicity`], without consuming the
+    /// tables. This copies the values.
+    pub fn at_metallicity(&self, metallicity: f64) -> Result<ConstMetalTables, OutOfBoundsError> {
+        match self
+            .metallicities
+            .idx_lin(metallicity)
+            .map_err(|e| e.on("metallicity"))?
+        {
+            IdxLin::Exact(i) => Ok(self.tables[i].clone()),
+            IdxLin::Between(i, j) => Ok(ConstMetalTables::between(
+                &self.tables[i],
+                &self.tables[j],
+                metallicity,
+            )),
+        }
     }
 
+    /// Tables at `metallicity`, interpolated between the surrounding
+    /// metallicities if needed.
+    ///
+    /// Between metallicities with different hydrogen fractions, the tables
+    /// are defined on the hydrogen fractions of the lower metallicity that
+    /// lie within those of the higher one, see [`Range::subrange_in`].
     pub fn take_at_metallicity(
-        self,
+        mut self,
         metallicity: f64,
     ) -> Result<ConstMetalTables, OutOfBoundsError> {
-        match self.metallicities.idx_lin(metallicity)? {
-            IdxLin::Exact(i) => Ok(ConstMetalTables {
+        match self
+            .metallicities
+            .idx_lin(metallicity)
+            .map_err(|e| e.on("metallicity"))?
+        {
+            // the tables hold the grid node, the requested value might be
+            // marginally outside of the grid
+            IdxLin::Exact(i) => Ok(self.tables.swap_remove(i)),
+            IdxLin::Between(i, j) => Ok(ConstMetalTables::between(
+                &self.tables[i],
+                &self.tables[j],
                 metallicity,
-                h_fracs: self.h_fracs,
-                log_temperature: self.log_temperature,
-                log_r: self.log_r,
-                values: self.values.index_axis_move(Axis(0), i),
-            }),
-            IdxLin::Between(i, j) => {
-                let lin = LinearInterpolator::new(
-                    self.metallicities.at(i),
-                    self.metallicities.at(j),
-                    metallicity,
-                );
-                let values = lin.interp(
-                    self.values.index_axis(Axis(0), i),
-                    self.values.index_axis(Axis(0), j),
-                );
-                Ok(ConstMetalTables {
-                    metallicity,
-                    h_fracs: self.h_fracs,
-                    log_temperature: self.log_temperature,
-                    log_r: self.log_r,
-                    values,
-                })
-            }
+            )),
         }
     }
 }
 
+#[cfg(feature = "embedded-tables")]
 impl From<&RawOpacityTable> for AllTables {
     fn from(rawtbl: &RawOpacityTable) -> Self {
         Self::read_from(rawtbl.0).expect("raw tables are well-formed")
     }
 }
 
+#[cfg(feature = "embedded-tables")]
 impl Default for AllTables {
     fn default() -> Self {
         (&RAW_TABLES).into()
     }
 }
 
+fn temperature_stencil(
+    log_temperature: Range,
+    value: f64,
+) -> Result<LinearStencil, OutOfBoundsError> {
+    log_temperature
+        .linear_stencil(value)
+        .map_err(|e| e.on("log_temperature"))
+}
+
+fn r_stencil(log_r: Range, value: f64) -> Result<LinearStencil, OutOfBoundsError> {
+    log_r.linear_stencil(value).map_err(|e| e.on("log_r"))
+}
+
+/// Nodes between which slopes are taken for `stencil`: the nodes surrounding
+/// the query, or the neighbours of the node it lies on (its only neighbour
+/// at the edges of an axis of `n_values` nodes).
+fn slope_nodes(stencil: LinearStencil, n_values: usize) -> (usize, usize) {
+    match stencil {
+        LinearStencil::Exact { i, .. } => (i.saturating_sub(1), (i + 1).min(n_values - 1)),
+        LinearStencil::Between { ileft, iright, .. } => (ileft, iright),
+    }
+}
+
 /// Opacity table at constant metallicity.
+#[derive(Clone)]
 pub struct ConstMetalTables {
     metallicity: f64,
     h_fracs: Range,
@@ -115,55 +424,210 @@ pub struct ConstMetalTables {
 }
 
 impl ConstMetalTables {
-    pub fn take_at_h_frac(self, h_frac: f64) -> Result<RTempTable, OutOfBoundsError> {
-        match self.h_fracs.idx_lin(h_frac)? {
-            IdxLin::Exact(i) => Ok(RTempTable {
-                metallicity: self.metallicity,
-                h_frac,
-                log_temperature: self.log_temperature,
-                log_r: self.log_r,
-                values: self.values.index_axis_move(Axis(0), i),
-            }),
-            IdxLin::Between(i, j) => {
-                let lin = LinearInterpolator::new(self.h_fracs.at(i), self.h_fracs.at(j), h_frac);
-                let values = lin.interp(
-                    self.values.index_axis(Axis(0), i),
-                    self.values.index_axis(Axis(0), j),
-                );
-                Ok(RTempTable {
-                    metallicity: self.metallicity,
-                    h_frac,
-                    log_temperature: self.log_temperature,
-                    log_r: self.log_r,
-                    values,
-                })
+    /// Tables at `metallicity`, interpolated between `left` and `right` on
+    /// the hydrogen fractions of `left` within those of `right`.
+    fn between(left: &Self, right: &Self, metallicity: f64) -> Self {
+        let overlap = left
+            .h_fracs
+            .subrange_in(right.h_fracs)
+            .expect("hydrogen fractions should overlap");
+        let lin = LinearInterpolator::new(left.metallicity, right.metallicity, metallicity);
+        let first = overlap.self_offset;
+        let mut values = left
+            .values
+            .slice(s![first..first + overlap.range.n_values(), .., ..])
+            .to_owned();
+        for (mut slab, h_frac) in values.outer_iter_mut().zip(overlap.range) {
+            match right
+                .h_fracs
+                .linear_stencil(h_frac)
+                .expect("hydrogen fraction should be in the right tables")
+            {
+                LinearStencil::Exact { i, .. } => {
+                    lin.interp_into(&mut slab, right.values.index_axis(Axis(0), i));
+                }
+                LinearStencil::Between {
+                    ileft,
+                    iright,
+                    lin: h_frac_lin,
+                } => {
+                    let right_slab = h_frac_lin.interp(
+                        right.values.index_axis(Axis(0), ileft),
+                        right.values.index_axis(Axis(0), iright),
+                    );
+                    lin.interp_into(&mut slab, right_slab.view());
+                }
             }
         }
+        Self {
+            metallicity,
+            h_fracs: overlap.range,
+            log_temperature: left.log_temperature,
+            log_r: left.log_r,
+            values,
+        }
+    }
+
+    /// Stencil along the hydrogen fraction axis. This is a cubic spline
+    /// where possible, falling back to linear interpolation near the edges of
+    /// the grid or if there are too few hydrogen fractions.
+    fn h_frac_stencil(&self, h_frac: f64) -> Result<CompoStencil, OutOfBoundsError> {
+        let lin = self
+            .h_fracs
+            .linear_stencil(h_frac)
+            .map_err(|e| e.on("h_frac"))?;
+        if let LinearStencil::Between { .. } = lin {
+            if let Ok(spline) = self.h_fracs.spline_stencil(h_frac) {
+                return Ok(CompoStencil::Spline(spline));
+            }
+        }
+        Ok(CompoStencil::Linear(lin))
+    }
+
+    /// Derivative of the log opacity with respect to the hydrogen fraction.
+    ///
+    /// This is the derivative of the cubic spline used by
+    /// [`ConstMetalTables::at`] where it is available.  Near the edges of the
+    /// grid, it is the slope of the linear interpolation, which on a node is
+    /// the slope between its two neighbours, or with its only neighbour at
+    /// the edges of the grid.
+    pub fn dlogkappa_dh_frac(
+        &self,
+        h_frac: f64,
+        log_temperature: f64,
+        log_r: f64,
+    ) -> Result<f64, OutOfBoundsError> {
+        let logt_st = temperature_stencil(self.log_temperature, log_temperature)?;
+        let logr_st = r_stencil(self.log_r, log_r)?;
+        let at_node = |k| lin_interp_2d(logt_st, logr_st, self.values.index_axis(Axis(0), k));
+        if let Ok(spline) = self.h_fracs.spline_stencil(h_frac) {
+            return Ok(spline.derivative(spline.nodes.map(at_node)));
+        }
+        let h_frac_st = self
+            .h_fracs
+            .linear_stencil(h_frac)
+            .map_err(|e| e.on("h_frac"))?;
+        let (i, j) = slope_nodes(h_frac_st, self.h_fracs.n_values());
+        Ok((at_node(j) - at_node(i)) / (self.h_fracs.at(j) - self.h_fracs.at(i)))
+    }
+
+    pub fn take_at_h_frac(self, h_frac: f64) -> Result<RTempTable, OutOfBoundsError> {
+        let values = match self.h_frac_stencil(h_frac)? {
+            CompoStencil::Linear(LinearStencil::Exact { i, .. }) => {
+                self.values.index_axis_move(Axis(0), i)
+            }
+            CompoStencil::Linear(LinearStencil::Between { ileft, iright, lin }) => lin.interp(
+                self.values.index_axis(Axis(0), ileft),
+                self.values.index_axis(Axis(0), iright),
+            ),
+            CompoStencil::Spline(spline) => {
+                let mut values = Array2::zeros(self.values.index_axis(Axis(0), 0).raw_dim());
+                for (i, weight) in spline.weights().into_iter().enumerate() {
+                    values.scaled_add(weight, &self.values.index_axis(Axis(0), spline.nodes[i]));
+                }
+                values
+            }
+        };
+        let grid = Grid2D::new(self.log_temperature, self.log_r, values)
+            .expect("values should match the axes")
+            .with_axis_names("log_temperature", "log_r");
+        Ok(RTempTable {
+            metallicity: self.metallicity,
+            h_frac,
+            grid,
+            method: InterpolationMethod::Linear,
+        })
+    }
+
+    pub fn take_at_he_frac(self, he_frac: f64) -> Result<RTempTable, OutOfBoundsError> {
+        let metal = self.metallicity();
+        self.take_at_h_frac(1.0 - he_frac - metal)
     }
 
     pub fn metallicity(&self) -> f64 {
         self.metallicity
     }
 
-    pub fn values(&self) -> ArrayView3<f64> {
+    pub fn h_fracs(&self) -> Range {
+        self.h_fracs
+    }
+
+    pub fn log_temperature(&self) -> Range {
+        self.log_temperature
+    }
+
+    pub fn log_r(&self) -> Range {
+        self.log_r
+    }
+
+    /// Log opacity, indexed by hydrogen fraction, log temperature and log R.
+    pub fn values(&self) -> ArrayView3<'_, f64> {
         self.values.view()
     }
 
+    /// Same as [`ConstMetalTables::at`], queries outside of the tables are
+    /// handled according to `policy`. Each coordinate is clamped to its
+    /// axis independently.
+    pub fn at_with_policy(
+        &self,
+        h_frac: f64,
+        log_temperature: f64,
+        log_r: f64,
+        policy: OobPolicy,
+    ) -> Result<f64, OutOfBoundsError> {
+        policy.apply(
+            (h_frac, log_temperature, log_r),
+            |query| self.clamp(query),
+            |(h_frac, logt, logr)| self.at(h_frac, logt, logr),
+        )
+    }
+
+    /// Same as [`ConstMetalTables::dlogkappa_dh_frac`], handling points
+    /// outside of the table according to `policy`.
+    pub fn dlogkappa_dh_frac_with_policy(
+        &self,
+        h_frac: f64,
+        log_temperature: f64,
+        log_r: f64,
+        policy: OobPolicy,
+    ) -> Result<f64, OutOfBoundsError> {
+        policy.apply(
+            (h_frac, log_temperature, log_r),
+            |query| self.clamp(query),
+            |(h_frac, logt, logr)| self.dlogkappa_dh_frac(h_frac, logt, logr),
+        )
+    }
+
+    /// Closest point of the table to `(h_frac, log_temperature, log_r)`.
+    fn clamp(&self, (h_frac, logt, logr): (f64, f64, f64)) -> (f64, f64, f64) {
+        (
+            self.h_fracs.clamp(h_frac),
+            self.log_temperature.clamp(logt),
+            self.log_r.clamp(logr),
+        )
+    }
+
     pub fn at(
         &self,
         h_frac: f64,
         log_temperature: f64,
         log_r: f64,
     ) -> Result<f64, OutOfBoundsError> {
-        let logt_st = self.log_temperature.linear_stencil(log_temperature)?;
-        let logr_st = self.log_r.linear_stencil(log_r)?;
-        match self.h_fracs.linear_stencil(h_frac)? {
-            LinearStencil::Exact { i, .. } => Ok(lin_interp_2d(
+        let logt_st = temperature_stencil(self.log_temperature, log_temperature)?;
+        let logr_st = r_stencil(self.log_r, log_r)?;
+        // fixed composition sweeps usually land on a node of the grid, the
+        // table at that node is then used directly.
+        if let IdxLin::Exact(i) = self.h_fracs.idx_lin(h_frac).map_err(|e| e.on("h_frac"))? {
+            let table = self.values().index_axis_move(Axis(0), i);
+            return Ok(lin_interp_2d(logt_st, logr_st, table));
+        }
+        match self.h_frac_stencil(h_frac)? {
+            CompoStencil::Linear(LinearStencil::Exact { i, .. }) => Ok(lin_interp_2d(
                 logt_st,
                 logr_st,
                 self.values().index_axis_move(Axis(0), i),
             )),
-            LinearStencil::Between { ileft, iright, lin } => {
+            CompoStencil::Linear(LinearStencil::Between { ileft, iright, lin }) => {
                 let mut ltable = self.values().index_axis_move(Axis(0), ileft);
                 let mut rtable = self.values().index_axis_move(Axis(0), iright);
                 logt_st.slice_view(Axis(0), &mut ltable);
@@ -173,20 +637,51 @@ impl ConstMetalTables {
                 let table = lin.interp(ltable, rtable);
                 Ok(lin_interp_2d(logt_st, logr_st, table.view()))
             }
+            CompoStencil::Spline(spline) => {
+                let values_at_nodes = std::array::from_fn(|i| {
+                    let table = self.values().index_axis_move(Axis(0), spline.nodes[i]);
+                    lin_interp_2d(logt_st, logr_st, table)
+                });
+                Ok(spline.interp(values_at_nodes))
+            }
         }
     }
 }
 
+enum CompoStencil {
+    Linear(LinearStencil),
+    Spline(SplineStencil),
+}
+
 /// Opacity table at constant metallicity and helium fraction.
+#[derive(Clone)]
 pub struct RTempTable {
     metallicity: f64,
     h_frac: f64,
-    log_temperature: Range,
-    log_r: Range,
-    values: Array2<f64>,
+    grid: Grid2D,
+    method: InterpolationMethod,
 }
 
 impl RTempTable {
+    /// Build a table from its components, `values` are the log opacities
+    /// indexed by log temperature and log R.
+    pub fn from_parts(
+        metallicity: f64,
+        h_frac: f64,
+        log_temperature: Range,
+        log_r: Range,
+        values: Array2<f64>,
+    ) -> Result<Self, ShapeError> {
+        let grid = Grid2D::new(log_temperature, log_r, values)?
+            .with_axis_names("log_temperature", "log_r");
+        Ok(Self {
+            metallicity,
+            h_frac,
+            grid,
+            method: InterpolationMethod::Linear,
+        })
+    }
+
     pub fn metallicity(&self) -> f64 {
         self.metallicity
     }
@@ -195,15 +690,672 @@ impl RTempTable {
         self.h_frac
     }
 
-    pub fn values(&self) -> ArrayView2<f64> {
-        self.values.view()
+    pub fn log_temperature(&self) -> Range {
+        self.grid.x()
+    }
+
+    pub fn log_r(&self) -> Range {
+        self.grid.y()
+    }
+
+    /// Intervals of log temperature and log R in which cubic interpolation
+    /// is possible, see [`Range::spline_valid_range`].
+    pub fn spline_valid_ranges(&self) -> SplineValidRanges {
+        (
+            self.log_temperature().spline_valid_range(),
+            self.log_r().spline_valid_range(),
+        )
+    }
+
+    /// Tabulated log opacity, indexed by log temperature and log R.
+    pub fn values(&self) -> ArrayView2<'_, f64> {
+        self.grid.values()
+    }
+
+    /// Tabulated log opacity at the node of index `i_t` on the log
+    /// temperature axis and `i_r` on the log R axis, without interpolation.
+    ///
+    /// # Panics
+    ///
+    /// If an index is out of its axis.
+    pub fn node_value(&self, i_t: usize, i_r: usize) -> f64 {
+        self.values()[[i_t, i_r]]
+    }
+
+    /// Write the table as CSV, with a header row
+    /// (`log_temperature,log_r,log_kappa`) and then one row per node of the
+    /// table, with log R varying fastest.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "log_temperature,log_r,log_kappa")?;
+        for (i_t, logt) in self.grid.x().into_iter().enumerate() {
+            for (i_r, logr) in self.grid.y().into_iter().enumerate() {
+                writeln!(writer, "{logt},{logr},{}", self.node_value(i_t, i_r))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The underlying grid, indexed by log temperature and log R.
+    pub fn grid(&self) -> &Grid2D {
+        &self.grid
+    }
+
+    /// Use `method` for interpolation in the table, the default is
+    /// [`InterpolationMethod::Linear`].
+    pub fn with_method(self, method: InterpolationMethod) -> Self {
+        Self { method, ..self }
+    }
+
+    pub fn method(&self) -> InterpolationMethod {
+        self.method
     }
 
     pub fn at(&self, log_temperature: f64, log_r: f64) -> Result<f64, OutOfBoundsError> {
-        Ok(lin_interp_2d(
-            self.log_temperature.linear_stencil(log_temperature)?,
-            self.log_r.linear_stencil(log_r)?,
-            self.values(),
-        ))
+        match self.method {
+            InterpolationMethod::Nearest => {
+                self.grid
+                    .lookup(log_temperature, log_r, LookupMode::Nearest)
+            }
+            InterpolationMethod::Linear => self.grid.at(log_temperature, log_r),
+            InterpolationMethod::Cubic => self.grid.at_cubic(log_temperature, log_r),
+        }
+    }
+
+    /// Derivative of the log opacity with respect to the log density at
+    /// constant temperature, `(dln(kappa)/dln(rho))_T`.
+    ///
+    /// Since `log_r = log_density - 3 log_temperature + 18`, this is the
+    /// derivative with respect to `log_r`, computed as the slope between the
+    /// log R nodes surrounding `log_r` (interpolated linearly in
+    /// temperature). On a node of the grid, it is the slope between its two
+    /// neighbours, or with its only neighbour at the edges of the grid.
+    pub fn dlogkappa_dlogrho_fixed_t(
+        &self,
+        log_temperature: f64,
+        log_r: f64,
+    ) -> Result<f64, OutOfBoundsError> {
+        let (_, _, dlogk_dlogr) = self.at_with_derivatives(log_temperature, log_r)?;
+        Ok(dlogk_dlogr)
+    }
+
+    /// Bilinear interpolation of the log opacity together with its
+    /// derivatives along both axes of the table,
+    /// `(log_kappa, (dln(kappa)/dln(T))_R, (dln(kappa)/dln(rho))_T)`.
+    ///
+    /// The stencils are built once for the value and both derivatives.  The
+    /// derivatives are slopes between nodes, as in
+    /// [`RTempTable::dlogkappa_dlogrho_fixed_t`].  The derivative with
+    /// respect to the temperature is at constant `log_r`, at constant
+    /// density it is `(dln(kappa)/dln(T))_R - 3 (dln(kappa)/dln(rho))_T`.
+    pub fn at_with_derivatives(
+        &self,
+        log_temperature: f64,
+        log_r: f64,
+    ) -> Result<(f64, f64, f64), OutOfBoundsError> {
+        let (log_temperatures, log_rs) = (self.grid.x(), self.grid.y());
+        let logt_st = temperature_stencil(log_temperatures, log_temperature)?;
+        let logr_st = r_stencil(log_rs, log_r)?;
+        let values = self.values();
+        let log_kappa = lin_interp_2d(logt_st, logr_st, values);
+
+        let (i, j) = slope_nodes(logt_st, log_temperatures.n_values());
+        let at_logt = |k| logr_st.apply_to(values.index_axis(Axis(0), k));
+        let dlogk_dlogt =
+            (at_logt(j) - at_logt(i)) / (log_temperatures.at(j) - log_temperatures.at(i));
+
+        let (i, j) = slope_nodes(logr_st, log_rs.n_values());
+        let at_logr = |k| logt_st.apply_to(values.index_axis(Axis(1), k));
+        let dlogk_dlogr = (at_logr(j) - at_logr(i)) / (log_rs.at(j) - log_rs.at(i));
+
+        Ok((log_kappa, dlogk_dlogt, dlogk_dlogr))
+    }
+
+    /// Log opacity at constant `log_density` along `log_temperatures`, with
+    /// `log_r` following the default [`LogRConvention`].
+    ///
+    /// Points outside of the table are handled according to `policy`.
+    pub fn opacity_vs_temperature(
+        &self,
+        log_density: f64,
+        log_temperatures: Range,
+        policy: OobPolicy,
+    ) -> Result<Array1<f64>, OutOfBoundsError> {
+        let convention = LogRConvention::default();
+        log_temperatures
+            .into_iter()
+            .map(|logt| {
+                let logr = convention.log_r(log_density, logt);
+                self.at_with_policy(logt, logr, policy)
+                    .map_err(|e| e.with_state(log_density, logt))
+            })
+            .collect()
+    }
+
+    /// Same as [`RTempTable::at`], queries outside of the table are handled
+    /// according to `policy`. Each coordinate is clamped to its axis
+    /// independently (away from the first and last nodes with
+    /// [`InterpolationMethod::Cubic`]).
+    pub fn at_with_policy(
+        &self,
+        log_temperature: f64,
+        log_r: f64,
+        policy: OobPolicy,
+    ) -> Result<f64, OutOfBoundsError> {
+        policy.apply(
+            (log_temperature, log_r),
+            |query| self.clamp(query),
+            |(logt, logr)| self.at(logt, logr),
+        )
+    }
+
+    /// Closest point to `(log_temperature, log_r)` at which the table can be
+    /// interpolated with its method.
+    pub(crate) fn clamp(&self, (logt, logr): (f64, f64)) -> (f64, f64) {
+        let domain = |axis: Range| match self.method {
+            InterpolationMethod::Cubic => axis.spline_domain().unwrap_or(axis),
+            _ => axis,
+        };
+        (
+            domain(self.grid.x()).clamp(logt),
+            domain(self.grid.y()).clamp(logr),
+        )
+    }
+
+    /// Look up the opacity with the given `mode`, see [`Grid2D::lookup`].
+    pub fn lookup(
+        &self,
+        log_temperature: f64,
+        log_r: f64,
+        mode: LookupMode,
+    ) -> Result<f64, OutOfBoundsError> {
+        self.grid.lookup(log_temperature, log_r, mode)
+    }
+
+    /// Interpolate at each `(log_temperatures[i], log_rs[i])` with the
+    /// interpolation method of the table, see [`Grid2D::at_batch`].
+    ///
+    /// # Panics
+    ///
+    /// If `log_temperatures` and `log_rs` don't have the same length.
+    pub fn at_batch(
+        &self,
+        log_temperatures: &[f64],
+        log_rs: &[f64],
+    ) -> Result<Vec<f64>, OutOfBoundsError> {
+        match self.method {
+            InterpolationMethod::Nearest => {
+                assert_eq!(log_temperatures.len(), log_rs.len());
+                log_temperatures
+                    .iter()
+                    .zip(log_rs)
+                    .map(|(&logt, &logr)| self.grid.lookup(logt, logr, LookupMode::Nearest))
+                    .collect()
+            }
+            InterpolationMethod::Linear => self.grid.at_batch(log_temperatures, log_rs),
+            InterpolationMethod::Cubic => self.grid.at_cubic_batch(log_temperatures, log_rs),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "embedded-tables"))]
+mod tests {
+    use std::io;
+
+    use crate::{
+        fort_unfmt::write_fort_record, grid::LookupMode, index::Indexable, is_close::IsClose,
+    };
+
+    use super::{AllTables, ConstMetalTables, InterpolationMethod, OobPolicy, RTempTable, Range};
+
+    #[test]
+    fn metallicity_marginally_outside_endpoints() {
+        let above_last = AllTables::default()
+            .take_at_metallicity(0.1 + 5e-13)
+            .expect("metallicity is close to the last grid point");
+        assert!(above_last.metallicity().is_close(0.1));
+        let below_first = AllTables::default()
+            .take_at_metallicity(-5e-13)
+            .expect("metallicity is close to the first grid point");
+        assert!(below_first.metallicity().is_close(0.0));
+        assert!(AllTables::default()
+            .take_at_metallicity(0.1 + 1e-6)
+            .is_err());
+    }
+
+    #[test]
+    fn he_frac_consistent_with_h_frac() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .expect("metallicity is in range");
+        let from_he = table
+            .take_at_he_frac(0.28)
+            .expect("helium fraction is in range");
+        assert!(from_he.h_frac().is_close(0.7));
+    }
+
+    #[test]
+    fn h_frac_derivative_continuity() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .expect("metallicity is in range");
+        let (logt, logr) = (5.03, -3.1);
+        let kappa = |h_frac| table.at(h_frac, logt, logr).expect("in range");
+        let eps = 1e-6;
+        let left_deriv = (kappa(0.4) - kappa(0.4 - eps)) / eps;
+        let right_deriv = (kappa(0.4 + eps) - kappa(0.4)) / eps;
+        assert!((left_deriv - right_deriv).abs() < 1e-4);
+    }
+
+    #[test]
+    fn h_frac_interp_consistency() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .expect("metallicity is in range");
+        let (logt, logr) = (5.03, -3.1);
+        let direct = table.at(0.33, logt, logr).expect("in range");
+        let full_interp = table
+            .take_at_h_frac(0.33)
+            .expect("in range")
+            .at(logt, logr)
+            .expect("in range");
+        assert!(direct.is_close(full_interp));
+    }
+
+    #[test]
+    fn tables_are_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<AllTables>();
+        assert_send_sync::<ConstMetalTables>();
+        assert_send_sync::<RTempTable>();
+    }
+
+    #[test]
+    fn batch_matches_pointwise() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .expect("metallicity is in range")
+            .take_at_h_frac(0.7)
+            .expect("hydrogen fraction is in range");
+        let logts = [3.0, 4.5125, 6.7, 8.7];
+        let logrs = [-8.0, -3.1, 0.8, 2.9];
+        let batch = table.at_batch(&logts, &logrs).expect("points are in range");
+        for ((&logt, &logr), value) in logts.iter().zip(&logrs).zip(batch) {
+            assert!(value.is_close(table.at(logt, logr).unwrap()));
+        }
+        let err = table.at_batch(&logts, &[0.0, 0.0, 0.0, 3.5]).unwrap_err();
+        assert_eq!(err.axis(), Some("log_r"));
+        let err = table
+            .at_batch(&logts, &[0.0, f64::NAN, 0.0, 1.0])
+            .unwrap_err();
+        assert_eq!(err.axis(), Some("log_r"));
+
+        let (logts, logrs) = ([3.0, 4.5125, 6.7], [-7.5, -3.1, 0.8]);
+        for method in [InterpolationMethod::Nearest, InterpolationMethod::Cubic] {
+            let table = table.clone().with_method(method);
+            let batch = table.at_batch(&logts, &logrs).expect("points are in range");
+            for ((&logt, &logr), value) in logts.iter().zip(&logrs).zip(batch) {
+                assert!(value.is_close(table.at(logt, logr).unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    fn single_metallicity_tables() {
+        let h_fracs = [0.0, 0.5, 1.0];
+        let log_temperature = [4.0, 5.0, 6.0];
+        let log_r = [-2.0, -1.0, 0.0, 1.0];
+        let mut raw = Vec::new();
+        write_fort_record(&mut raw, &[1_u32, 3, 4, 3]).unwrap();
+        write_fort_record(&mut raw, &[0.02]).unwrap();
+        write_fort_record(&mut raw, &h_fracs).unwrap();
+        write_fort_record(&mut raw, &log_temperature).unwrap();
+        write_fort_record(&mut raw, &log_r).unwrap();
+        for h_frac in h_fracs {
+            for logt in log_temperature {
+                write_fort_record(&mut raw, &log_r.map(|logr| h_frac + logt + logr)).unwrap();
+            }
+        }
+        let footer = b"source: synthetic";
+        raw.extend_from_slice(footer);
+        let tables = AllTables::read_from(raw.as_slice()).expect("synthetic table is well-formed");
+        assert_eq!(tables.metadata(), footer);
+        let err = AllTables::read_from(raw.as_slice())
+            .unwrap()
+            .take_at_metallicity(0.03)
+            .err()
+            .unwrap();
+        assert_eq!(err.axis(), Some("metallicity"));
+        let table = tables
+            .take_at_metallicity(0.02)
+            .expect("metallicity is the tabulated one")
+            .take_at_h_frac(0.5)
+            .expect("hydrogen fraction is in range");
+        assert!(table.at(4.5, -0.5).unwrap().is_close(4.5));
+    }
+
+    #[test]
+    fn nearest_lookup() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .expect("metallicity is in range")
+            .take_at_h_frac(0.7)
+            .expect("hydrogen fraction is in range");
+        let node = table.at(5.0, -3.0).unwrap();
+        let nearest = table.lookup(5.01, -2.95, LookupMode::Nearest).unwrap();
+        assert_eq!(nearest, node);
+        let interpolated = table.lookup(5.01, -2.95, LookupMode::Interpolated).unwrap();
+        assert!((interpolated - nearest).abs() < 0.1);
+        assert!(table.lookup(8.7, 3.0, LookupMode::Nearest).is_ok());
+        assert!(table.lookup(8.8, 3.0, LookupMode::Nearest).is_err());
+    }
+
+    /// Run a future that never waits, such as reads from an in-memory
+    /// buffer, without an async runtime.
+    #[cfg(feature = "async")]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::task::{Context, Poll, Waker};
+        let mut future = std::pin::pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("future is not ready"),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn read_async() {
+        let mut raw = Vec::new();
+        write_fort_record(&mut raw, &[2_u32, 2, 2, 3]).unwrap();
+        write_fort_record(&mut raw, &[0.0_f32, 0.02]).unwrap();
+        write_fort_record(&mut raw, &[0.0, 1.0]).unwrap();
+        write_fort_record(&mut raw, &[4.0, 5.0, 6.0]).unwrap();
+        write_fort_record(&mut raw, &[-1.0, 0.0]).unwrap();
+        for i in 0..12 {
+            write_fort_record(&mut raw, &[i as f32, 2.0 * i as f32]).unwrap();
+        }
+        let sync = AllTables::read_from(raw.as_slice()).unwrap();
+        let from_async = block_on(AllTables::read_from_async(raw.as_slice())).unwrap();
+        for (sync, from_async) in sync.tables.iter().zip(&from_async.tables) {
+            assert!(sync.h_fracs.is_close(from_async.h_fracs));
+            assert_eq!(sync.values, from_async.values);
+        }
+        assert!(block_on(AllTables::read_from_async(&raw[..raw.len() - 4])).is_err());
+    }
+
+    #[test]
+    fn borrowed_metallicity() {
+        let tables = AllTables::default();
+        for metallicity in [0.02, 0.025] {
+            let borrowed = tables.at_metallicity(metallicity).unwrap();
+            let taken = AllTables::default()
+                .take_at_metallicity(metallicity)
+                .unwrap();
+            assert_eq!(borrowed.metallicity(), taken.metallicity());
+            assert_eq!(borrowed.values(), taken.values());
+        }
+    }
+
+    #[test]
+    fn h_frac_derivative() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let h_fracs = table.h_fracs;
+        let (logt, logr) = (5.3, -2.5);
+        let at = |h_frac| table.at(h_frac, logt, logr).unwrap();
+        let slope = |i: usize, j: usize| {
+            (at(h_fracs.at(j)) - at(h_fracs.at(i))) / (h_fracs.at(j) - h_fracs.at(i))
+        };
+        let mid_cell = 0.5 * (h_fracs.at(1) + h_fracs.at(2));
+        let eps = 1e-6;
+        let finite_diff = (at(mid_cell + eps) - at(mid_cell - eps)) / (2.0 * eps);
+        let deriv = table.dlogkappa_dh_frac(mid_cell, logt, logr).unwrap();
+        assert!((deriv - finite_diff).abs() < 1e-6 * deriv.abs().max(1.0));
+        let deriv = table.dlogkappa_dh_frac(h_fracs.at(2), logt, logr).unwrap();
+        assert!(deriv.is_close(slope(1, 3)));
+        let deriv = table.dlogkappa_dh_frac(h_fracs.at(0), logt, logr).unwrap();
+        assert!(deriv.is_close(slope(0, 1)));
+    }
+
+    #[test]
+    fn malformed_headers() {
+        let header = |shape: [u32; 4], metallicities: &[f64], h_fracs: &[f64]| {
+            let mut raw = Vec::new();
+            write_fort_record(&mut raw, &shape).unwrap();
+            write_fort_record(&mut raw, metallicities).unwrap();
+            write_fort_record(&mut raw, h_fracs).unwrap();
+            write_fort_record(&mut raw, &[4.0, 5.0]).unwrap();
+            write_fort_record(&mut raw, &[-1.0, 0.0]).unwrap();
+            raw
+        };
+        let invalid = |raw: Vec<u8>| {
+            let err = AllTables::read_from(raw.as_slice()).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        };
+        invalid(header([u32::MAX; 4], &[0.02], &[0.0, 1.0]));
+        invalid(header([0, 2, 2, 2], &[], &[0.0, 1.0]));
+        invalid(header([2, 2, 2, 2], &[0.02, 0.01], &[0.0, 1.0]));
+        invalid(header([1, 2, 2, 2], &[0.02], &[1.0, 0.0]));
+        invalid(header([1, 3, 2, 2], &[0.02], &[0.0, 1.0]));
+        let truncated = header([1, 2, 2, 2], &[0.02], &[0.0, 1.0]);
+        let err = AllTables::read_from(truncated.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn exact_node_values() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_h_frac(0.7)
+            .unwrap();
+        let (i_t, i_r) = (20, 5);
+        let logt = table.grid().x().at(i_t);
+        let logr = table.grid().y().at(i_r);
+        assert!(table
+            .node_value(i_t, i_r)
+            .is_close(table.at(logt, logr).unwrap()));
+    }
+
+    #[test]
+    fn opacity_along_temperature() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_h_frac(0.7)
+            .unwrap();
+        let log_temperatures = Range::linspace(4.0, 6.0, 5).unwrap();
+        let curve = table
+            .opacity_vs_temperature(-6.0, log_temperatures, OobPolicy::Error)
+            .unwrap();
+        assert_eq!(curve.len(), 5);
+        assert!(curve[2].is_close(table.at(5.0, -3.0).unwrap()));
+
+        let beyond = Range::linspace(4.0, 12.0, 5).unwrap();
+        assert!(table
+            .opacity_vs_temperature(-6.0, beyond, OobPolicy::Error)
+            .is_err());
+        let curve = table
+            .opacity_vs_temperature(-6.0, beyond, OobPolicy::Nan)
+            .unwrap();
+        assert!(curve[0].is_finite() && curve[4].is_nan());
+    }
+
+    #[test]
+    fn interpolation_method_dispatch() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_h_frac(0.7)
+            .unwrap();
+        assert_eq!(table.method(), InterpolationMethod::Linear);
+        let (logt, logr) = (5.03, -3.02);
+        let linear = table.at(logt, logr).unwrap();
+        let nearest = table.clone().with_method(InterpolationMethod::Nearest);
+        assert_eq!(
+            nearest.at(logt, logr).unwrap(),
+            table.lookup(logt, logr, LookupMode::Nearest).unwrap()
+        );
+        let cubic = table.with_method(InterpolationMethod::Cubic);
+        assert!((cubic.at(logt, logr).unwrap() - linear).abs() < 1e-2);
+        let first_logt = cubic.grid().x().first();
+        assert!(cubic.at(first_logt, logr).is_err());
+        assert!(cubic
+            .at_with_policy(first_logt, logr, OobPolicy::Clamp)
+            .is_ok());
+    }
+
+    #[test]
+    fn csv_export() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_h_frac(0.7)
+            .unwrap();
+        let mut csv = Vec::new();
+        table.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("log_temperature,log_r,log_kappa"));
+        let (n_t, n_r) = table.values().dim();
+        let rows: Vec<Vec<f64>> = lines
+            .map(|l| l.split(',').map(|v| v.parse().unwrap()).collect())
+            .collect();
+        assert_eq!(rows.len(), n_t * n_r);
+        assert!(rows.iter().all(|row| row.len() == 3));
+        assert_eq!(rows[n_r + 1][2], table.node_value(1, 1));
+    }
+
+    #[test]
+    fn exact_h_frac_fast_path() {
+        let all = AllTables::default();
+        let tables = all.at_metallicity(0.02).unwrap();
+        let h_node = tables.h_fracs().at(3);
+        let (logt, logr) = (5.37, -3.21);
+        let expected = all
+            .at_metallicity(0.02)
+            .unwrap()
+            .take_at_h_frac(h_node)
+            .unwrap()
+            .at(logt, logr)
+            .unwrap();
+        assert_eq!(tables.at(h_node, logt, logr).unwrap(), expected);
+        let nearby = tables.at(h_node + 1e-9, logt, logr).unwrap();
+        assert!((nearby - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn density_derivative_at_fixed_temperature() {
+        let log_temperature = Range::from_slice(&[4.0, 5.0, 6.0]).unwrap();
+        let log_r = Range::from_slice(&[-3.0, -2.0, -1.0, 0.0]).unwrap();
+        let values = ndarray::Array2::from_shape_fn((3, 4), |(i, j)| {
+            let (logt, logr) = (log_temperature.at(i), log_r.at(j));
+            0.5 * logt + logr * logr
+        });
+        let table = RTempTable::from_parts(0.02, 0.7, log_temperature, log_r, values).unwrap();
+        let deriv = table.dlogkappa_dlogrho_fixed_t(4.5, -1.5).unwrap();
+        assert!(deriv.is_close(-3.0));
+        // on a node, slope between its neighbours
+        let deriv = table.dlogkappa_dlogrho_fixed_t(5.0, -2.0).unwrap();
+        assert!(deriv.is_close(-4.0));
+        // at the edge, slope with its only neighbour
+        let deriv = table.dlogkappa_dlogrho_fixed_t(5.0, 0.0).unwrap();
+        assert!(deriv.is_close(-1.0));
+        let err = table.dlogkappa_dlogrho_fixed_t(5.0, 0.5).unwrap_err();
+        assert_eq!(err.axis(), Some("log_r"));
+    }
+
+    #[test]
+    fn metallicity_slab_memory() {
+        let all = AllTables::default();
+        let slab_len = all.tables[1].values.len();
+        let nodes = [
+            all.metallicities.at(1),
+            0.5 * (all.metallicities.at(1) + all.metallicities.at(2)),
+        ];
+        for metallicity in nodes {
+            // the buffer of the tables holds a single metallicity
+            let borrowed = all.at_metallicity(metallicity).unwrap();
+            assert_eq!(borrowed.values.into_raw_vec().len(), slab_len);
+            let taken = AllTables::default()
+                .take_at_metallicity(metallicity)
+                .unwrap();
+            assert_eq!(taken.values.into_raw_vec().len(), slab_len);
+        }
+    }
+
+    #[test]
+    fn value_and_derivatives_in_one_pass() {
+        let log_temperature = Range::from_slice(&[4.0, 5.0, 6.0]).unwrap();
+        let log_r = Range::from_slice(&[-3.0, -2.0, -1.0, 0.0]).unwrap();
+        let values = ndarray::Array2::from_shape_fn((3, 4), |(i, j)| {
+            let (logt, logr) = (log_temperature.at(i), log_r.at(j));
+            0.5 * logt * logt + 2.0 * logr
+        });
+        let table = RTempTable::from_parts(0.02, 0.7, log_temperature, log_r, values).unwrap();
+        let (log_kappa, dlogk_dlogt, dlogk_dlogr) = table.at_with_derivatives(4.5, -1.5).unwrap();
+        assert!(log_kappa.is_close(table.at(4.5, -1.5).unwrap()));
+        assert!(dlogk_dlogt.is_close(4.5));
+        assert!(dlogk_dlogr.is_close(2.0));
+        assert!(dlogk_dlogr.is_close(table.dlogkappa_dlogrho_fixed_t(4.5, -1.5).unwrap()));
+        // on a node, slope between its neighbours
+        let (log_kappa, dlogk_dlogt, _) = table.at_with_derivatives(5.0, -2.0).unwrap();
+        assert!(log_kappa.is_close(8.5));
+        assert!(dlogk_dlogt.is_close(5.0));
+        // at the edge, slope with its only neighbour
+        let (_, dlogk_dlogt, _) = table.at_with_derivatives(6.0, -2.0).unwrap();
+        assert!(dlogk_dlogt.is_close(5.5));
+        let err = table.at_with_derivatives(6.5, -2.0).unwrap_err();
+        assert_eq!(err.axis(), Some("log_temperature"));
+    }
+
+    #[test]
+    fn ragged_h_fracs() {
+        let metallicities = [0.0, 0.02];
+        let h_fracs: [&[f64]; 2] = [&[0.0, 0.25, 0.5, 0.75], &[0.2, 0.4, 0.6, 0.8, 1.0]];
+        let log_temperature = [4.0, 5.0, 6.0];
+        let log_r = [-2.0, -1.0, 0.0, 1.0];
+        let header = |h_fracs: [&[f64]; 2]| {
+            let mut raw = Vec::new();
+            write_fort_record(&mut raw, &[2_u32, 0, 4, 3]).unwrap();
+            write_fort_record(&mut raw, &metallicities).unwrap();
+            write_fort_record(&mut raw, &h_fracs.map(|h| h.len() as u32)).unwrap();
+            for h_fracs in h_fracs {
+                write_fort_record(&mut raw, h_fracs).unwrap();
+            }
+            write_fort_record(&mut raw, &log_temperature).unwrap();
+            write_fort_record(&mut raw, &log_r).unwrap();
+            raw
+        };
+        let mut raw = header(h_fracs);
+        for (metallicity, h_fracs) in metallicities.into_iter().zip(h_fracs) {
+            for &h_frac in h_fracs {
+                for logt in log_temperature {
+                    let values = log_r.map(|logr| 10.0 * metallicity + h_frac + logt + logr);
+                    write_fort_record(&mut raw, &values).unwrap();
+                }
+            }
+        }
+        let tables = AllTables::read_from(raw.as_slice()).expect("synthetic table is well-formed");
+
+        let at_node = tables.at_metallicity(0.02).unwrap();
+        assert_eq!(at_node.h_fracs().describe(), (0.2, 1.0, 0.2, 5));
+        assert!(at_node.at(0.6, 4.5, -0.5).unwrap().is_close(4.8));
+
+        // tables in between are on the nodes of the lower metallicity within
+        // the hydrogen fractions of the higher one
+        let between = tables.take_at_metallicity(0.01).unwrap();
+        assert!(between.h_fracs().is_close(Range::new(0.25, 0.25, 3)));
+        for h_frac in [0.25, 0.5, 0.75] {
+            let value = between.at(h_frac, 4.5, -0.5).unwrap();
+            assert!(value.is_close(0.1 + h_frac + 4.0));
+        }
+        assert_eq!(
+            between.at(0.1, 4.5, -0.5).unwrap_err().axis(),
+            Some("h_frac")
+        );
+
+        let disjoint = header([&[0.0, 0.1], &[0.5, 1.0]]);
+        let err = AllTables::read_from(disjoint.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
     }
 }
@@ -2,25 +2,32 @@ use std::io::{self, Read};
 
 use ndarray::{s, Array3};
 
-use crate::{fort_unfmt::read_fort_record, index::Range};
+use crate::{
+    fort_unfmt::{read_fort_record, read_fort_record_widened},
+    index::Range,
+};
 
+#[cfg(feature = "embedded-tables")]
 pub(crate) struct AllRawTables {
-    pub metallicities: Range,
     pub tables: &'static [MetalRawTables],
 }
 
+#[cfg(feature = "embedded-tables")]
 pub(crate) struct MetalRawTables {
+    pub metallicity: f64,
     pub h_fracs: Range,
     pub tables: &'static [RawTable],
 }
 
+#[cfg(feature = "embedded-tables")]
 pub(crate) struct RawTable(pub &'static [u8]);
 
+#[cfg(feature = "embedded-tables")]
 pub(crate) const RAW_TABLES: AllRawTables = AllRawTables {
-    metallicities: Range::new(0.0, 0.02, 3),
     tables: &[
         MetalRawTables {
-            h_fracs: Range::new(0.0, 1.0, 6),
+            metallicity: 0.0,
+            h_fracs: Range::new(0.0, 0.2, 6),
             tables: &[
                 RawTable(include_bytes!("output_DE_z0.00x0.00.bindata")),
                 RawTable(include_bytes!("output_DE_z0.00x0.20.bindata")),
@@ -31,6 +38,7 @@ pub(crate) const RAW_TABLES: AllRawTables = AllRawTables {
             ],
         },
         MetalRawTables {
+            metallicity: 0.02,
             h_fracs: Range::new(0.0, 0.2, 5),
             tables: &[
                 RawTable(include_bytes!("output_DE_z0.02x0.00.bindata")),
@@ -41,6 +49,7 @@ pub(crate) const RAW_TABLES: AllRawTables = AllRawTables {
             ],
         },
         MetalRawTables {
+            metallicity: 0.04,
             h_fracs: Range::new(0.0, 0.2, 5),
             tables: &[
                 RawTable(include_bytes!("output_DE_z0.04x0.00.bindata")),
@@ -60,27 +69,32 @@ pub(crate) struct RawTableContent {
 }
 
 impl RawTableContent {
-    fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+    pub(crate) fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
         let mut shape = [0_u32; 3]; // ne, nv, nvars
         read_fort_record(&mut reader, &mut shape)?;
         let shape = shape.map(|e| e as usize);
 
         let mut log_volume = vec![0.0; shape[1]];
-        read_fort_record(&mut reader, &mut log_volume)?;
-        let log_volume = Range::from_slice(&log_volume)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        read_fort_record_widened(&mut reader, &mut log_volume)?;
+        let log_volume = Range::from_slice(&log_volume)?;
 
         let mut log_energy = vec![0.0; shape[0]];
-        read_fort_record(&mut reader, &mut log_energy)?;
-        let log_energy = Range::from_slice(&log_energy)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        read_fort_record_widened(&mut reader, &mut log_energy)?;
+        let log_energy = Range::from_slice(&log_energy)?;
+
+        #[cfg(feature = "log")]
+        log::debug!(
+            "EOS table axes: log_volume {:?}, log_energy {:?}",
+            log_volume.describe(),
+            log_energy.describe()
+        );
 
         let mut values = Array3::zeros(shape);
         for i_v in 0..shape[1] {
             for i_e in 0..shape[0] {
                 let mut slc = values.slice_mut(s![i_e, i_v, ..]);
                 let raw_slc = slc.as_slice_mut().expect("values should be contiguous");
-                read_fort_record(&mut reader, raw_slc)?;
+                read_fort_record_widened(&mut reader, raw_slc)?;
             }
         }
 
@@ -92,6 +106,7 @@ impl RawTableContent {
     }
 }
 
+#[cfg(feature = "embedded-tables")]
 impl From<&RawTable> for RawTableContent {
     fn from(rawtbl: &RawTable) -> Self {
         Self::read_from(rawtbl.0).expect("raw tables are well-formed")
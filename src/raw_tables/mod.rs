@@ -1,2 +1,88 @@
+#[cfg(feature = "ndarray")]
 pub(crate) mod eos;
+#[cfg(feature = "embedded-tables")]
 pub(crate) mod opacity;
+
+#[cfg(feature = "embedded-tables")]
+use std::sync::OnceLock;
+
+/// FNV-1a hash of the concatenation of `chunks`.
+#[cfg_attr(not(feature = "embedded-tables"), allow(dead_code))]
+fn fnv1a<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    chunks
+        .into_iter()
+        .flatten()
+        .fold(OFFSET_BASIS, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        })
+}
+
+/// Raw content of each embedded EOS table.
+#[cfg(feature = "embedded-tables")]
+fn eos_chunks() -> impl Iterator<Item = &'static [u8]> {
+    eos::RAW_TABLES
+        .tables
+        .iter()
+        .flat_map(|metal| metal.tables.iter().map(|table| table.0))
+}
+
+/// Hash of the embedded EOS and opacity data, computed once.
+#[cfg(feature = "embedded-tables")]
+pub(crate) fn fingerprint() -> u64 {
+    static FINGERPRINT: OnceLock<u64> = OnceLock::new();
+    *FINGERPRINT.get_or_init(|| fnv1a(eos_chunks().chain([opacity::RAW_TABLES.0])))
+}
+
+/// Size of the embedded EOS data in bytes.
+#[cfg(feature = "embedded-tables")]
+pub(crate) fn eos_bytes() -> usize {
+    eos_chunks().map(<[u8]>::len).sum()
+}
+
+#[cfg(not(feature = "embedded-tables"))]
+pub(crate) fn eos_bytes() -> usize {
+    0
+}
+
+/// Size of the embedded opacity data in bytes.
+#[cfg(feature = "embedded-tables")]
+pub(crate) fn opacity_bytes() -> usize {
+    opacity::RAW_TABLES.0.len()
+}
+
+#[cfg(not(feature = "embedded-tables"))]
+pub(crate) fn opacity_bytes() -> usize {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fnv1a;
+
+    #[test]
+    fn fnv1a_reference_values() {
+        assert_eq!(fnv1a([]), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a([b"a".as_slice()]), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(
+            fnv1a([b"foo".as_slice(), b"bar".as_slice()]),
+            fnv1a([b"foobar".as_slice()])
+        );
+    }
+
+    #[cfg(feature = "embedded-tables")]
+    #[test]
+    fn fingerprint_is_stable() {
+        // changes only when the embedded data files change
+        assert_eq!(super::fingerprint(), 0xf441_75a1_7194_40ef);
+    }
+
+    #[cfg(feature = "embedded-tables")]
+    #[test]
+    fn embedded_sizes() {
+        // sizes of the .bindata files on disk
+        assert_eq!(super::eos_bytes(), 105_271_744);
+        assert_eq!(super::opacity_bytes(), 5_671_496);
+    }
+}
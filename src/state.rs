@@ -1,26 +1,162 @@
 use std::sync::Arc;
 
-use ndarray::{Array, ArrayView, Dimension, Zip};
+use ndarray::{arr0, Array, ArrayView, Dimension, Ix0, Zip};
+use thiserror::Error;
 
-use crate::eos_tables::{ConstMetalTables, StateVar, VolumeEnergyTable};
+use crate::{
+    coords::{self, Coordinates},
+    eos_tables::{AllTables, ConstMetalTables, StateVar, StencilCache, VolumeEnergyTable},
+    grid::OobPolicy,
+    index::{OutOfBoundsError, Range},
+    zip::try_zip_map,
+};
+
+#[cfg(feature = "rayon")]
+use crate::zip::try_collect;
+
+/// Maximum number of errors listed by [`CstMetalState::compute_reporting`]
+/// and [`CompositionError`].
+pub const MAX_REPORTED_ERRORS: usize = 1000;
+
+/// Elements of a state with an unphysical composition, see
+/// [`CstMetalState::validate_composition`].
+#[derive(Error, Debug, Clone)]
+#[error(
+    "unphysical composition in {n_invalid} element(s), first at index {} with (X, Y, Z) = {:?}",
+    .elements[0].0,
+    .elements[0].1
+)]
+pub struct CompositionError {
+    elements: Vec<(usize, [f64; 3])>,
+    n_invalid: usize,
+}
+
+impl CompositionError {
+    /// Flat index (in logical order of the arrays) and `(X, Y, Z)` of the
+    /// first [`MAX_REPORTED_ERRORS`] invalid elements.
+    pub fn elements(&self) -> &[(usize, [f64; 3])] {
+        &self.elements
+    }
+
+    /// Total number of invalid elements.
+    pub fn n_invalid(&self) -> usize {
+        self.n_invalid
+    }
+}
+
+/// Coverage of an axis of the tables by the elements of a state, see
+/// [`DomainReport`].
+#[derive(Debug, Clone, Copy)]
+pub struct AxisCoverage {
+    /// Interval of the axis in which the tables can be interpolated.
+    pub bounds: (f64, f64),
+    /// Number of elements below the interval.
+    pub n_below: usize,
+    /// Number of elements above the interval.
+    pub n_above: usize,
+    /// Number of NaN elements.
+    pub n_nan: usize,
+    /// Smallest value over the elements, this is the extremal offending
+    /// value if some are below the interval.  NaN if there are no elements.
+    pub min: f64,
+    /// Largest value over the elements, this is the extremal offending value
+    /// if some are above the interval.  NaN if there are no elements.
+    pub max: f64,
+}
+
+impl AxisCoverage {
+    fn new(domain: Range) -> Self {
+        Self {
+            bounds: (domain.first(), domain.last()),
+            n_below: 0,
+            n_above: 0,
+            n_nan: 0,
+            min: f64::NAN,
+            max: f64::NAN,
+        }
+    }
+
+    /// Record `value`, returning whether it is outside of the interval.
+    fn record(&mut self, domain: Range, value: f64) -> bool {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if value.is_nan() {
+            self.n_nan += 1;
+        } else if domain.contains(value) {
+            return false;
+        } else if value < domain.first() {
+            self.n_below += 1;
+        } else {
+            self.n_above += 1;
+        }
+        true
+    }
+
+    /// Number of elements outside of the interval, including NaN.
+    pub fn n_outside(&self) -> usize {
+        self.n_below + self.n_above + self.n_nan
+    }
+}
+
+/// Coverage of the tables by the elements of a state, see
+/// [`CstMetalState::domain_report`].
+#[derive(Debug, Clone, Copy)]
+pub struct DomainReport {
+    /// Total number of elements.
+    pub n_elements: usize,
+    /// Number of elements outside of the tables on at least one axis.
+    pub n_outside: usize,
+    pub log_energy: AxisCoverage,
+    pub log_volume: AxisCoverage,
+    pub h_frac: AxisCoverage,
+}
+
+impl DomainReport {
+    /// Fraction of the elements outside of the tables, 0 if there are no
+    /// elements.
+    pub fn fraction_outside(&self) -> f64 {
+        if self.n_elements == 0 {
+            0.0
+        } else {
+            self.n_outside as f64 / self.n_elements as f64
+        }
+    }
+
+    /// Whether all the elements are within the tables.
+    pub fn is_covered(&self) -> bool {
+        self.n_outside == 0
+    }
+}
+
+const JACOBIAN_VARS: [StateVar; 4] = [
+    StateVar::DPresDDensEcst,
+    StateVar::DPresDEnerDcst,
+    StateVar::DTempDDensEcst,
+    StateVar::DTempDEnerDcst,
+];
+
+/// Exponentiate `values` of `var` if it is stored in log.
+fn to_linear<D: Dimension>(var: StateVar, mut values: Array<f64, D>) -> Array<f64, D> {
+    if var.is_log() {
+        values.mapv_inplace(|v| 10_f64.powf(v));
+    }
+    values
+}
 
 pub struct CstCompoState<D: Dimension> {
     log_density: Array<f64, D>,
     log_volume: Array<f64, D>,
     log_energy: Array<f64, D>,
     table: Arc<VolumeEnergyTable>,
+    stencil_cache: bool,
 }
 
 fn from_de_to_logdve<D: Dimension>(
     density: ArrayView<'_, f64, D>,
     energy: ArrayView<'_, f64, D>,
 ) -> (Array<f64, D>, Array<f64, D>, Array<f64, D>) {
-    let log_density = density.mapv(f64::log10);
-    let log_energy = energy.mapv(f64::log10);
-    let log_volume = Zip::from(&log_energy)
-        .and(&log_density)
-        .map_collect(|&loge, &logd| 20.0 + logd - 0.7 * loge);
-    (log_density, log_volume, log_energy)
+    let coords = Coordinates::from_density_energy(density, energy);
+    (coords.log_density, coords.log_volume, coords.log_energy)
 }
 
 impl<D: Dimension> CstCompoState<D> {
@@ -36,13 +172,152 @@ impl<D: Dimension> CstCompoState<D> {
             log_volume,
             log_energy,
             table,
+            stencil_cache: false,
+        }
+    }
+
+    /// Build a state owning its table, for single use where the table doesn't
+    /// need to be shared with other states.
+    pub fn from_table(
+        table: VolumeEnergyTable,
+        density: ArrayView<'_, f64, D>,
+        energy: ArrayView<'_, f64, D>,
+    ) -> Self {
+        Self::new(Arc::new(table), density, energy)
+    }
+
+    /// Build a state from coordinates already computed in the tables.
+    pub fn from_coordinates(table: Arc<VolumeEnergyTable>, coords: Coordinates<D>) -> Self {
+        Self {
+            log_density: coords.log_density,
+            log_volume: coords.log_volume,
+            log_energy: coords.log_energy,
+            table,
+            stencil_cache: false,
+        }
+    }
+
+    /// Reuse spline stencils between elements sharing the same coordinates
+    /// in [`CstCompoState::compute`] and related methods.
+    ///
+    /// This can speed up computations when coordinates are often repeated,
+    /// e.g. on grids structured in log energy and log volume, but roughly
+    /// doubles their cost when coordinates are all distinct.  Building
+    /// stencils is cheap compared to the interpolation itself, so the gain is
+    /// modest at best (see the `stencil_cache` example to measure both
+    /// regimes).  This is disabled by default.
+    pub fn with_stencil_cache(self, enabled: bool) -> Self {
+        Self {
+            stencil_cache: enabled,
+            ..self
         }
     }
 
     pub fn compute(&self, var: StateVar) -> Array<f64, D> {
-        Zip::from(&self.log_volume)
-            .and(&self.log_energy)
-            .map_collect(|&logv, &loge| self.table.at(loge, logv, var).expect("out of table"))
+        self.try_compute(var).expect("out of table")
+    }
+
+    /// Project the state into the domain of the table, the density is updated
+    /// accordingly. This returns which elements were moved.
+    ///
+    /// Elements of the state are then guaranteed to be in the table.
+    pub fn clamp_to_domain(&mut self) -> Array<bool, D> {
+        let (loge_domain, logv_domain) = self.table.method_domain();
+        Zip::from(&mut self.log_energy)
+            .and(&mut self.log_volume)
+            .and(&mut self.log_density)
+            .map_collect(|loge, logv, logd| {
+                let (new_loge, new_logv) = (loge_domain.clamp(*loge), logv_domain.clamp(*logv));
+                let moved = new_loge != *loge || new_logv != *logv;
+                if moved {
+                    *loge = new_loge;
+                    *logv = new_logv;
+                    *logd = coords::log_density(new_logv, new_loge);
+                }
+                moved
+            })
+    }
+
+    /// Compute the requested [`StateVar`], distributing the work over threads.
+    #[cfg(feature = "rayon")]
+    pub fn par_compute(&self, var: StateVar) -> Array<f64, D> {
+        self.try_par_compute(var).expect("out of table")
+    }
+
+    /// Same as [`CstCompoState::par_compute`], failing if a state is outside
+    /// of the table.
+    #[cfg(feature = "rayon")]
+    pub fn try_par_compute(&self, var: StateVar) -> Result<Array<f64, D>, OutOfBoundsError> {
+        try_collect(
+            Zip::from(&self.log_volume)
+                .and(&self.log_energy)
+                .par_map_collect(|&logv, &loge| self.table.at(loge, logv, var)),
+        )
+    }
+
+    /// Compute the requested [`StateVar`] in linear space, i.e. `10**value`
+    /// for the variables stored in log (see [`StateVar::is_log`]), other
+    /// variables are left unchanged.
+    pub fn compute_linear(&self, var: StateVar) -> Array<f64, D> {
+        self.try_compute_linear(var).expect("out of table")
+    }
+
+    pub fn try_compute_linear(&self, var: StateVar) -> Result<Array<f64, D>, OutOfBoundsError> {
+        self.try_compute(var).map(|values| to_linear(var, values))
+    }
+
+    pub fn try_compute(&self, var: StateVar) -> Result<Array<f64, D>, OutOfBoundsError> {
+        let [out] = self.try_compute_many([var])?;
+        Ok(out)
+    }
+
+    /// Compute the requested [`StateVar`], states outside of the table being
+    /// handled according to `policy`, see
+    /// [`VolumeEnergyTable::at_with_policy`].
+    pub fn try_compute_with_policy(
+        &self,
+        var: StateVar,
+        policy: OobPolicy,
+    ) -> Result<Array<f64, D>, OutOfBoundsError> {
+        try_zip_map(
+            [self.log_volume.view(), self.log_energy.view()],
+            |[logv, loge]| self.table.at_with_policy(loge, logv, var, policy),
+        )
+    }
+
+    /// Compute several [`StateVar`]s in one interpolation pass.
+    pub fn compute_many<const N: usize>(&self, vars: [StateVar; N]) -> [Array<f64, D>; N] {
+        self.try_compute_many(vars).expect("out of table")
+    }
+
+    pub fn try_compute_many<const N: usize>(
+        &self,
+        vars: [StateVar; N],
+    ) -> Result<[Array<f64, D>; N], OutOfBoundsError> {
+        let mut cache = self.stencil_cache.then(StencilCache::default);
+        let out = try_zip_map(
+            [self.log_volume.view(), self.log_energy.view()],
+            |[logv, loge]| match &mut cache {
+                Some(cache) => self.table.at_many_cached(loge, logv, vars, cache),
+                None => self.table.at_many(loge, logv, vars),
+            },
+        )?;
+        Ok(std::array::from_fn(|i| out.map(|values| values[i])))
+    }
+
+    /// Compute the derivatives of pressure and temperature with respect to
+    /// density and internal energy.
+    ///
+    /// The four arrays are, in order, `DPresDDensEcst`, `DPresDEnerDcst`,
+    /// `DTempDDensEcst`, and `DTempDEnerDcst`. These are logarithmic
+    /// derivatives (e.g. `dln(P)/dln(rho)` at constant energy), hence
+    /// dimensionless.
+    pub fn jacobian(&self) -> [Array<f64, D>; 4] {
+        self.compute_many(JACOBIAN_VARS)
+    }
+
+    pub fn try_jacobian(&self) -> Result<[Array<f64, D>; 4], OutOfBoundsError> {
+        self.try_compute_many(JACOBIAN_VARS)
     }
 
     pub fn metallicity(&self) -> f64 {
@@ -62,12 +337,24 @@ impl<D: Dimension> CstCompoState<D> {
     }
 }
 
+impl CstCompoState<Ix0> {
+    /// State at a single point.
+    pub fn scalar(table: Arc<VolumeEnergyTable>, density: f64, energy: f64) -> Self {
+        Self::new(table, arr0(density).view(), arr0(energy).view())
+    }
+
+    pub fn compute_scalar(&self, var: StateVar) -> f64 {
+        self.compute(var).into_scalar()
+    }
+}
+
 pub struct CstMetalState<D: Dimension> {
     h_frac: Array<f64, D>,
     log_density: Array<f64, D>,
     log_volume: Array<f64, D>,
     log_energy: Array<f64, D>,
     table: Arc<ConstMetalTables>,
+    stencil_cache: bool,
 }
 
 impl<D: Dimension> CstMetalState<D> {
@@ -87,6 +374,65 @@ impl<D: Dimension> CstMetalState<D> {
             log_volume,
             log_energy,
             table,
+            stencil_cache: false,
+        }
+    }
+
+    /// Build a state owning its tables, for single use where the tables don't
+    /// need to be shared with other states.
+    pub fn from_tables(
+        table: ConstMetalTables,
+        he_frac: ArrayView<'_, f64, D>,
+        density: ArrayView<'_, f64, D>,
+        energy: ArrayView<'_, f64, D>,
+    ) -> Self {
+        Self::new(Arc::new(table), he_frac, density, energy)
+    }
+
+    /// Build a state from the hydrogen fraction, stored as is.
+    pub fn from_h_frac(
+        table: Arc<ConstMetalTables>,
+        h_frac: ArrayView<'_, f64, D>,
+        density: ArrayView<'_, f64, D>,
+        energy: ArrayView<'_, f64, D>,
+    ) -> Self {
+        assert_eq!(h_frac.shape(), density.shape());
+        assert_eq!(h_frac.shape(), energy.shape());
+        let (log_density, log_volume, log_energy) = from_de_to_logdve(density, energy);
+        Self {
+            h_frac: h_frac.to_owned(),
+            log_density,
+            log_volume,
+            log_energy,
+            table,
+            stencil_cache: false,
+        }
+    }
+
+    /// Build a state from the hydrogen fraction and coordinates already
+    /// computed in the tables.
+    pub fn from_coordinates(
+        table: Arc<ConstMetalTables>,
+        h_frac: ArrayView<'_, f64, D>,
+        coords: Coordinates<D>,
+    ) -> Self {
+        assert_eq!(h_frac.shape(), coords.log_density.shape());
+        Self {
+            h_frac: h_frac.to_owned(),
+            log_density: coords.log_density,
+            log_volume: coords.log_volume,
+            log_energy: coords.log_energy,
+            table,
+            stencil_cache: false,
+        }
+    }
+
+    /// Reuse spline stencils between elements sharing the same coordinates,
+    /// see [`CstCompoState::with_stencil_cache`].
+    pub fn with_stencil_cache(self, enabled: bool) -> Self {
+        Self {
+            stencil_cache: enabled,
+            ..self
         }
     }
 
@@ -106,14 +452,178 @@ impl<D: Dimension> CstMetalState<D> {
     }
 
     pub fn compute(&self, var: StateVar) -> Array<f64, D> {
-        Zip::from(&self.log_volume)
-            .and(&self.log_energy)
-            .and(&self.h_frac)
-            .map_collect(|&logv, &loge, &h_frac| {
+        self.try_compute(var).expect("out of table")
+    }
+
+    /// Compute the requested [`StateVar`], distributing the work over threads.
+    #[cfg(feature = "rayon")]
+    pub fn par_compute(&self, var: StateVar) -> Array<f64, D> {
+        self.try_par_compute(var).expect("out of table")
+    }
+
+    /// Same as [`CstMetalState::par_compute`], failing if a state is outside
+    /// of the tables.
+    #[cfg(feature = "rayon")]
+    pub fn try_par_compute(&self, var: StateVar) -> Result<Array<f64, D>, OutOfBoundsError> {
+        try_collect(
+            Zip::from(&self.log_volume)
+                .and(&self.log_energy)
+                .and(&self.h_frac)
+                .par_map_collect(|&logv, &loge, &h_frac| self.table.at(h_frac, loge, logv, var)),
+        )
+    }
+
+    /// Compute the requested [`StateVar`] in linear space, i.e. `10**value`
+    /// for the variables stored in log (see [`StateVar::is_log`]), other
+    /// variables are left unchanged.
+    pub fn compute_linear(&self, var: StateVar) -> Array<f64, D> {
+        self.try_compute_linear(var).expect("out of table")
+    }
+
+    pub fn try_compute_linear(&self, var: StateVar) -> Result<Array<f64, D>, OutOfBoundsError> {
+        self.try_compute(var).map(|values| to_linear(var, values))
+    }
+
+    pub fn try_compute(&self, var: StateVar) -> Result<Array<f64, D>, OutOfBoundsError> {
+        let [out] = self.try_compute_many([var])?;
+        Ok(out)
+    }
+
+    /// Compute the requested [`StateVar`], states outside of the tables
+    /// being handled according to `policy`, see
+    /// [`ConstMetalTables::at_with_policy`].
+    pub fn try_compute_with_policy(
+        &self,
+        var: StateVar,
+        policy: OobPolicy,
+    ) -> Result<Array<f64, D>, OutOfBoundsError> {
+        try_zip_map(
+            [
+                self.h_frac.view(),
+                self.log_volume.view(),
+                self.log_energy.view(),
+            ],
+            |[h_frac, logv, loge]| self.table.at_with_policy(h_frac, loge, logv, var, policy),
+        )
+    }
+
+    /// Compute the requested [`StateVar`] where possible, reporting the
+    /// elements outside of the tables instead of failing.
+    ///
+    /// Elements outside of the tables are set to NaN, and listed with their
+    /// error by flat index (in logical order of the arrays).  Only the first
+    /// [`MAX_REPORTED_ERRORS`] errors are listed.
+    pub fn compute_reporting(
+        &self,
+        var: StateVar,
+    ) -> (Array<f64, D>, Vec<(usize, OutOfBoundsError)>) {
+        let mut errors = Vec::new();
+        let values = self
+            .h_frac
+            .iter()
+            .zip(&self.log_volume)
+            .zip(&self.log_energy)
+            .enumerate()
+            .map(|(i, ((&h_frac, &logv), &loge))| {
                 self.table
                     .at(h_frac, loge, logv, var)
-                    .expect("out of table")
+                    .unwrap_or_else(|err| {
+                        if errors.len() < MAX_REPORTED_ERRORS {
+                            errors.push((i, err));
+                        }
+                        f64::NAN
+                    })
             })
+            .collect();
+        let values = Array::from_shape_vec(self.h_frac.raw_dim(), values)
+            .expect("values are in logical order of the state");
+        (values, errors)
+    }
+
+    /// Compute several [`StateVar`]s in one interpolation pass.
+    pub fn compute_many<const N: usize>(&self, vars: [StateVar; N]) -> [Array<f64, D>; N] {
+        self.try_compute_many(vars).expect("out of table")
+    }
+
+    pub fn try_compute_many<const N: usize>(
+        &self,
+        vars: [StateVar; N],
+    ) -> Result<[Array<f64, D>; N], OutOfBoundsError> {
+        let mut cache = self.stencil_cache.then(StencilCache::default);
+        let out = try_zip_map(
+            [
+                self.log_volume.view(),
+                self.log_energy.view(),
+                self.h_frac.view(),
+            ],
+            |[logv, loge, h_frac]| match &mut cache {
+                Some(cache) => self.table.at_many_cached(h_frac, loge, logv, vars, cache),
+                None => self.table.at_many(h_frac, loge, logv, vars),
+            },
+        )?;
+        Ok(std::array::from_fn(|i| out.map(|values| values[i])))
+    }
+
+    /// Compute the derivatives of pressure and temperature with respect to
+    /// density and internal energy.
+    ///
+    /// The four arrays are, in order, `DPresDDensEcst`, `DPresDEnerDcst`,
+    /// `DTempDDensEcst`, and `DTempDEnerDcst`. These are logarithmic
+    /// derivatives (e.g. `dln(P)/dln(rho)` at constant energy), hence
+    /// dimensionless.
+    pub fn jacobian(&self) -> [Array<f64, D>; 4] {
+        self.compute_many(JACOBIAN_VARS)
+    }
+
+    pub fn try_jacobian(&self) -> Result<[Array<f64, D>; 4], OutOfBoundsError> {
+        self.try_compute_many(JACOBIAN_VARS)
+    }
+
+    /// Flag the elements where `Gamma1` and `Gamma` are consistent, within
+    /// `rel_tol`, with the pressure and temperature derivatives.
+    ///
+    /// The identities checked are `Gamma1 = dlnP/dlnrho|e + P/(rho e)
+    /// dlnP/dlne|rho` and `Gamma = Gamma1 / chi_rho`, where `chi_rho =
+    /// dlnP/dlnrho|T` is obtained from the derivatives at constant energy.
+    pub fn check_gamma_consistency(&self, rel_tol: f64) -> Array<bool, D> {
+        self.try_check_gamma_consistency(rel_tol)
+            .expect("out of table")
+    }
+
+    pub fn try_check_gamma_consistency(
+        &self,
+        rel_tol: f64,
+    ) -> Result<Array<bool, D>, OutOfBoundsError> {
+        let [logp, gamma1, gamma, dp_dd, dp_de, dt_dd, dt_de] = self.try_compute_many([
+            StateVar::LogPressure,
+            StateVar::Gamma1,
+            StateVar::Gamma,
+            StateVar::DPresDDensEcst,
+            StateVar::DPresDEnerDcst,
+            StateVar::DTempDDensEcst,
+            StateVar::DTempDEnerDcst,
+        ])?;
+        let close = |a: f64, b: f64| (a - b).abs() <= rel_tol * a.abs().max(b.abs());
+        let p_over_rho_e = Zip::from(&logp)
+            .and(&self.log_density)
+            .and(&self.log_energy)
+            .map_collect(|&logp, &logd, &loge| 10_f64.powf(logp - logd - loge));
+        let gamma1_from_derivs = Zip::from(&dp_dd)
+            .and(&dp_de)
+            .and(&p_over_rho_e)
+            .map_collect(|&dp_dd, &dp_de, &ratio| dp_dd + ratio * dp_de);
+        let chi_rho = Zip::from(&dp_dd)
+            .and(&dp_de)
+            .and(&dt_dd)
+            .and(&dt_de)
+            .map_collect(|&dp_dd, &dp_de, &dt_dd, &dt_de| dp_dd - dp_de * dt_dd / dt_de);
+        Ok(Zip::from(&gamma1)
+            .and(&gamma)
+            .and(&gamma1_from_derivs)
+            .and(&chi_rho)
+            .map_collect(|&gamma1, &gamma, &gamma1_from_derivs, &chi_rho| {
+                close(gamma1, gamma1_from_derivs) && close(gamma, gamma1 / chi_rho)
+            }))
     }
 
     pub fn metallicity(&self) -> f64 {
@@ -124,19 +634,210 @@ impl<D: Dimension> CstMetalState<D> {
         self.h_frac.view()
     }
 
+    /// Helium fraction, computed from the hydrogen fraction and metallicity.
+    pub fn he_frac(&self) -> Array<f64, D> {
+        let metallicity = self.metallicity();
+        self.h_frac.mapv(|h| 1.0 - h - metallicity)
+    }
+
+    /// Check that the composition of each element is physical: the mass
+    /// fractions X, Y, and Z lie in `[0, 1]`, within `tol`.
+    ///
+    /// Y is derived as `1 - X - Z` so the fractions sum to 1 by
+    /// construction, only their range is checked.
+    ///
+    /// This is meant to catch e.g. composition arrays corrupted upstream
+    /// before computing anything, NaN fractions are reported as invalid.
+    pub fn validate_composition(&self, tol: f64) -> Result<(), CompositionError> {
+        let metallicity = self.metallicity();
+        let in_unit = |frac: f64| (-tol..=1.0 + tol).contains(&frac);
+        let mut elements = Vec::new();
+        let mut n_invalid = 0;
+        for (i, &h_frac) in self.h_frac.iter().enumerate() {
+            let he_frac = 1.0 - h_frac - metallicity;
+            let fracs = [h_frac, he_frac, metallicity];
+            if !fracs.into_iter().all(in_unit) {
+                n_invalid += 1;
+                if elements.len() < MAX_REPORTED_ERRORS {
+                    elements.push((i, fracs));
+                }
+            }
+        }
+        if n_invalid == 0 {
+            Ok(())
+        } else {
+            Err(CompositionError {
+                elements,
+                n_invalid,
+            })
+        }
+    }
+
+    /// Pressure scale height `H_P = P / (rho g)`, in cm.
+    ///
+    /// The pressure is computed from the tables while the density is the
+    /// one of the state. `gravity` is the norm of the gravitational
+    /// acceleration in cm/s^2 and must have the shape of the state.
+    pub fn pressure_scale_height(&self, gravity: ArrayView<'_, f64, D>) -> Array<f64, D> {
+        self.try_pressure_scale_height(gravity)
+            .expect("out of table")
+    }
+
+    /// Same as [`CstMetalState::pressure_scale_height`], failing if an
+    /// element is outside of the table.
+    ///
+    /// # Panics
+    ///
+    /// If `gravity` doesn't have the shape of the state.
+    pub fn try_pressure_scale_height(
+        &self,
+        gravity: ArrayView<'_, f64, D>,
+    ) -> Result<Array<f64, D>, OutOfBoundsError> {
+        assert_eq!(gravity.shape(), self.log_density.shape());
+        let logp = self.try_compute(StateVar::LogPressure)?;
+        Ok(Zip::from(&logp)
+            .and(&self.log_density)
+            .and(&gravity)
+            .map_collect(|&logp, &logd, &gravity| 10_f64.powf(logp - logd) / gravity))
+    }
+
+    /// Coverage of the tables by the elements of the state, to check
+    /// upfront whether computations will fail.
+    ///
+    /// The log energy and log volume intervals are the domains in which the
+    /// tables can be interpolated with their method, e.g. the inner nodes of
+    /// the axes for cubic interpolation.
+    pub fn domain_report(&self) -> DomainReport {
+        let (loge_domain, logv_domain) = self.table.method_domain();
+        let h_fracs = self.table.h_fracs();
+        let mut report = DomainReport {
+            n_elements: self.h_frac.len(),
+            n_outside: 0,
+            log_energy: AxisCoverage::new(loge_domain),
+            log_volume: AxisCoverage::new(logv_domain),
+            h_frac: AxisCoverage::new(h_fracs),
+        };
+        Zip::from(&self.log_energy)
+            .and(&self.log_volume)
+            .and(&self.h_frac)
+            .for_each(|&loge, &logv, &h_frac| {
+                let outside = [
+                    report.log_energy.record(loge_domain, loge),
+                    report.log_volume.record(logv_domain, logv),
+                    report.h_frac.record(h_fracs, h_frac),
+                ];
+                if outside.contains(&true) {
+                    report.n_outside += 1;
+                }
+            });
+        report
+    }
+
+    pub fn log_density(&self) -> ArrayView<'_, f64, D> {
+        self.log_density.view()
+    }
+}
+
+impl CstMetalState<Ix0> {
+    /// State at a single point.
+    pub fn scalar(table: Arc<ConstMetalTables>, he_frac: f64, density: f64, energy: f64) -> Self {
+        Self::new(
+            table,
+            arr0(he_frac).view(),
+            arr0(density).view(),
+            arr0(energy).view(),
+        )
+    }
+
+    pub fn compute_scalar(&self, var: StateVar) -> f64 {
+        self.compute(var).into_scalar()
+    }
+}
+
+/// A state with varying metallicity and hydrogen fraction.
+pub struct CstVaryingState<D: Dimension> {
+    metallicity: Array<f64, D>,
+    h_frac: Array<f64, D>,
+    log_density: Array<f64, D>,
+    log_volume: Array<f64, D>,
+    log_energy: Array<f64, D>,
+    tables: Arc<AllTables>,
+}
+
+impl<D: Dimension> CstVaryingState<D> {
+    pub fn new(
+        tables: Arc<AllTables>,
+        metallicity: ArrayView<'_, f64, D>,
+        h_frac: ArrayView<'_, f64, D>,
+        density: ArrayView<'_, f64, D>,
+        energy: ArrayView<'_, f64, D>,
+    ) -> Self {
+        assert_eq!(metallicity.shape(), h_frac.shape());
+        assert_eq!(metallicity.shape(), density.shape());
+        assert_eq!(metallicity.shape(), energy.shape());
+        let (log_density, log_volume, log_energy) = from_de_to_logdve(density, energy);
+        Self {
+            metallicity: metallicity.to_owned(),
+            h_frac: h_frac.to_owned(),
+            log_density,
+            log_volume,
+            log_energy,
+            tables,
+        }
+    }
+
+    pub fn compute(&self, var: StateVar) -> Array<f64, D> {
+        self.try_compute(var).expect("out of table")
+    }
+
+    /// Compute the requested [`StateVar`] in linear space, i.e. `10**value`
+    /// for the variables stored in log (see [`StateVar::is_log`]), other
+    /// variables are left unchanged.
+    pub fn compute_linear(&self, var: StateVar) -> Array<f64, D> {
+        self.try_compute_linear(var).expect("out of table")
+    }
+
+    pub fn try_compute_linear(&self, var: StateVar) -> Result<Array<f64, D>, OutOfBoundsError> {
+        self.try_compute(var).map(|values| to_linear(var, values))
+    }
+
+    pub fn try_compute(&self, var: StateVar) -> Result<Array<f64, D>, OutOfBoundsError> {
+        try_zip_map(
+            [
+                self.metallicity.view(),
+                self.h_frac.view(),
+                self.log_volume.view(),
+                self.log_energy.view(),
+            ],
+            |[metal, h_frac, logv, loge]| self.tables.at(metal, h_frac, loge, logv, var),
+        )
+    }
+
+    pub fn metallicity(&self) -> ArrayView<'_, f64, D> {
+        self.metallicity.view()
+    }
+
+    pub fn h_frac(&self) -> ArrayView<'_, f64, D> {
+        self.h_frac.view()
+    }
+
     pub fn log_density(&self) -> ArrayView<'_, f64, D> {
         self.log_density.view()
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "embedded-tables"))]
 mod tests {
-    use ndarray::{arr1, Zip};
+    use std::sync::Arc;
+
+    use ndarray::{arr1, arr2, Array2, Zip};
 
     use crate::{
+        coords::Coordinates,
         eos_tables::{AllTables, StateVar},
+        grid::InterpolationMethod,
         is_close::IsClose,
-        state::CstMetalState,
+        state::{CstMetalState, CstVaryingState},
     };
 
     use super::CstCompoState;
@@ -150,7 +851,7 @@ mod tests {
             .unwrap();
         let density = arr1(&[3.5, 10.3, 10.5]);
         let energy = arr1(&[5.7e14, 4.5e15, 6.7e16]);
-        let state = CstCompoState::new(table.into(), density.view(), energy.view());
+        let state = CstCompoState::from_table(table, density.view(), energy.view());
         let d_comp = state
             .compute(StateVar::LogDensity)
             .mapv_into(|logd| 10.0_f64.powf(logd));
@@ -166,7 +867,7 @@ mod tests {
         let mut density = arr1(&[3.5, 10.3, 10.5]);
         let mut energy = arr1(&[5.7e14, 4.5e15, 6.7e16]);
         let mut state =
-            CstMetalState::new(table.into(), he_frac.view(), density.view(), energy.view());
+            CstMetalState::from_tables(table, he_frac.view(), density.view(), energy.view());
         let d_comp = state
             .compute(StateVar::LogDensity)
             .mapv_into(|logd| 10.0_f64.powf(logd));
@@ -180,4 +881,372 @@ mod tests {
         let logt = state.compute(StateVar::LogTemperature);
         assert!(((logt[0] + logt[2]) / 2.0 - logt[1]) / logt[1] < 1e-4);
     }
+
+    #[test]
+    fn jacobian_matches_compute() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let he_frac = arr1(&[0.2, 0.3, 0.4]);
+        let density = arr1(&[3.5, 10.3, 10.5]);
+        let energy = arr1(&[5.7e14, 4.5e15, 6.7e16]);
+        let state = CstMetalState::new(table.into(), he_frac.view(), density.view(), energy.view());
+        let jacobian = state.jacobian();
+        let vars = [
+            StateVar::DPresDDensEcst,
+            StateVar::DPresDEnerDcst,
+            StateVar::DTempDDensEcst,
+            StateVar::DTempDEnerDcst,
+        ];
+        for (jac, var) in jacobian.iter().zip(vars) {
+            assert!(Zip::from(jac)
+                .and(&state.compute(var))
+                .all(|&a, &b| a.is_close(b)));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_compute() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let he_frac = arr1(&[0.2, 0.3, 0.4]);
+        let density = arr1(&[3.5, 10.3, 10.5]);
+        let energy = arr1(&[5.7e14, 4.5e15, 6.7e16]);
+        let state = CstMetalState::new(table.into(), he_frac.view(), density.view(), energy.view());
+        assert_eq!(
+            state.compute(StateVar::LogTemperature),
+            state.par_compute(StateVar::LogTemperature)
+        );
+
+        let energy = arr1(&[5.7e14, 1e30, 6.7e16]);
+        let state = CstMetalState::new(
+            state.table.clone(),
+            he_frac.view(),
+            density.view(),
+            energy.view(),
+        );
+        assert!(state.try_par_compute(StateVar::LogTemperature).is_err());
+    }
+
+    #[test]
+    fn scalar_state() {
+        let table = Arc::new(AllTables::default().take_at_metallicity(0.02).unwrap());
+        let state = CstMetalState::scalar(table.clone(), 0.3, 10.3, 4.5e15);
+        let array_state = CstMetalState::new(
+            table,
+            arr1(&[0.3]).view(),
+            arr1(&[10.3]).view(),
+            arr1(&[4.5e15]).view(),
+        );
+        let logt = state.compute_scalar(StateVar::LogTemperature);
+        assert!(logt.is_close(array_state.compute(StateVar::LogTemperature)[0]));
+
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_he_frac(0.3)
+            .unwrap();
+        let state = CstCompoState::scalar(table.into(), 10.3, 4.5e15);
+        assert!(state
+            .compute_scalar(StateVar::LogTemperature)
+            .is_close(logt));
+    }
+
+    #[test]
+    fn metal_state_from_h_frac() {
+        let table = Arc::new(AllTables::default().take_at_metallicity(0.02).unwrap());
+        let h_frac = arr1(&[0.7, 0.5]);
+        let density = arr1(&[3.5, 10.3]);
+        let energy = arr1(&[5.7e14, 4.5e15]);
+        let state =
+            CstMetalState::from_h_frac(table.clone(), h_frac.view(), density.view(), energy.view());
+        assert_eq!(state.h_frac(), h_frac);
+        let he_frac = h_frac.mapv(|h| 1.0 - h - 0.02);
+        let he_state = CstMetalState::new(table, he_frac.view(), density.view(), energy.view());
+        let logt = state.compute(StateVar::LogTemperature);
+        let he_logt = he_state.compute(StateVar::LogTemperature);
+        assert!(Zip::from(&logt)
+            .and(&he_logt)
+            .all(|&a, &b| (a - b).abs() < 1e-10));
+    }
+
+    #[test]
+    fn clamp_compo_state() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_he_frac(0.28)
+            .unwrap();
+        let density = arr1(&[10.3, 1e-12, 10.3]);
+        let energy = arr1(&[4.5e15, 4.5e15, 1e30]);
+        let mut state = CstCompoState::new(table.into(), density.view(), energy.view());
+        assert!(state.try_compute(StateVar::LogTemperature).is_err());
+        let logd = state.log_density().to_owned();
+        let moved = state.clamp_to_domain();
+        assert_eq!(moved, arr1(&[false, true, true]));
+        assert_eq!(state.log_density()[0], logd[0]);
+        assert!(state.log_density()[1] > logd[1]);
+        assert!(state.try_compute(StateVar::LogTemperature).is_ok());
+        assert!(!state.clamp_to_domain().iter().any(|&m| m));
+    }
+
+    #[test]
+    fn clamp_compo_state_follows_method() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_he_frac(0.28)
+            .unwrap()
+            .with_method(InterpolationMethod::Linear);
+        let log_energy = table.log_energy();
+        // in the first cell of the axis, outside of the spline stencils
+        let energy = arr1(&[10_f64.powf(log_energy.first() + 0.5 * log_energy.step())]);
+        let density = arr1(&[1e-2]);
+        let mut state = CstCompoState::new(table.into(), density.view(), energy.view());
+        assert!(state.try_compute(StateVar::LogTemperature).is_ok());
+        assert_eq!(state.clamp_to_domain(), arr1(&[false]));
+    }
+
+    #[test]
+    fn gamma_consistency() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let he_frac = arr1(&[0.28, 0.28, 0.5]);
+        let density = arr1(&[1e-2, 10.3, 1e-5]);
+        let energy = arr1(&[1e13, 4.5e15, 1e14]);
+        let state = CstMetalState::new(table.into(), he_frac.view(), density.view(), energy.view());
+        // interpolation errors are of the order of the percent in partial
+        // ionization regions
+        assert!(state.check_gamma_consistency(3e-2).iter().all(|&ok| ok));
+        assert!(!state.check_gamma_consistency(1e-6).iter().all(|&ok| ok));
+    }
+
+    #[test]
+    fn varying_metallicity() {
+        let tables = Arc::new(AllTables::default());
+        let metallicity = arr1(&[0.02, 0.03]);
+        let h_frac = arr1(&[0.7, 0.6]);
+        let density = arr1(&[3.5, 10.3]);
+        let energy = arr1(&[5.7e14, 4.5e15]);
+        let state = CstVaryingState::new(
+            tables,
+            metallicity.view(),
+            h_frac.view(),
+            density.view(),
+            energy.view(),
+        );
+        let logt = state.compute(StateVar::LogTemperature);
+        for i in 0..2 {
+            let table = AllTables::default()
+                .take_at_metallicity(metallicity[i])
+                .unwrap()
+                .take_at_h_frac(h_frac[i])
+                .unwrap();
+            let fixed = CstCompoState::scalar(table.into(), density[i], energy[i]);
+            assert!((fixed.compute_scalar(StateVar::LogTemperature) - logt[i]).abs() < 1e-10);
+        }
+    }
+
+    #[test]
+    fn linear_output() {
+        let table = AllTables::default()
+            .take_at_metallicity(0.02)
+            .unwrap()
+            .take_at_he_frac(0.28)
+            .unwrap();
+        let state = CstCompoState::new(
+            Arc::new(table),
+            arr1(&[3.5, 10.3]).view(),
+            arr1(&[1e14, 3e15]).view(),
+        );
+        for var in StateVar::ALL {
+            let log = state.compute(var);
+            let lin = state.compute_linear(var);
+            Zip::from(&log).and(&lin).for_each(|&log, &lin| {
+                let expected = if var.is_log() { 10_f64.powf(log) } else { log };
+                assert!(lin.is_close(expected));
+            });
+        }
+        let density = state.compute_linear(StateVar::LogDensity);
+        assert!((density[0] / 3.5 - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn metal_state_he_frac() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let he_frac = arr1(&[0.2, 0.3, 0.4]);
+        let density = arr1(&[3.5, 10.3, 10.5]);
+        let energy = arr1(&[5.7e14, 4.5e15, 6.7e16]);
+        let state =
+            CstMetalState::from_tables(table, he_frac.view(), density.view(), energy.view());
+        assert!(Zip::from(&state.he_frac())
+            .and(&he_frac)
+            .all(|&a, &b| a.is_close(b)));
+        assert!(state.h_frac()[0].is_close(0.78));
+    }
+
+    #[test]
+    fn report_out_of_table_elements() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let he_frac = arr2(&[[0.2, 0.3], [0.4, 0.3]]);
+        let density = arr2(&[[3.5, 10.3], [10.5, 3.5]]);
+        let energy = arr2(&[[5.7e14, 1e30], [6.7e16, 1e30]]);
+        let state =
+            CstMetalState::from_tables(table, he_frac.view(), density.view(), energy.view());
+        let (values, errors) = state.compute_reporting(StateVar::LogTemperature);
+        assert!(values[[0, 0]].is_finite() && values[[1, 0]].is_finite());
+        assert!(values[[0, 1]].is_nan() && values[[1, 1]].is_nan());
+        let indices: Vec<_> = errors.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indices, [1, 3]);
+        assert_eq!(errors[0].1.axis(), Some("log_energy"));
+    }
+
+    #[test]
+    fn shared_coordinates_sweep() {
+        let table = Arc::new(AllTables::default().take_at_metallicity(0.02).unwrap());
+        let density = arr1(&[3.5, 10.3]);
+        let energy = arr1(&[5.7e14, 4.5e15]);
+        let coords = Coordinates::from_density_energy(density.view(), energy.view());
+        for h in [0.5, 0.6, 0.7] {
+            let h_frac = arr1(&[h, h]);
+            let shared = coords
+                .try_compute(&table, h_frac.view(), StateVar::LogPressure)
+                .unwrap();
+            let state = CstMetalState::from_h_frac(
+                table.clone(),
+                h_frac.view(),
+                density.view(),
+                energy.view(),
+            );
+            assert_eq!(shared, state.compute(StateVar::LogPressure));
+        }
+    }
+
+    #[test]
+    fn stencil_cache_on_structured_grid() {
+        let metal_table = Arc::new(AllTables::default().take_at_metallicity(0.02).unwrap());
+        let density = arr1(&[1e-3, 1e-2, 0.1, 1.0]);
+        let energy = arr1(&[1e14, 3e14, 1e15]);
+        let density = Array2::from_shape_fn((4, 3), |(i, _)| density[i]);
+        let energy = Array2::from_shape_fn((4, 3), |(_, j)| energy[j]);
+        let he_frac = Array2::from_elem((4, 3), 0.25);
+        let state = CstMetalState::new(
+            metal_table.clone(),
+            he_frac.view(),
+            density.view(),
+            energy.view(),
+        );
+        let cached = CstMetalState::new(metal_table, he_frac.view(), density.view(), energy.view())
+            .with_stencil_cache(true);
+        assert_eq!(
+            cached.compute(StateVar::LogPressure),
+            state.compute(StateVar::LogPressure)
+        );
+
+        let table = Arc::new(
+            AllTables::default()
+                .take_at_metallicity(0.02)
+                .unwrap()
+                .take_at_h_frac(0.7)
+                .unwrap(),
+        );
+        let state = CstCompoState::new(table.clone(), density.view(), energy.view());
+        let cached =
+            CstCompoState::new(table, density.view(), energy.view()).with_stencil_cache(true);
+        assert_eq!(cached.jacobian(), state.jacobian());
+    }
+
+    #[test]
+    fn validate_composition() {
+        let table = Arc::new(AllTables::default().take_at_metallicity(0.02).unwrap());
+        let density = arr1(&[3.5, 10.3, 1.0, 2.0]);
+        let energy = arr1(&[5.7e14, 4.5e15, 1e15, 1e15]);
+        let h_frac = arr1(&[0.7, 0.98, 0.5, 0.3]);
+        let state =
+            CstMetalState::from_h_frac(table.clone(), h_frac.view(), density.view(), energy.view());
+        assert!(state.validate_composition(1e-10).is_ok());
+
+        let h_frac = arr1(&[0.7, 1.01, f64::NAN, -0.2]);
+        let state = CstMetalState::from_h_frac(table, h_frac.view(), density.view(), energy.view());
+        let err = state.validate_composition(1e-10).unwrap_err();
+        assert_eq!(err.n_invalid(), 3);
+        let indices: Vec<_> = err.elements().iter().map(|&(i, _)| i).collect();
+        assert_eq!(indices, [1, 2, 3]);
+        assert!(err.elements()[0].1[1].is_close(-0.03));
+        assert!(err
+            .to_string()
+            .starts_with("unphysical composition in 3 element(s)"));
+    }
+
+    #[test]
+    fn domain_report() {
+        let table = Arc::new(AllTables::default().take_at_metallicity(0.02).unwrap());
+        let density = arr1(&[3.5, 10.3, 1.0, 2.0, 1.0]);
+        let energy = arr1(&[5.7e14, 4.5e15, 1e20, 1e9, 1e15]);
+        let h_frac = arr1(&[0.7, 0.3, 0.5, 0.9, f64::NAN]);
+        let state = CstMetalState::from_h_frac(table, h_frac.view(), density.view(), energy.view());
+        let report = state.domain_report();
+        assert_eq!(report.n_elements, 5);
+        assert_eq!(report.n_outside, 3);
+        assert!(report.fraction_outside().is_close(0.6));
+        assert!(!report.is_covered());
+        let loge = report.log_energy;
+        assert_eq!((loge.n_below, loge.n_above, loge.n_nan), (1, 1, 0));
+        assert!(loge.min.is_close(9.0));
+        assert!(loge.max.is_close(20.0));
+        assert!(loge.bounds.0 > 9.0 && loge.bounds.1 < 20.0);
+        let h_frac = report.h_frac;
+        assert_eq!((h_frac.n_below, h_frac.n_above, h_frac.n_nan), (0, 1, 1));
+        assert_eq!(h_frac.n_outside(), 2);
+        assert!(h_frac.max.is_close(0.9));
+        // computations agree with the report
+        assert!(state.try_compute(StateVar::LogPressure).is_err());
+        let density = arr1(&[3.5, 10.3]);
+        let energy = arr1(&[5.7e14, 4.5e15]);
+        let he_frac = arr1(&[0.28, 0.68]);
+        let table = Arc::new(AllTables::default().take_at_metallicity(0.02).unwrap());
+        let state = CstMetalState::new(table, he_frac.view(), density.view(), energy.view());
+        assert!(state.domain_report().is_covered());
+        assert!(state.try_compute(StateVar::LogPressure).is_ok());
+    }
+
+    #[test]
+    fn domain_report_follows_method() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let log_energy = table.log_energy();
+        let h_frac = arr1(&[0.7]);
+        let density = arr1(&[3.5]);
+        // in the first cell of the axis, outside of the spline stencils
+        let energy = arr1(&[10_f64.powf(log_energy.first() + 0.5 * log_energy.step())]);
+        let cubic = CstMetalState::from_h_frac(
+            Arc::new(table.clone()),
+            h_frac.view(),
+            density.view(),
+            energy.view(),
+        );
+        assert_eq!(cubic.domain_report().log_energy.n_below, 1);
+        let linear = CstMetalState::from_h_frac(
+            Arc::new(table.with_method(InterpolationMethod::Linear)),
+            h_frac.view(),
+            density.view(),
+            energy.view(),
+        );
+        let report = linear.domain_report();
+        assert_eq!(report.log_energy.n_below, 0);
+        assert_eq!(report.log_energy.bounds.0, log_energy.first());
+    }
+
+    #[test]
+    fn pressure_scale_height() {
+        let table = AllTables::default().take_at_metallicity(0.02).unwrap();
+        let he_frac = arr1(&[0.28, 0.3]);
+        let density = arr1(&[3.5, 10.3]);
+        let energy = arr1(&[5.7e14, 4.5e15]);
+        let gravity = arr1(&[2.7e4, 1.5e5]);
+        let state =
+            CstMetalState::from_tables(table, he_frac.view(), density.view(), energy.view());
+        let scale_height = state.pressure_scale_height(gravity.view());
+        let pressure = state.compute_linear(StateVar::LogPressure);
+        for i in 0..2 {
+            let expected = pressure[i] / (density[i] * gravity[i]);
+            assert!((scale_height[i] / expected - 1.0).abs() < 1e-12);
+        }
+    }
 }
@@ -0,0 +1,28 @@
+//! Conventions relating physical quantities to the coordinates of the tables.
+//!
+//! All quantities are in cgs units: densities in g/cm^3, specific internal
+//! energies in erg/g, temperatures in K, pressures in dyn/cm^2, specific
+//! entropies in erg/g/K, and opacities in cm^2/g.  The tables store base 10
+//! logarithms of those, the other state variables are logarithmic
+//! derivatives or adiabatic indices, hence dimensionless.
+//!
+//! EOS tables are indexed by `log_energy` and
+//! `log_volume = LOG_VOLUME_OFFSET + log_density - LOG_VOLUME_ENERGY_COEF *
+//! log_energy`.  Opacity tables are indexed by `log_temperature` and
+//! `log_r = log_density + LOG_R_OFFSET - LOG_R_TEMP_COEF * log_temperature`,
+//! the OPAL definition `R = density / T6^3` with `T6` the temperature in
+//! millions of Kelvins.
+
+/// Offset of the `log_volume` axis of EOS tables.
+pub const LOG_VOLUME_OFFSET: f64 = 20.0;
+
+/// Coefficient of the log energy in the `log_volume` axis of EOS tables.
+pub const LOG_VOLUME_ENERGY_COEF: f64 = 0.7;
+
+/// Offset of the `log_r` axis of opacity tables, `3 log10(1e6)` from the
+/// temperature in millions of Kelvins.
+pub const LOG_R_OFFSET: f64 = 18.0;
+
+/// Coefficient of the log temperature in the `log_r` axis of opacity
+/// tables.
+pub const LOG_R_TEMP_COEF: f64 = 3.0;
@@ -0,0 +1,67 @@
+use ndarray::{Array, ArrayView, Dimension};
+
+#[cfg(feature = "rayon")]
+use crate::index::OutOfBoundsError;
+
+/// Map `f` over the elements of `inputs` taken together, stopping at the
+/// first error in logical order of the arrays.
+///
+/// All inputs should have the same shape.
+pub(crate) fn try_zip_map<D, T, E, const N: usize>(
+    inputs: [ArrayView<'_, f64, D>; N],
+    mut f: impl FnMut([f64; N]) -> Result<T, E>,
+) -> Result<Array<T, D>, E>
+where
+    D: Dimension,
+{
+    let dim = inputs[0].raw_dim();
+    for input in &inputs {
+        assert_eq!(input.shape(), dim.slice());
+    }
+    let mut elements = inputs.map(|input| input.into_iter());
+    let values = (0..dim.size())
+        .map(|_| {
+            f(elements
+                .each_mut()
+                .map(|it| *it.next().expect("inputs have the same shape")))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Array::from_shape_vec(dim, values).expect("values are in logical order of the inputs"))
+}
+
+/// Values of `results`, or the first error in logical order of the array.
+#[cfg(feature = "rayon")]
+pub(crate) fn try_collect<D: Dimension>(
+    results: Array<Result<f64, OutOfBoundsError>, D>,
+) -> Result<Array<f64, D>, OutOfBoundsError> {
+    let values = results.iter().cloned().collect::<Result<_, _>>()?;
+    Ok(Array::from_shape_vec(results.raw_dim(), values)
+        .expect("values are in logical order of the results"))
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{arr2, Array2};
+
+    use super::try_zip_map;
+
+    #[test]
+    fn zip_map_in_logical_order() {
+        let a = arr2(&[[1.0, 2.0], [3.0, 4.0]]);
+        let b = Array2::from_shape_fn((2, 2), |(i, j)| (i + 10 * j) as f64);
+        let sums: Result<_, ()> = try_zip_map([a.view(), b.t()], |[a, b]| Ok(a + b));
+        assert_eq!(sums.unwrap(), arr2(&[[1.0, 3.0], [13.0, 15.0]]));
+
+        let mut n_calls = 0;
+        let first_error = try_zip_map([a.view()], |[a]| {
+            n_calls += 1;
+            if a > 1.5 {
+                Err(a)
+            } else {
+                Ok(a)
+            }
+        });
+        assert_eq!(first_error, Err(2.0));
+        assert_eq!(n_calls, 2);
+    }
+}